@@ -0,0 +1,9 @@
+-- Create Sessions Table
+-- The session id is stored in a signed cookie on the client
+CREATE TABLE sessions(
+  session_id uuid NOT NULL,
+  PRIMARY KEY (session_id),
+  user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+  created_at timestamptz NOT NULL,
+  expires_at timestamptz NOT NULL
+);
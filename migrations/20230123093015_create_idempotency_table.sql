@@ -0,0 +1,19 @@
+-- Create Idempotency Table
+-- Stores the response of requests carrying an idempotency key,
+-- so retries can be answered without performing the side effects again.
+-- The response columns are nullable: the row is inserted as soon as
+-- processing starts, which makes concurrent duplicates wait on it
+CREATE TYPE header_pair AS (
+  name TEXT,
+  value BYTEA
+);
+
+CREATE TABLE idempotency(
+  user_id uuid NOT NULL REFERENCES users (user_id),
+  idempotency_key TEXT NOT NULL,
+  response_status_code SMALLINT NULL,
+  response_headers header_pair[] NULL,
+  response_body BYTEA NULL,
+  created_at timestamptz NOT NULL,
+  PRIMARY KEY (user_id, idempotency_key)
+);
@@ -0,0 +1,10 @@
+-- Create Newsletter Issues Table
+-- Issues are persisted so delivery can happen in the background
+CREATE TABLE newsletter_issues(
+  newsletter_issue_id uuid NOT NULL,
+  PRIMARY KEY (newsletter_issue_id),
+  title TEXT NOT NULL,
+  text_content TEXT NOT NULL,
+  html_content TEXT NOT NULL,
+  published_at timestamptz NOT NULL
+);
@@ -0,0 +1,17 @@
+-- Track delivery attempts so failed emails can be retried later
+ALTER TABLE
+  issue_delivery_queue
+ADD
+  COLUMN n_attempts SMALLINT NOT NULL DEFAULT 0,
+ADD
+  COLUMN next_attempt_at timestamptz NOT NULL DEFAULT now();
+
+-- Deliveries that failed for good end up here, along with the last error
+CREATE TABLE issue_delivery_dead_letters(
+  newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+  subscriber_email TEXT NOT NULL,
+  n_attempts SMALLINT NOT NULL,
+  last_error TEXT NOT NULL,
+  failed_at timestamptz NOT NULL,
+  PRIMARY KEY (newsletter_issue_id, subscriber_email)
+);
@@ -0,0 +1,6 @@
+-- Subscribers can now leave the list
+-- `status` becomes 'unsubscribed' and this records when it happened
+ALTER TABLE
+  subscriptions
+ADD
+  COLUMN unsubscribed_at timestamptz NULL;
@@ -0,0 +1,108 @@
+-- A deployment can run several mailing lists
+-- Everything that existed before belongs to the default `newsletter` list
+CREATE TABLE lists(
+  list_id uuid NOT NULL,
+  PRIMARY KEY (list_id),
+  slug TEXT NOT NULL UNIQUE,
+  name TEXT NOT NULL,
+  created_at timestamptz NOT NULL
+);
+
+-- A fixed id, the same in every environment
+-- (and `gen_random_uuid()` is only built in from Postgres 13)
+INSERT INTO
+  lists (list_id, slug, name, created_at)
+VALUES
+  (
+    '833f8890-92e4-4c16-b621-053b5d450159',
+    'newsletter',
+    'Newsletter',
+    now()
+  );
+
+-- Status and confirmation are now tracked per list,
+-- `subscriptions` only holds who the subscriber is
+CREATE TABLE list_memberships(
+  list_id uuid NOT NULL REFERENCES lists (list_id),
+  subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+  PRIMARY KEY (list_id, subscriber_id),
+  status TEXT NOT NULL,
+  subscribed_at timestamptz NOT NULL,
+  unsubscribed_at timestamptz NULL
+);
+
+CREATE INDEX list_memberships_subscriber_id_idx ON list_memberships (subscriber_id);
+
+INSERT INTO
+  list_memberships (
+    list_id,
+    subscriber_id,
+    status,
+    subscribed_at,
+    unsubscribed_at
+  )
+SELECT
+  l.list_id,
+  s.id,
+  s.status,
+  s.subscribed_at,
+  s.unsubscribed_at
+FROM
+  subscriptions s,
+  lists l
+WHERE
+  l.slug = 'newsletter';
+
+ALTER TABLE
+  subscriptions DROP COLUMN status,
+  DROP COLUMN unsubscribed_at;
+
+-- A confirmation link confirms the subscription to a single list
+ALTER TABLE
+  subscription_tokens
+ADD
+  COLUMN list_id uuid NULL REFERENCES lists (list_id);
+
+UPDATE
+  subscription_tokens
+SET
+  list_id = (
+    SELECT
+      list_id
+    FROM
+      lists
+    WHERE
+      slug = 'newsletter'
+  );
+
+ALTER TABLE
+  subscription_tokens
+ALTER COLUMN
+  list_id
+SET
+  NOT NULL;
+
+-- Issues are sent to the confirmed members of a single list
+ALTER TABLE
+  newsletter_issues
+ADD
+  COLUMN list_id uuid NULL REFERENCES lists (list_id);
+
+UPDATE
+  newsletter_issues
+SET
+  list_id = (
+    SELECT
+      list_id
+    FROM
+      lists
+    WHERE
+      slug = 'newsletter'
+  );
+
+ALTER TABLE
+  newsletter_issues
+ALTER COLUMN
+  list_id
+SET
+  NOT NULL;
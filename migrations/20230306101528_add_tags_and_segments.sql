@@ -0,0 +1,54 @@
+-- Tags are set at signup or by admins, segments filter on them
+ALTER TABLE
+  subscriptions
+ADD
+  COLUMN tags TEXT [] NOT NULL DEFAULT '{}';
+
+CREATE INDEX subscriptions_tags_idx ON subscriptions USING GIN (tags);
+
+-- Last time we saw the subscriber act on their subscription,
+-- e.g. signing up or following a confirmation link
+ALTER TABLE
+  subscriptions
+ADD
+  COLUMN last_engaged_at timestamptz NULL;
+
+UPDATE
+  subscriptions
+SET
+  last_engaged_at = subscribed_at;
+
+ALTER TABLE
+  subscriptions
+ALTER COLUMN
+  last_engaged_at
+SET
+  NOT NULL,
+ALTER COLUMN
+  last_engaged_at
+SET
+  DEFAULT now();
+
+-- A saved audience, every condition that is set must hold
+CREATE TABLE segments(
+  segment_id uuid NOT NULL,
+  PRIMARY KEY (segment_id),
+  slug TEXT NOT NULL UNIQUE,
+  name TEXT NOT NULL,
+  -- Subscribers must carry every one of these
+  tags TEXT [] NOT NULL,
+  -- and none of these
+  excluded_tags TEXT [] NOT NULL,
+  -- Signed up to the list at or after
+  subscribed_after timestamptz NULL,
+  -- Signed up to the list strictly before
+  subscribed_before timestamptz NULL,
+  engaged_within_days INTEGER NULL,
+  created_at timestamptz NOT NULL
+);
+
+-- Issues may go to a segment of their list only
+ALTER TABLE
+  newsletter_issues
+ADD
+  COLUMN segment_id uuid NULL REFERENCES segments (segment_id);
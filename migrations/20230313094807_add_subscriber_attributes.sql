@@ -0,0 +1,25 @@
+-- The custom attributes subscribers can have, defined by admins
+CREATE TABLE subscriber_attributes(
+  key TEXT NOT NULL,
+  PRIMARY KEY (key),
+  label TEXT NOT NULL,
+  kind TEXT NOT NULL CHECK (kind IN ('text', 'number', 'choice')),
+  -- The values a `choice` attribute can take
+  options TEXT [] NOT NULL DEFAULT '{}',
+  required BOOLEAN NOT NULL DEFAULT false,
+  created_at timestamptz NOT NULL
+);
+
+-- Values of the attributes above, keyed by attribute
+ALTER TABLE
+  subscriptions
+ADD
+  COLUMN attributes JSONB NOT NULL DEFAULT '{}';
+
+CREATE INDEX subscriptions_attributes_idx ON subscriptions USING GIN (attributes);
+
+-- Subscribers must have these exact attribute values to be in the segment
+ALTER TABLE
+  segments
+ADD
+  COLUMN attributes JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,5 @@
+-- Tags picked at signup, only applied once the subscription is confirmed
+ALTER TABLE
+  subscription_tokens
+ADD
+  COLUMN tags TEXT [] NOT NULL DEFAULT '{}';
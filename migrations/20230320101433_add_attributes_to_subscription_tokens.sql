@@ -0,0 +1,5 @@
+-- Attributes sent at signup, only merged once the subscription is confirmed
+ALTER TABLE
+  subscription_tokens
+ADD
+  COLUMN attributes JSONB NOT NULL DEFAULT '{}';
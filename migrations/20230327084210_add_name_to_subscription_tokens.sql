@@ -0,0 +1,5 @@
+-- The name sent at signup, only applied once the subscription is confirmed
+ALTER TABLE
+  subscription_tokens
+ADD
+  COLUMN name TEXT NULL;
@@ -0,0 +1,62 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{self, request::Parts},
+    response::{IntoResponse, Redirect, Response},
+};
+use axum_extra::extract::cookie::SignedCookieJar;
+use uuid::Uuid;
+
+use crate::{
+    session_state::{get_user_id, session_id_from_jar},
+    startup::AppState,
+    util::flash_cookie,
+};
+
+/// The id of the user behind the current session
+///
+/// Use it as an extractor on any route that requires a logged in user,
+/// anonymous visitors get redirected to the login form with a flash message
+#[derive(Copy, Clone, Debug)]
+pub struct UserId(Uuid);
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::ops::Deref for UserId {
+    type Target = Uuid;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for UserId {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let jar = SignedCookieJar::from_headers(&parts.headers, state.key.clone());
+
+        let user_id = match session_id_from_jar(&jar) {
+            Some(session_id) => get_user_id(&state.pool, session_id)
+                .await
+                .map_err(|_| http::StatusCode::INTERNAL_SERVER_ERROR.into_response())?,
+            None => None,
+        };
+
+        user_id.map(UserId).ok_or_else(|| {
+            (
+                jar.add(flash_cookie("You must be logged in to access this page.")),
+                Redirect::to("/login"),
+            )
+                .into_response()
+        })
+    }
+}
@@ -0,0 +1,5 @@
+mod extractor;
+mod password;
+
+pub use extractor::UserId;
+pub use password::{change_password, validate_credentials, AuthError, Credentials};
@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use secrecy::{ExposeSecret, Secret};
 use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::{
@@ -5,24 +7,233 @@ use sqlx::{
     ConnectOptions,
 };
 
-use crate::domain::SubscriberEmail;
+use crate::{
+    domain::SubscriberEmail,
+    email_client::{
+        CircuitBreaker, CircuitBreakerSender, EmailSender, MailgunClient, OutboxClient,
+        PostmarkClient, RateLimitedSender, RateLimiter, SendGridClient, SesClient,
+        SmtpAuthMechanism, SmtpClient, SmtpConnection, SmtpTls,
+    },
+    metrics::{MeteredSender, Metrics},
+};
 
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
     pub application: ApplicationSettings,
     pub database: DatabaseSettings,
     pub email_client: EmailClientSettings,
+    pub telemetry: TelemetrySettings,
+}
+
+/// Where traces go, on top of the Bunyan logs on stdout
+#[derive(serde::Deserialize, Clone)]
+pub struct TelemetrySettings {
+    /// OTLP (gRPC) collector to export traces to, e.g. `http://localhost:4317`
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+    /// Share of the traces started here that are exported, from 0 to 1
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub sampling_ratio: f64,
 }
 
 #[derive(serde::Deserialize, Clone)]
 pub struct EmailClientSettings {
+    pub provider: EmailProvider,
     pub base_url: String,
     pub sender_email: String,
+    /// API key of the provider, the secret access key for SES
     pub authorization_token: Secret<String>,
     pub timeout_milliseconds: u64,
+    /// Only used by the `smtp` provider
+    pub smtp: SmtpSettings,
+    pub rate_limit: EmailRateLimitSettings,
+    pub circuit_breaker: CircuitBreakerSettings,
+    /// Make `/health/ready` check that the provider can be reached
+    #[serde(default)]
+    pub ping_on_readiness: bool,
+}
+
+/// When to stop calling a failing email provider, and for how long
+#[derive(serde::Deserialize, Clone)]
+pub struct CircuitBreakerSettings {
+    /// Consecutive failures opening the circuit
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub failure_threshold: u32,
+    /// How long the circuit stays open before probing the provider again
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub cool_down_milliseconds: u64,
+}
+
+/// Limits on outgoing emails, shared by everything sending from this process
+#[derive(serde::Deserialize, Clone)]
+pub struct EmailRateLimitSettings {
+    /// Between 0.01 and 1,000,000, values outside of that range are clamped
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub messages_per_second: f64,
+    /// How many messages can go out at once after a quiet period
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub burst: u32,
+    /// Maximum number of requests waiting on the provider at the same time
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_in_flight: usize,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub tls: SmtpTls,
+    /// Leave empty to skip authentication
+    pub username: Option<String>,
+    pub password: Secret<String>,
+    pub auth_mechanism: SmtpAuthMechanism,
+    /// Maximum number of connections kept open to the server
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub pool_max_size: u32,
+}
+
+/// The service used to deliver emails, along with its specific settings
+#[derive(serde::Deserialize, Clone)]
+#[serde(tag = "name", rename_all = "lowercase")]
+pub enum EmailProvider {
+    Postmark,
+    SendGrid,
+    Mailgun {
+        domain: String,
+    },
+    Ses {
+        region: String,
+        access_key_id: String,
+    },
+    Smtp,
+    /// Write emails to `directory` instead of sending them, for local development
+    Outbox {
+        directory: String,
+        /// Browse the outbox at `/dev/outbox`
+        #[serde(default)]
+        serve_listing: bool,
+    },
+}
+
+impl EmailProvider {
+    /// Used to label the metrics of the provider
+    pub fn name(&self) -> &'static str {
+        match self {
+            EmailProvider::Postmark => "postmark",
+            EmailProvider::SendGrid => "sendgrid",
+            EmailProvider::Mailgun { .. } => "mailgun",
+            EmailProvider::Ses { .. } => "ses",
+            EmailProvider::Smtp => "smtp",
+            EmailProvider::Outbox { .. } => "outbox",
+        }
+    }
 }
 
 impl EmailClientSettings {
+    /// Where to find the emails to list at `/dev/outbox`, if the listing is enabled
+    pub fn outbox_listing_directory(&self) -> Option<std::path::PathBuf> {
+        match &self.provider {
+            EmailProvider::Outbox {
+                directory,
+                serve_listing: true,
+            } => Some(directory.into()),
+            _ => None,
+        }
+    }
+
+    pub fn circuit_breaker(&self) -> Arc<CircuitBreaker> {
+        Arc::new(CircuitBreaker::new(
+            self.circuit_breaker.failure_threshold,
+            std::time::Duration::from_millis(self.circuit_breaker.cool_down_milliseconds),
+        ))
+    }
+
+    /// The configured provider, behind `circuit_breaker` and the configured rate limit
+    ///
+    /// Build it once and share it, every client has a limiter of its own
+    pub fn client(
+        self,
+        circuit_breaker: Arc<CircuitBreaker>,
+        metrics: Arc<Metrics>,
+    ) -> Arc<dyn EmailSender> {
+        let limiter = Arc::new(RateLimiter::new(
+            self.rate_limit.messages_per_second,
+            self.rate_limit.burst,
+            self.rate_limit.max_in_flight,
+        ));
+        let provider = self.provider.name();
+        let metered = Arc::new(MeteredSender::new(
+            self.provider_client(),
+            provider,
+            metrics,
+        ));
+        let rate_limited = Arc::new(RateLimitedSender::new(metered, limiter));
+        // An open circuit fails right away, without waiting for the rate limit
+        Arc::new(CircuitBreakerSender::new(rate_limited, circuit_breaker))
+    }
+
+    fn provider_client(self) -> Arc<dyn EmailSender> {
+        let sender_email = self.sender().expect("Invalid sender email address.");
+        let timeout = self.timeout();
+        let base_url =
+            reqwest::Url::parse(self.base_url.as_str()).expect("Unable to parse config base_url");
+
+        match self.provider {
+            EmailProvider::Postmark => Arc::new(PostmarkClient::new(
+                base_url,
+                sender_email,
+                self.authorization_token,
+                timeout,
+            )),
+            EmailProvider::SendGrid => Arc::new(SendGridClient::new(
+                base_url,
+                sender_email,
+                self.authorization_token,
+                timeout,
+            )),
+            EmailProvider::Mailgun { domain } => Arc::new(MailgunClient::new(
+                base_url,
+                domain,
+                sender_email,
+                self.authorization_token,
+                timeout,
+            )),
+            EmailProvider::Ses {
+                region,
+                access_key_id,
+            } => Arc::new(SesClient::new(
+                base_url,
+                region,
+                sender_email,
+                access_key_id,
+                self.authorization_token,
+                timeout,
+            )),
+            EmailProvider::Smtp => {
+                let smtp = self.smtp;
+                let connection = SmtpConnection {
+                    host: smtp.host,
+                    port: smtp.port,
+                    tls: smtp.tls,
+                    credentials: smtp
+                        .username
+                        .filter(|username| !username.is_empty())
+                        .map(|username| (username, smtp.password)),
+                    auth_mechanism: smtp.auth_mechanism,
+                    pool_max_size: smtp.pool_max_size,
+                };
+                Arc::new(
+                    SmtpClient::new(connection, sender_email, timeout)
+                        .expect("Invalid SMTP settings."),
+                )
+            }
+            EmailProvider::Outbox { directory, .. } => {
+                Arc::new(OutboxClient::new(directory.into(), sender_email))
+            }
+        }
+    }
+
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
@@ -0,0 +1,60 @@
+/// The name of a custom subscriber attribute, as used in requests and merge fields
+///
+/// Lowercase letters, digits and underscores, starting with a letter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeKey(String);
+
+impl AttributeKey {
+    pub fn parse(s: String) -> Result<AttributeKey, String> {
+        let is_valid_char = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_';
+        if s.len() <= 64
+            && s.starts_with(|c: char| c.is_ascii_lowercase())
+            && s.chars().all(is_valid_char)
+        {
+            Ok(Self(s))
+        } else {
+            Err(format!("{s} is not a valid attribute key."))
+        }
+    }
+}
+
+impl AsRef<str> for AttributeKey {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for AttributeKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+
+    use super::AttributeKey;
+
+    #[test]
+    fn snake_case_keys_are_valid() {
+        assert_ok!(AttributeKey::parse("preferred_language".into()));
+        assert_ok!(AttributeKey::parse("address2".into()));
+    }
+
+    #[test]
+    fn keys_with_unexpected_characters_are_rejected() {
+        for key in [
+            "",
+            "Company",
+            "with space",
+            "dashed-key",
+            "2fa",
+            "_private",
+            "é",
+        ] {
+            assert_err!(AttributeKey::parse(key.into()));
+        }
+        assert_err!(AttributeKey::parse("a".repeat(65)));
+    }
+}
@@ -0,0 +1,81 @@
+/// The short name of a mailing list, as used in URLs and API requests
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListSlug(String);
+
+impl ListSlug {
+    /// The list every deployment starts with, used when none is given
+    pub const DEFAULT: &'static str = "newsletter";
+
+    pub fn parse(s: String) -> Result<ListSlug, String> {
+        if is_valid_slug(&s) {
+            Ok(Self(s))
+        } else {
+            Err(format!("{s} is not a valid list slug."))
+        }
+    }
+}
+
+/// Lowercase letters, digits and dashes, without leading or trailing dash
+pub(super) fn is_valid_slug(s: &str) -> bool {
+    let is_valid_char = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-';
+    !s.is_empty()
+        && s.len() <= 64
+        && s.chars().all(is_valid_char)
+        && !s.starts_with('-')
+        && !s.ends_with('-')
+}
+
+impl Default for ListSlug {
+    fn default() -> Self {
+        Self(Self::DEFAULT.into())
+    }
+}
+
+impl AsRef<str> for ListSlug {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for ListSlug {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+
+    use super::ListSlug;
+
+    #[test]
+    fn the_default_slug_is_valid() {
+        assert_ok!(ListSlug::parse(ListSlug::DEFAULT.into()));
+    }
+
+    #[test]
+    fn lowercase_words_separated_by_dashes_are_valid() {
+        assert_ok!(ListSlug::parse("rust-weekly-2023".into()));
+    }
+
+    #[test]
+    fn empty_and_too_long_slugs_are_rejected() {
+        assert_err!(ListSlug::parse("".into()));
+        assert_err!(ListSlug::parse("a".repeat(65)));
+    }
+
+    #[test]
+    fn slugs_with_unexpected_characters_are_rejected() {
+        for slug in [
+            "Weekly",
+            "with space",
+            "under_score",
+            "-leading",
+            "trailing-",
+            "é",
+        ] {
+            assert_err!(ListSlug::parse(slug.into()));
+        }
+    }
+}
@@ -1,7 +1,15 @@
+mod attribute_key;
+mod list_slug;
 mod new_subscriber;
+mod segment_slug;
 mod subscriber_email;
 mod subscriber_name;
+mod subscriber_tag;
 
+pub use attribute_key::AttributeKey;
+pub use list_slug::ListSlug;
 pub use new_subscriber::NewSubscriber;
+pub use segment_slug::SegmentSlug;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use subscriber_tag::{SubscriberTag, MAX_SUBSCRIBER_TAGS};
@@ -1,6 +1,15 @@
-use crate::domain::{subscriber_email::SubscriberEmail, subscriber_name::SubscriberName};
+use crate::{
+    domain::{
+        list_slug::ListSlug, subscriber_email::SubscriberEmail, subscriber_name::SubscriberName,
+        subscriber_tag::SubscriberTag,
+    },
+    subscriber_attributes::SubscriberAttributes,
+};
 
 pub struct NewSubscriber {
     pub email: SubscriberEmail,
     pub name: SubscriberName,
+    pub list: ListSlug,
+    pub tags: Vec<SubscriberTag>,
+    pub attributes: SubscriberAttributes,
 }
@@ -0,0 +1,27 @@
+use super::list_slug::is_valid_slug;
+
+/// The short name of a saved segment, follows the same rules as list slugs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentSlug(String);
+
+impl SegmentSlug {
+    pub fn parse(s: String) -> Result<SegmentSlug, String> {
+        if is_valid_slug(&s) {
+            Ok(Self(s))
+        } else {
+            Err(format!("{s} is not a valid segment slug."))
+        }
+    }
+}
+
+impl AsRef<str> for SegmentSlug {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for SegmentSlug {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
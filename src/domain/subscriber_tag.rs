@@ -0,0 +1,117 @@
+/// Most tags a subscriber can have, counting those picked on every list
+pub const MAX_SUBSCRIBER_TAGS: usize = 20;
+
+/// A label put on subscribers, e.g. where they signed up or what they like
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SubscriberTag(String);
+
+impl SubscriberTag {
+    /// Tags are case-insensitive, they are stored lowercase
+    pub fn parse(s: &str) -> Result<SubscriberTag, String> {
+        let tag = s.trim().to_lowercase();
+        let is_valid_char =
+            |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '-' | '_');
+        if !tag.is_empty() && tag.len() <= 64 && tag.chars().all(is_valid_char) {
+            Ok(Self(tag))
+        } else {
+            Err(format!("{s} is not a valid tag."))
+        }
+    }
+
+    /// A comma separated list of tags, as typed in forms
+    ///
+    /// Blank entries are skipped and duplicates removed
+    pub fn parse_list(s: &str) -> Result<Vec<SubscriberTag>, String> {
+        Self::parse_all(s.split(',').filter(|tag| !tag.trim().is_empty()))
+    }
+
+    pub fn parse_all<'a>(
+        tags: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Vec<SubscriberTag>, String> {
+        let mut tags = tags
+            .into_iter()
+            .map(Self::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        tags.sort();
+        tags.dedup();
+        Ok(tags)
+    }
+
+    /// Plain strings, as stored in the database
+    pub fn to_strings(tags: &[SubscriberTag]) -> Vec<String> {
+        tags.iter().map(|tag| tag.0.clone()).collect()
+    }
+
+    /// Add stored `tags` to the `current` ones, sorted
+    ///
+    /// Tags already there are always kept, new ones are dropped
+    /// once the subscriber has `MAX_SUBSCRIBER_TAGS`
+    pub fn merge(mut current: Vec<String>, tags: &[String]) -> Vec<String> {
+        for tag in tags {
+            if current.len() >= MAX_SUBSCRIBER_TAGS {
+                break;
+            }
+            if !current.contains(tag) {
+                current.push(tag.clone());
+            }
+        }
+        current.sort();
+        current
+    }
+}
+
+impl AsRef<str> for SubscriberTag {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok_eq};
+
+    use super::{SubscriberTag, MAX_SUBSCRIBER_TAGS};
+
+    #[test]
+    fn tags_are_lowercased_and_trimmed() {
+        assert_ok_eq!(
+            SubscriberTag::parse(" Rust-Conf_2023 ").map(|tag| tag.as_ref().to_owned()),
+            "rust-conf_2023"
+        );
+    }
+
+    #[test]
+    fn tags_with_unexpected_characters_are_rejected() {
+        for tag in ["", " ", "with space", "semi;colon", "<b>", &"a".repeat(65)] {
+            assert_err!(SubscriberTag::parse(tag));
+        }
+    }
+
+    #[test]
+    fn a_comma_separated_list_is_deduplicated() {
+        let tags = SubscriberTag::parse_list("rust, beta,,Rust ,").unwrap();
+        assert_eq!(SubscriberTag::to_strings(&tags), vec!["beta", "rust"]);
+    }
+
+    #[test]
+    fn one_invalid_tag_fails_the_whole_list() {
+        assert_err!(SubscriberTag::parse_list("rust, not valid"));
+    }
+
+    #[test]
+    fn merged_tags_are_deduplicated_and_sorted() {
+        let merged = SubscriberTag::merge(vec!["rust".into()], &["beta".into(), "rust".into()]);
+        assert_eq!(merged, vec!["beta", "rust"]);
+    }
+
+    #[test]
+    fn merging_stops_at_the_maximum_number_of_tags() {
+        let current: Vec<String> = (0..MAX_SUBSCRIBER_TAGS - 1)
+            .map(|i| format!("tag-{i:02}"))
+            .collect();
+        let merged = SubscriberTag::merge(current.clone(), &["b".into(), "a".into()]);
+        assert_eq!(merged.len(), MAX_SUBSCRIBER_TAGS);
+        assert!(merged.contains(&"b".to_string()));
+        assert!(current.iter().all(|tag| merged.contains(tag)));
+    }
+}
@@ -0,0 +1,370 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+use super::{EmailHeader, EmailSender, OutgoingEmail, SendEmailError};
+use crate::domain::SubscriberEmail;
+
+/// Stops calling the email provider after too many failures in a row
+///
+/// While open, sends fail right away with `SendEmailError::CircuitOpen`
+/// instead of waiting for the provider to time out.
+/// After `cool_down`, a single probe is let through (half-open):
+/// the circuit closes if it succeeds and opens again otherwise
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cool_down: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Closed { consecutive_failures: u32 },
+    Open { until: Instant },
+    HalfOpen { probe_in_flight: bool },
+}
+
+/// What the circuit breaker looks like from the outside, e.g. for health checks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl std::fmt::Display for CircuitState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "half_open",
+        };
+        f.write_str(state)
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cool_down: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            cool_down,
+            state: Mutex::new(State::Closed {
+                consecutive_failures: 0,
+            }),
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        match *self.state.lock().unwrap() {
+            State::Closed { .. } => CircuitState::Closed,
+            // The cool-down is over, the next send will be the probe
+            State::Open { until } if Instant::now() >= until => CircuitState::HalfOpen,
+            State::Open { .. } => CircuitState::Open,
+            State::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Whether a call to the provider may go ahead
+    fn try_acquire(&self, now: Instant) -> Result<Permit<'_>, SendEmailError> {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            State::Closed { .. } => Ok(Permit::new(self, false)),
+            State::Open { until } if now >= until => {
+                tracing::info!(
+                    circuit_breaker.state = %CircuitState::HalfOpen,
+                    "Email provider cool-down is over, probing it."
+                );
+                *state = State::HalfOpen {
+                    probe_in_flight: true,
+                };
+                Ok(Permit::new(self, true))
+            }
+            State::HalfOpen {
+                probe_in_flight: false,
+            } => {
+                *state = State::HalfOpen {
+                    probe_in_flight: true,
+                };
+                Ok(Permit::new(self, true))
+            }
+            State::Open { .. } | State::HalfOpen { .. } => Err(SendEmailError::CircuitOpen),
+        }
+    }
+
+    fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let State::HalfOpen { .. } = *state {
+            tracing::info!(
+                circuit_breaker.state = %CircuitState::Closed,
+                "The email provider is back, closing the circuit breaker."
+            );
+        }
+        *state = State::Closed {
+            consecutive_failures: 0,
+        };
+    }
+
+    fn record_failure(&self, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        let trip = match *state {
+            State::Closed {
+                consecutive_failures,
+            } => {
+                let consecutive_failures = consecutive_failures + 1;
+                *state = State::Closed {
+                    consecutive_failures,
+                };
+                consecutive_failures >= self.failure_threshold
+            }
+            // The probe failed, the provider is still down
+            State::HalfOpen { .. } => true,
+            State::Open { .. } => false,
+        };
+        if trip {
+            tracing::warn!(
+                circuit_breaker.state = %CircuitState::Open,
+                cool_down_seconds = self.cool_down.as_secs_f64(),
+                "The email provider keeps failing, opening the circuit breaker."
+            );
+            *state = State::Open {
+                until: now + self.cool_down,
+            };
+        }
+    }
+
+    /// The provider answered the probe with something that says nothing
+    /// about its health, e.g. a 429: let the next send probe again
+    fn release_probe(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let State::HalfOpen { .. } = *state {
+            *state = State::HalfOpen {
+                probe_in_flight: false,
+            };
+        }
+    }
+
+    fn record<T>(&self, outcome: &Result<T, SendEmailError>) {
+        match outcome {
+            Err(SendEmailError::CircuitOpen) => {}
+            Err(SendEmailError::Throttled { .. }) => self.release_probe(),
+            // Anything the provider answered, even a rejection, means it is up
+            Err(e) if e.is_transient() => self.record_failure(Instant::now()),
+            _ => self.record_success(),
+        }
+    }
+}
+
+/// Lets a call to the provider go ahead, its outcome has to be recorded
+///
+/// If the call is cancelled before that (e.g. the request timed out),
+/// dropping the permit of a probe lets the next send probe instead
+#[derive(Debug)]
+struct Permit<'a> {
+    circuit_breaker: &'a CircuitBreaker,
+    is_probe: bool,
+}
+
+impl<'a> Permit<'a> {
+    fn new(circuit_breaker: &'a CircuitBreaker, is_probe: bool) -> Self {
+        Self {
+            circuit_breaker,
+            is_probe,
+        }
+    }
+
+    fn record<T>(mut self, outcome: &Result<T, SendEmailError>) {
+        self.is_probe = false;
+        self.circuit_breaker.record(outcome);
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if self.is_probe {
+            self.circuit_breaker.release_probe();
+        }
+    }
+}
+
+/// Wraps another sender, failing fast while its circuit breaker is open
+pub struct CircuitBreakerSender {
+    inner: Arc<dyn EmailSender>,
+    circuit_breaker: Arc<CircuitBreaker>,
+}
+
+impl CircuitBreakerSender {
+    pub fn new(inner: Arc<dyn EmailSender>, circuit_breaker: Arc<CircuitBreaker>) -> Self {
+        Self {
+            inner,
+            circuit_breaker,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for CircuitBreakerSender {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<(), SendEmailError> {
+        let permit = self.circuit_breaker.try_acquire(Instant::now())?;
+        let outcome = self
+            .inner
+            .send_email_with_headers(recipient, subject, html_content, text_content, headers)
+            .await;
+        permit.record(&outcome);
+        outcome
+    }
+
+    async fn ping(&self) -> Option<Result<(), SendEmailError>> {
+        self.inner.ping().await
+    }
+
+    fn max_batch_size(&self) -> usize {
+        self.inner.max_batch_size()
+    }
+
+    async fn send_batch(
+        &self,
+        emails: &[OutgoingEmail],
+    ) -> Result<Vec<Result<(), SendEmailError>>, SendEmailError> {
+        let permit = self.circuit_breaker.try_acquire(Instant::now())?;
+        let outcome = self.inner.send_batch(emails).await;
+        permit.record(&outcome);
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+
+    use super::*;
+
+    const COOL_DOWN: Duration = Duration::from_secs(30);
+
+    #[test]
+    fn the_circuit_opens_after_enough_consecutive_failures() {
+        let now = Instant::now();
+        let breaker = CircuitBreaker::new(3, COOL_DOWN);
+
+        for _ in 0..2 {
+            breaker.record_failure(now);
+            assert_ok!(breaker.try_acquire(now));
+        }
+        breaker.record_failure(now);
+
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(matches!(
+            breaker.try_acquire(now),
+            Err(SendEmailError::CircuitOpen)
+        ));
+    }
+
+    #[test]
+    fn a_success_resets_the_failure_count() {
+        let now = Instant::now();
+        let breaker = CircuitBreaker::new(2, COOL_DOWN);
+
+        breaker.record_failure(now);
+        breaker.record_success();
+        breaker.record_failure(now);
+
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn a_single_probe_goes_through_after_the_cool_down() {
+        let now = Instant::now();
+        let breaker = CircuitBreaker::new(1, COOL_DOWN);
+        breaker.record_failure(now);
+
+        let later = now + COOL_DOWN;
+        let _probe = assert_ok!(breaker.try_acquire(later));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        // Everyone else waits for the outcome of the probe
+        assert_err!(breaker.try_acquire(later));
+    }
+
+    #[test]
+    fn a_successful_probe_closes_the_circuit() {
+        let now = Instant::now();
+        let breaker = CircuitBreaker::new(1, COOL_DOWN);
+        breaker.record_failure(now);
+        let probe = assert_ok!(breaker.try_acquire(now + COOL_DOWN));
+
+        probe.record::<()>(&Ok(()));
+
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert_ok!(breaker.try_acquire(now + COOL_DOWN));
+    }
+
+    #[test]
+    fn a_failed_probe_opens_the_circuit_again() {
+        let now = Instant::now();
+        let breaker = CircuitBreaker::new(1, COOL_DOWN);
+        breaker.record_failure(now);
+        let later = now + COOL_DOWN;
+        let _probe = assert_ok!(breaker.try_acquire(later));
+
+        breaker.record_failure(later);
+
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert_err!(breaker.try_acquire(later + COOL_DOWN / 2));
+    }
+
+    /// Never answers, like a provider that hangs until the caller gives up
+    struct HangingSender;
+
+    #[async_trait::async_trait]
+    impl EmailSender for HangingSender {
+        async fn send_email_with_headers(
+            &self,
+            _recipient: &SubscriberEmail,
+            _subject: &str,
+            _html_content: &str,
+            _text_content: &str,
+            _headers: &[EmailHeader],
+        ) -> Result<(), SendEmailError> {
+            std::future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn a_cancelled_probe_lets_the_next_send_through() {
+        // Arrange
+        let breaker = Arc::new(CircuitBreaker::new(1, Duration::ZERO));
+        breaker.record_failure(Instant::now());
+        let sender = CircuitBreakerSender::new(Arc::new(HangingSender), breaker.clone());
+        let recipient = SubscriberEmail::parse("ursula@example.com".into()).unwrap();
+
+        // Act - the probe is dropped before the provider answers
+        let probe = sender.send_email(&recipient, "Subject", "<p>Body</p>", "Body");
+        assert_err!(tokio::time::timeout(Duration::from_millis(10), probe).await);
+
+        // Assert
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert_ok!(breaker.try_acquire(Instant::now()));
+    }
+
+    #[test]
+    fn rejected_messages_do_not_count_as_provider_failures() {
+        let breaker = CircuitBreaker::new(1, COOL_DOWN);
+
+        breaker.record::<()>(&Err(SendEmailError::Rejected {
+            code: 406,
+            message: "Inactive recipient".into(),
+        }));
+
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+}
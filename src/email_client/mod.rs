@@ -0,0 +1,313 @@
+//! Sending emails through one of several delivery providers
+//!
+//! The rest of the application only deals with `dyn EmailSender`,
+//! which provider backs it is picked from `EmailClientSettings`
+mod circuit_breaker;
+mod mailgun;
+mod outbox;
+mod postmark;
+mod rate_limit;
+mod sendgrid;
+mod ses;
+mod smtp;
+
+pub use circuit_breaker::{CircuitBreaker, CircuitBreakerSender, CircuitState};
+pub use mailgun::MailgunClient;
+pub use outbox::{list_outbox_messages, read_outbox_message, OutboxClient, OutboxMessage};
+pub use postmark::PostmarkClient;
+pub use postmark::POSTMARK_MAX_BATCH_SIZE;
+pub use rate_limit::{RateLimitedSender, RateLimiter};
+pub use sendgrid::SendGridClient;
+pub use ses::SesClient;
+pub use smtp::{SmtpAuthMechanism, SmtpClient, SmtpConnection, SmtpTls};
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    domain::SubscriberEmail,
+    request_id::{RequestId, REQUEST_ID_HEADER},
+    telemetry::trace_context_headers,
+};
+
+/// Longest wait a provider can ask for with `Retry-After`
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60 * 60);
+
+#[async_trait::async_trait]
+pub trait EmailSender: Send + Sync {
+    /// Send a single message, with extra headers added to the message itself
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<(), SendEmailError>;
+
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), SendEmailError> {
+        self.send_email_with_headers(recipient, subject, html_content, text_content, &[])
+            .await
+    }
+
+    /// Check that the provider can be reached, without sending anything
+    ///
+    /// `None` when the provider has no cheap way to tell
+    async fn ping(&self) -> Option<Result<(), SendEmailError>> {
+        None
+    }
+
+    /// How many messages `send_batch` accepts at once
+    fn max_batch_size(&self) -> usize {
+        1
+    }
+
+    /// Send several messages, at most `max_batch_size`, in as few calls as possible
+    ///
+    /// The outcome of each message is returned in the same order as `emails`,
+    /// the outer error means the batch as a whole failed.
+    /// Providers without a batch API send the messages one after the other
+    async fn send_batch(
+        &self,
+        emails: &[OutgoingEmail],
+    ) -> Result<Vec<Result<(), SendEmailError>>, SendEmailError> {
+        let mut results = Vec::with_capacity(emails.len());
+        for email in emails {
+            results.push(
+                self.send_email_with_headers(
+                    &email.recipient,
+                    &email.subject,
+                    &email.html_content,
+                    &email.text_content,
+                    &email.headers,
+                )
+                .await,
+            );
+        }
+        Ok(results)
+    }
+}
+
+/// A message handed over to `EmailSender::send_batch`
+#[derive(Debug, Clone)]
+pub struct OutgoingEmail {
+    pub recipient: SubscriberEmail,
+    pub subject: String,
+    pub html_content: String,
+    pub text_content: String,
+    pub headers: Vec<EmailHeader>,
+}
+
+/// A custom header of an outgoing message, e.g. `List-Unsubscribe`
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct EmailHeader {
+    pub name: String,
+    pub value: String,
+}
+
+impl EmailHeader {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+        }
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum SendEmailError {
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    Smtp(#[from] lettre::transport::smtp::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// The provider refused this message, while accepting the rest of its batch
+    #[error("The email provider rejected the message (error code {code}): {message}")]
+    Rejected { code: i64, message: String },
+    /// The message could not even be put together, e.g. an invalid address
+    #[error("Invalid message: {0}")]
+    InvalidMessage(String),
+    /// The provider answered 429, asking us to slow down
+    #[error("The email provider is throttling us")]
+    Throttled { retry_after: Option<Duration> },
+    /// The provider failed too many times in a row, we are not calling it for now
+    #[error("The email provider is unavailable, its circuit breaker is open")]
+    CircuitOpen,
+}
+
+impl std::fmt::Debug for SendEmailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        crate::util::error_chain_fmt(self, f)
+    }
+}
+
+impl SendEmailError {
+    /// Whether the failed send is worth retrying later
+    ///
+    /// Timeouts, connection issues, 5xx and throttling (408/429) are transient,
+    /// any other 4xx means the provider rejected the message itself
+    pub fn is_transient(&self) -> bool {
+        match self {
+            SendEmailError::Http(e) => match e.status() {
+                Some(status) => {
+                    status.is_server_error()
+                        || status == reqwest::StatusCode::REQUEST_TIMEOUT
+                        || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                }
+                None => e.is_timeout() || e.is_connect() || e.is_request(),
+            },
+            // 5xx replies are final, anything else (4xx, connection
+            // issues, timeouts) may go through on a later attempt
+            SendEmailError::Smtp(e) => !e.is_permanent(),
+            SendEmailError::Io(_)
+            | SendEmailError::Throttled { .. }
+            | SendEmailError::CircuitOpen => true,
+            SendEmailError::Rejected { .. } | SendEmailError::InvalidMessage(_) => false,
+        }
+    }
+
+    /// How long the provider asked us to wait before sending again
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            SendEmailError::Throttled { retry_after } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+/// Headers added to every call to an HTTP provider, so that it can be
+/// correlated with the request that caused it
+fn outgoing_headers() -> reqwest::header::HeaderMap {
+    let mut headers = trace_context_headers();
+    if let Some(request_id) = RequestId::current() {
+        headers.insert(
+            REQUEST_ID_HEADER,
+            request_id
+                .as_ref()
+                .parse()
+                .expect("Request ids are always valid header values"),
+        );
+    }
+    headers
+}
+
+fn http_client(timeout: Duration) -> reqwest::Client {
+    reqwest::Client::builder().timeout(timeout).build().unwrap()
+}
+
+/// Like `Response::error_for_status`, but 429s keep their `Retry-After`
+fn error_for_status(response: reqwest::Response) -> Result<reqwest::Response, SendEmailError> {
+    if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| parse_retry_after(value, Utc::now()));
+        return Err(SendEmailError::Throttled { retry_after });
+    }
+    Ok(response.error_for_status()?)
+}
+
+/// `Retry-After` is either a number of seconds or an HTTP date
+///
+/// Capped to `MAX_RETRY_AFTER`, the provider doesn't get to stop us for good
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    let retry_after = match value.parse::<u64>() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => {
+            let retry_at = DateTime::parse_from_rfc2822(value).ok()?;
+            // A date in the past means we can go again right away
+            (retry_at.with_timezone(&Utc) - now)
+                .to_std()
+                .unwrap_or_default()
+        }
+    };
+    Some(retry_after.min(MAX_RETRY_AFTER))
+}
+
+/// Fake data shared by the tests of every provider
+#[cfg(test)]
+mod test_data {
+    use fake::{
+        faker::{
+            internet::en::SafeEmail,
+            lorem::en::{Paragraph, Sentence},
+        },
+        Fake,
+    };
+
+    use crate::domain::SubscriberEmail;
+
+    /// Generate a random email subject
+    pub fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    /// Generate a random email content
+    pub fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    /// Generate a random subscriber email
+    pub fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use claims::{assert_none, assert_some_eq};
+
+    use super::*;
+
+    #[test]
+    fn retry_after_accepts_a_number_of_seconds() {
+        assert_some_eq!(
+            parse_retry_after("120", Utc::now()),
+            Duration::from_secs(120)
+        );
+    }
+
+    #[test]
+    fn retry_after_accepts_an_http_date() {
+        let now = Utc.with_ymd_and_hms(2023, 2, 27, 10, 0, 0).unwrap();
+        assert_some_eq!(
+            parse_retry_after("Mon, 27 Feb 2023 10:00:30 GMT", now),
+            Duration::from_secs(30)
+        );
+        // Already past
+        assert_some_eq!(
+            parse_retry_after("Mon, 27 Feb 2023 09:59:00 GMT", now),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn retry_after_is_capped() {
+        let now = Utc.with_ymd_and_hms(2023, 2, 27, 10, 0, 0).unwrap();
+        assert_some_eq!(
+            parse_retry_after("18446744073709551615", now),
+            MAX_RETRY_AFTER
+        );
+        assert_some_eq!(
+            parse_retry_after("Fri, 31 Dec 9999 23:59:59 GMT", now),
+            MAX_RETRY_AFTER
+        );
+    }
+
+    #[test]
+    fn retry_after_ignores_garbage() {
+        assert_none!(parse_retry_after("soon", Utc::now()));
+    }
+}
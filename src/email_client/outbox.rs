@@ -0,0 +1,195 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use super::{
+    smtp::{build_message, extra_headers},
+    EmailHeader, EmailSender, SendEmailError,
+};
+use crate::domain::SubscriberEmail;
+
+/// Development backend writing every message to a directory instead of sending it
+///
+/// Each message ends up as `<id>.eml`, ready to open in a mail client,
+/// next to `<id>.json` holding its metadata and both bodies
+pub struct OutboxClient {
+    directory: PathBuf,
+    sender: SubscriberEmail,
+}
+
+/// Metadata stored next to every `.eml` file
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct OutboxMessage {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub headers: Vec<(String, String)>,
+    pub html_content: String,
+    pub text_content: String,
+}
+
+impl OutboxClient {
+    pub fn new(directory: PathBuf, sender: SubscriberEmail) -> Self {
+        Self { directory, sender }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for OutboxClient {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<(), SendEmailError> {
+        let message = build_message(&self.sender, recipient, subject, html_content, text_content)?;
+        let mut eml = extra_headers(headers)?.to_string().into_bytes();
+        eml.extend(message.formatted());
+
+        let metadata = OutboxMessage {
+            id: Uuid::new_v4(),
+            created_at: Utc::now(),
+            from: self.sender.as_ref().to_owned(),
+            to: recipient.as_ref().to_owned(),
+            subject: subject.to_owned(),
+            headers: headers
+                .iter()
+                .map(|h| (h.name.clone(), h.value.clone()))
+                .collect(),
+            html_content: html_content.to_owned(),
+            text_content: text_content.to_owned(),
+        };
+        let json = serde_json::to_vec_pretty(&metadata)
+            .map_err(|e| SendEmailError::InvalidMessage(e.to_string()))?;
+
+        tokio::fs::create_dir_all(&self.directory).await?;
+        tokio::fs::write(self.directory.join(format!("{}.eml", metadata.id)), eml).await?;
+        // Written last, so listings never see a message without its `.eml`
+        tokio::fs::write(self.directory.join(format!("{}.json", metadata.id)), json).await?;
+
+        tracing::info!(
+            outbox_message_id = %metadata.id,
+            "Wrote email to the outbox instead of sending it."
+        );
+
+        Ok(())
+    }
+
+    /// The outbox is ready as long as its directory can be created
+    async fn ping(&self) -> Option<Result<(), SendEmailError>> {
+        Some(
+            tokio::fs::create_dir_all(&self.directory)
+                .await
+                .map_err(SendEmailError::from),
+        )
+    }
+}
+
+/// Every message of the outbox, most recent first
+pub async fn list_outbox_messages(directory: &Path) -> Result<Vec<OutboxMessage>, anyhow::Error> {
+    let mut messages = Vec::new();
+
+    let mut entries = match tokio::fs::read_dir(directory).await {
+        Ok(entries) => entries,
+        // Nothing was sent yet
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(messages),
+        Err(e) => return Err(e.into()),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) == Some("json") {
+            let json = tokio::fs::read(&path).await?;
+            messages.push(serde_json::from_slice(&json)?);
+        }
+    }
+
+    messages.sort_by_key(|m| std::cmp::Reverse(m.created_at));
+    Ok(messages)
+}
+
+/// A single message of the outbox, if it exists
+pub async fn read_outbox_message(
+    directory: &Path,
+    id: Uuid,
+) -> Result<Option<OutboxMessage>, anyhow::Error> {
+    match tokio::fs::read(directory.join(format!("{}.json", id))).await {
+        Ok(json) => Ok(Some(serde_json::from_slice(&json)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_none, assert_ok};
+    use uuid::Uuid;
+
+    use super::{list_outbox_messages, read_outbox_message, OutboxClient};
+    use crate::{
+        domain::SubscriberEmail,
+        email_client::{test_data::email, EmailHeader, EmailSender},
+    };
+
+    fn outbox_client() -> (OutboxClient, std::path::PathBuf) {
+        let directory = std::env::temp_dir().join(format!("outbox-{}", Uuid::new_v4()));
+        let client = OutboxClient::new(
+            directory.clone(),
+            SubscriberEmail::parse("sender@example.com".into()).unwrap(),
+        );
+        (client, directory)
+    }
+
+    #[tokio::test]
+    async fn send_email_writes_an_eml_file_and_its_metadata() {
+        // Arrange
+        let (email_client, directory) = outbox_client();
+        let recipient = email();
+
+        // Act
+        let headers = [EmailHeader::new(
+            "List-Unsubscribe",
+            "<https://example.com/unsubscribe>",
+        )];
+        let outcome = email_client
+            .send_email_with_headers(
+                &recipient,
+                "Welcome!",
+                "<p>Hello in HTML</p>",
+                "Hello in plain text",
+                &headers,
+            )
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+
+        let messages = list_outbox_messages(&directory).await.unwrap();
+        assert_eq!(messages.len(), 1);
+        let message = &messages[0];
+        assert_eq!(message.to, recipient.as_ref());
+        assert_eq!(message.subject, "Welcome!");
+        assert_eq!(message.html_content, "<p>Hello in HTML</p>");
+
+        let eml = std::fs::read_to_string(directory.join(format!("{}.eml", message.id))).unwrap();
+        assert!(eml.contains("List-Unsubscribe: <https://example.com/unsubscribe>"));
+        assert!(eml.contains("Subject: Welcome!"));
+        assert!(eml.contains("multipart/alternative"));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn an_empty_outbox_has_no_messages() {
+        let (_, directory) = outbox_client();
+
+        assert!(list_outbox_messages(&directory).await.unwrap().is_empty());
+        assert_none!(read_outbox_message(&directory, Uuid::new_v4())
+            .await
+            .unwrap());
+    }
+}
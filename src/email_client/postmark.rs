@@ -0,0 +1,511 @@
+use reqwest::{Client, Url};
+use secrecy::{ExposeSecret, Secret};
+
+use super::{
+    error_for_status, http_client, outgoing_headers, EmailHeader, EmailSender, OutgoingEmail,
+    SendEmailError,
+};
+use crate::domain::SubscriberEmail;
+
+/// Upper bound on the number of messages of a single `/email/batch` call
+pub const POSTMARK_MAX_BATCH_SIZE: usize = 500;
+
+/// Postmark's message APIs, authenticated with a server token
+pub struct PostmarkClient {
+    http_client: Client,
+    base_url: Url,
+    sender: SubscriberEmail,
+    authorization_token: Secret<String>,
+}
+
+impl PostmarkClient {
+    pub fn new(
+        base_url: Url,
+        sender: SubscriberEmail,
+        authorization_token: Secret<String>,
+        timeout: std::time::Duration,
+    ) -> Self {
+        Self {
+            http_client: http_client(timeout),
+            base_url,
+            sender,
+            authorization_token,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for PostmarkClient {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<(), SendEmailError> {
+        let url = self
+            .base_url
+            .join("email")
+            .expect("Unable to join base url");
+        let request_body = SendEmailRequest {
+            from: self.sender.as_ref(),
+            to: recipient.as_ref(),
+            subject,
+            html_body: html_content,
+            text_body: text_content,
+            headers,
+        };
+        error_for_status(
+            self.http_client
+                .post(url)
+                .headers(outgoing_headers())
+                .header(
+                    "X-Postmark-Server-Token",
+                    self.authorization_token.expose_secret(),
+                )
+                .json(&request_body)
+                .send()
+                .await?,
+        )?;
+
+        Ok(())
+    }
+
+    /// Fetch the details of the server the token belongs to
+    async fn ping(&self) -> Option<Result<(), SendEmailError>> {
+        let url = self
+            .base_url
+            .join("server")
+            .expect("Unable to join base url");
+        let outcome = self
+            .http_client
+            .get(url)
+            .headers(outgoing_headers())
+            .header("Accept", "application/json")
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .send()
+            .await;
+        Some(match outcome {
+            Ok(response) => error_for_status(response).map(|_| ()),
+            Err(e) => Err(e.into()),
+        })
+    }
+
+    fn max_batch_size(&self) -> usize {
+        POSTMARK_MAX_BATCH_SIZE
+    }
+
+    async fn send_batch(
+        &self,
+        emails: &[OutgoingEmail],
+    ) -> Result<Vec<Result<(), SendEmailError>>, SendEmailError> {
+        if emails.len() > POSTMARK_MAX_BATCH_SIZE {
+            return Err(SendEmailError::InvalidMessage(format!(
+                "A batch can't hold more than {} messages",
+                POSTMARK_MAX_BATCH_SIZE
+            )));
+        }
+
+        let url = self
+            .base_url
+            .join("email/batch")
+            .expect("Unable to join base url");
+        let request_body: Vec<_> = emails
+            .iter()
+            .map(|email| SendEmailRequest {
+                from: self.sender.as_ref(),
+                to: email.recipient.as_ref(),
+                subject: &email.subject,
+                html_body: &email.html_content,
+                text_body: &email.text_content,
+                headers: &email.headers,
+            })
+            .collect();
+        let results: Vec<BatchResult> = error_for_status(
+            self.http_client
+                .post(url)
+                .headers(outgoing_headers())
+                .header(
+                    "X-Postmark-Server-Token",
+                    self.authorization_token.expose_secret(),
+                )
+                .json(&request_body)
+                .send()
+                .await?,
+        )?
+        .json()
+        .await?;
+
+        // Postmark answers with one result per message, in order
+        if results.len() != emails.len() {
+            return Err(SendEmailError::InvalidMessage(format!(
+                "Postmark returned {} results for a batch of {} messages",
+                results.len(),
+                emails.len()
+            )));
+        }
+        Ok(results
+            .into_iter()
+            .map(|result| match result.error_code {
+                0 => Ok(()),
+                code => Err(SendEmailError::Rejected {
+                    code,
+                    message: result.message,
+                }),
+            })
+            .collect())
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BatchResult {
+    error_code: i64,
+    message: String,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    headers: &'a [EmailHeader],
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+    use fake::{Fake, Faker};
+    use reqwest::Url;
+    use secrecy::Secret;
+    use wiremock::{
+        matchers::{any, body_partial_json, header, header_exists, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::{PostmarkClient, POSTMARK_MAX_BATCH_SIZE};
+    use crate::email_client::{
+        test_data::{content, email, subject},
+        EmailHeader, EmailSender, OutgoingEmail, SendEmailError,
+    };
+
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &wiremock::Request) -> bool {
+            // Parse body as JSON value
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+            if let Ok(body) = result {
+                // Check that all mandatory fields are populated
+                body.get("From").is_some()
+                    && body.get("To").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("HtmlBody").is_some()
+                    && body.get("TextBody").is_some()
+            } else {
+                false
+            }
+        }
+    }
+
+    /// Get a test instance of `PostmarkClient`
+    fn email_client(base_url: Url) -> PostmarkClient {
+        PostmarkClient::new(
+            base_url,
+            email(),
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+        )
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_the_expected_request() {
+        // Arrange mock server
+        let mock_server = MockServer::start().await;
+        let email_client =
+            email_client(Url::parse(mock_server.uri().as_str()).expect("Unable to parse mock url"));
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let _ = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+    }
+
+    #[tokio::test]
+    async fn send_email_with_headers_sends_them_as_a_headers_array() {
+        // Arrange mock server
+        let mock_server = MockServer::start().await;
+        let email_client =
+            email_client(Url::parse(mock_server.uri().as_str()).expect("Unable to parse mock url"));
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .and(body_partial_json(serde_json::json!({
+                "Headers": [
+                    { "Name": "List-Unsubscribe", "Value": "<https://example.com/unsubscribe>" },
+                    { "Name": "List-Unsubscribe-Post", "Value": "List-Unsubscribe=One-Click" },
+                ]
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let headers = [
+            EmailHeader::new("List-Unsubscribe", "<https://example.com/unsubscribe>"),
+            EmailHeader::new("List-Unsubscribe-Post", "List-Unsubscribe=One-Click"),
+        ];
+        let outcome = email_client
+            .send_email_with_headers(&email(), &subject(), &content(), &content(), &headers)
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_succeeds_if_the_server_returns_200() {
+        // Arrange mock server
+        let mock_server = MockServer::start().await;
+        let email_client =
+            email_client(Url::parse(mock_server.uri().as_str()).expect("Unable to parse mock url"));
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        // Arrange mock server
+        let mock_server = MockServer::start().await;
+        let email_client =
+            email_client(Url::parse(mock_server.uri().as_str()).expect("Unable to parse mock url"));
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_the_server_takes_too_long() {
+        // Arrange mock server
+        let mock_server = MockServer::start().await;
+        let email_client =
+            email_client(Url::parse(mock_server.uri().as_str()).expect("Unable to parse mock url"));
+
+        let response = ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180));
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_transient() {
+        // Arrange mock server
+        let mock_server = MockServer::start().await;
+        let email_client =
+            email_client(Url::parse(mock_server.uri().as_str()).expect("Unable to parse mock url"));
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert!(outcome.unwrap_err().is_transient());
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_permanent() {
+        // Arrange mock server
+        let mock_server = MockServer::start().await;
+        let email_client =
+            email_client(Url::parse(mock_server.uri().as_str()).expect("Unable to parse mock url"));
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert!(!outcome.unwrap_err().is_transient());
+    }
+
+    #[tokio::test]
+    async fn throttled_requests_carry_the_retry_after() {
+        // Arrange mock server
+        let mock_server = MockServer::start().await;
+        let email_client =
+            email_client(Url::parse(mock_server.uri().as_str()).expect("Unable to parse mock url"));
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "30"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        let e = outcome.unwrap_err();
+        assert!(e.is_transient());
+        assert_eq!(e.retry_after(), Some(std::time::Duration::from_secs(30)));
+    }
+
+    fn outgoing_email() -> OutgoingEmail {
+        OutgoingEmail {
+            recipient: email(),
+            subject: subject(),
+            html_content: content(),
+            text_content: content(),
+            headers: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn send_batch_reports_the_outcome_of_each_message() {
+        // Arrange mock server
+        let mock_server = MockServer::start().await;
+        let email_client =
+            email_client(Url::parse(mock_server.uri().as_str()).expect("Unable to parse mock url"));
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                { "ErrorCode": 0, "Message": "OK", "To": "a@example.com" },
+                { "ErrorCode": 406, "Message": "Inactive recipient", "To": "b@example.com" },
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let results = email_client
+            .send_batch(&[outgoing_email(), outgoing_email()])
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(results.len(), 2);
+        assert_ok!(&results[0]);
+        match &results[1] {
+            Err(e @ SendEmailError::Rejected { code: 406, .. }) => assert!(!e.is_transient()),
+            other => panic!("Unexpected outcome: {:?}", other),
+        }
+
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body.as_array().unwrap().len(), 2);
+        assert!(body[0].get("HtmlBody").is_some());
+    }
+
+    #[tokio::test]
+    async fn send_batch_fails_as_a_whole_if_the_server_returns_500() {
+        // Arrange mock server
+        let mock_server = MockServer::start().await;
+        let email_client =
+            email_client(Url::parse(mock_server.uri().as_str()).expect("Unable to parse mock url"));
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client.send_batch(&[outgoing_email()]).await;
+
+        // Assert
+        assert!(outcome.unwrap_err().is_transient());
+    }
+
+    #[tokio::test]
+    async fn send_batch_rejects_oversized_batches_without_calling_the_api() {
+        // Arrange mock server
+        let mock_server = MockServer::start().await;
+        let email_client =
+            email_client(Url::parse(mock_server.uri().as_str()).expect("Unable to parse mock url"));
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let emails = vec![outgoing_email(); POSTMARK_MAX_BATCH_SIZE + 1];
+        let outcome = email_client.send_batch(&emails).await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+}
@@ -0,0 +1,354 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    sync::{Semaphore, SemaphorePermit},
+    time::Instant,
+};
+
+use super::{EmailHeader, EmailSender, OutgoingEmail, SendEmailError};
+use crate::domain::SubscriberEmail;
+
+/// How long to hold off after a 429 that didn't say how long to wait
+const DEFAULT_THROTTLE_PAUSE: Duration = Duration::from_secs(1);
+/// Longest pause, whatever the provider asked for
+const MAX_PAUSE: Duration = Duration::from_secs(60 * 60);
+
+/// Rates are brought within these bounds, waits are derived from them
+/// and have to fit in a `Duration`
+const MIN_MESSAGES_PER_SECOND: f64 = 0.01;
+const MAX_MESSAGES_PER_SECOND: f64 = 1_000_000.0;
+
+/// Token bucket limiting how many messages go out per second,
+/// along with how many requests can be in flight at once
+///
+/// A single limiter is meant to be shared by every sender of the process,
+/// so that the API and the delivery worker draw from the same budget
+pub struct RateLimiter {
+    bucket: Mutex<TokenBucket>,
+    /// Waiters line up here, so they get their tokens first come, first served
+    queue: tokio::sync::Mutex<()>,
+    in_flight: Semaphore,
+}
+
+impl RateLimiter {
+    pub fn new(messages_per_second: f64, burst: u32, max_in_flight: usize) -> Self {
+        // Zero, negative or NaN rates are misconfigurations, go as slow as allowed
+        let messages_per_second = if messages_per_second.is_nan() {
+            MIN_MESSAGES_PER_SECOND
+        } else {
+            messages_per_second.clamp(MIN_MESSAGES_PER_SECOND, MAX_MESSAGES_PER_SECOND)
+        };
+        Self {
+            bucket: Mutex::new(TokenBucket::new(
+                messages_per_second,
+                burst.max(1) as f64,
+                Instant::now(),
+            )),
+            queue: tokio::sync::Mutex::new(()),
+            in_flight: Semaphore::new(max_in_flight.max(1)),
+        }
+    }
+
+    /// Wait until `n_messages` can be sent,
+    /// the request counts as in flight until the permit is dropped
+    pub async fn acquire(&self, n_messages: usize) -> SemaphorePermit<'_> {
+        let permit = self
+            .in_flight
+            .acquire()
+            .await
+            .expect("The in-flight semaphore is never closed");
+
+        let _turn = self.queue.lock().await;
+        loop {
+            let wait = self
+                .bucket
+                .lock()
+                .unwrap()
+                .try_take(n_messages as f64, Instant::now());
+            match wait {
+                None => break,
+                Some(wait) => tokio::time::sleep(wait).await,
+            }
+        }
+
+        permit
+    }
+
+    /// Stop sending for a while, e.g. when the provider answers with a 429
+    pub fn pause_for(&self, pause: Duration) {
+        let until = Instant::now() + pause.min(MAX_PAUSE);
+        self.bucket.lock().unwrap().pause_until(until);
+    }
+}
+
+struct TokenBucket {
+    messages_per_second: f64,
+    capacity: f64,
+    tokens: f64,
+    refilled_at: Instant,
+    paused_until: Option<Instant>,
+}
+
+impl TokenBucket {
+    fn new(messages_per_second: f64, capacity: f64, now: Instant) -> Self {
+        Self {
+            messages_per_second,
+            capacity,
+            tokens: capacity,
+            refilled_at: now,
+            paused_until: None,
+        }
+    }
+
+    /// Take `n` tokens, or say how long to wait before trying again
+    ///
+    /// Batches larger than the bucket go through once it is full,
+    /// leaving it in debt so the average rate still holds
+    fn try_take(&mut self, n: f64, now: Instant) -> Option<Duration> {
+        if let Some(paused_until) = self.paused_until {
+            if now < paused_until {
+                return Some(paused_until - now);
+            }
+            self.paused_until = None;
+        }
+
+        let elapsed = now
+            .saturating_duration_since(self.refilled_at)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.messages_per_second).min(self.capacity);
+        self.refilled_at = now;
+
+        let needed = n.min(self.capacity);
+        if self.tokens >= needed {
+            self.tokens -= n;
+            None
+        } else {
+            Some(Duration::from_secs_f64(
+                (needed - self.tokens) / self.messages_per_second,
+            ))
+        }
+    }
+
+    fn pause_until(&mut self, until: Instant) {
+        // Never shorten a pause that is already running
+        if self.paused_until.is_none_or(|current| current < until) {
+            self.paused_until = Some(until);
+        }
+    }
+}
+
+/// Wraps another sender, keeping it under the limits of a `RateLimiter`
+pub struct RateLimitedSender {
+    inner: Arc<dyn EmailSender>,
+    limiter: Arc<RateLimiter>,
+}
+
+impl RateLimitedSender {
+    pub fn new(inner: Arc<dyn EmailSender>, limiter: Arc<RateLimiter>) -> Self {
+        Self { inner, limiter }
+    }
+
+    /// Back off when the provider tells us we are going too fast
+    fn observe<T>(&self, outcome: &Result<T, SendEmailError>) {
+        if let Err(e @ SendEmailError::Throttled { retry_after }) = outcome {
+            let pause = retry_after.unwrap_or(DEFAULT_THROTTLE_PAUSE);
+            tracing::warn!(
+                error.message = %e,
+                pause_seconds = pause.as_secs_f64(),
+                "The email provider is throttling us, pausing outgoing emails."
+            );
+            self.limiter.pause_for(pause);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for RateLimitedSender {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<(), SendEmailError> {
+        let _permit = self.limiter.acquire(1).await;
+        let outcome = self
+            .inner
+            .send_email_with_headers(recipient, subject, html_content, text_content, headers)
+            .await;
+        self.observe(&outcome);
+        outcome
+    }
+
+    async fn ping(&self) -> Option<Result<(), SendEmailError>> {
+        self.inner.ping().await
+    }
+
+    fn max_batch_size(&self) -> usize {
+        self.inner.max_batch_size()
+    }
+
+    async fn send_batch(
+        &self,
+        emails: &[OutgoingEmail],
+    ) -> Result<Vec<Result<(), SendEmailError>>, SendEmailError> {
+        let _permit = self.limiter.acquire(emails.len()).await;
+        let outcome = self.inner.send_batch(emails).await;
+        self.observe(&outcome);
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use claims::{assert_none, assert_some_eq};
+
+    use super::*;
+    use crate::email_client::test_data::{content, email, subject};
+
+    #[test]
+    fn a_full_bucket_lets_a_burst_through() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(10.0, 3.0, now);
+
+        for _ in 0..3 {
+            assert_none!(bucket.try_take(1.0, now));
+        }
+        assert_some_eq!(bucket.try_take(1.0, now), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn tokens_are_refilled_at_the_configured_rate() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(10.0, 1.0, now);
+        assert_none!(bucket.try_take(1.0, now));
+
+        assert!(bucket
+            .try_take(1.0, now + Duration::from_millis(50))
+            .is_some());
+        assert_none!(bucket.try_take(1.0, now + Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn batches_larger_than_the_bucket_put_it_in_debt() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(10.0, 5.0, now);
+
+        assert_none!(bucket.try_take(20.0, now));
+        // 15 tokens short, plus one for the next message
+        assert_some_eq!(bucket.try_take(1.0, now), Duration::from_millis(1600));
+    }
+
+    #[test]
+    fn invalid_rates_are_raised_to_the_minimum() {
+        for rate in [0.0, -1.0, f64::NAN] {
+            let limiter = RateLimiter::new(rate, 1, 1);
+            let mut bucket = limiter.bucket.lock().unwrap();
+            let now = bucket.refilled_at;
+
+            assert_none!(bucket.try_take(1.0, now));
+            assert_some_eq!(bucket.try_take(1.0, now), Duration::from_secs(100));
+        }
+    }
+
+    #[test]
+    fn pauses_are_capped() {
+        let limiter = RateLimiter::new(10.0, 1, 1);
+
+        limiter.pause_for(Duration::MAX);
+
+        let mut bucket = limiter.bucket.lock().unwrap();
+        let now = Instant::now();
+        let wait = bucket.try_take(1.0, now).unwrap();
+        assert!(wait <= MAX_PAUSE);
+    }
+
+    #[test]
+    fn nothing_goes_out_while_paused() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(10.0, 5.0, now);
+        bucket.pause_until(now + Duration::from_secs(2));
+        // A shorter pause doesn't cut the running one short
+        bucket.pause_until(now + Duration::from_secs(1));
+
+        assert_some_eq!(
+            bucket.try_take(1.0, now + Duration::from_secs(1)),
+            Duration::from_secs(1)
+        );
+        assert_none!(bucket.try_take(1.0, now + Duration::from_secs(2)));
+    }
+
+    /// Answers 429 on the first call, then keeps count of the messages it gets
+    struct ThrottlingSender {
+        calls: AtomicUsize,
+        retry_after: Duration,
+    }
+
+    #[async_trait::async_trait]
+    impl EmailSender for ThrottlingSender {
+        async fn send_email_with_headers(
+            &self,
+            _recipient: &SubscriberEmail,
+            _subject: &str,
+            _html_content: &str,
+            _text_content: &str,
+            _headers: &[EmailHeader],
+        ) -> Result<(), SendEmailError> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                return Err(SendEmailError::Throttled {
+                    retry_after: Some(self.retry_after),
+                });
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn sending_waits_for_the_retry_after_of_a_429() {
+        // Arrange
+        let inner = Arc::new(ThrottlingSender {
+            calls: AtomicUsize::new(0),
+            retry_after: Duration::from_millis(300),
+        });
+        let sender =
+            RateLimitedSender::new(inner.clone(), Arc::new(RateLimiter::new(100.0, 10, 1)));
+
+        // Act
+        let first = sender
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+        let started_at = Instant::now();
+        let second = sender
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert!(matches!(first, Err(SendEmailError::Throttled { .. })));
+        assert!(second.is_ok());
+        assert!(started_at.elapsed() >= Duration::from_millis(250));
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn in_flight_requests_are_capped() {
+        // Arrange
+        let limiter = Arc::new(RateLimiter::new(1000.0, 100, 2));
+        let first = limiter.acquire(1).await;
+        let _second = limiter.acquire(1).await;
+
+        // Act - a third request has to wait for one of the first two
+        let blocked = tokio::time::timeout(Duration::from_millis(50), limiter.acquire(1)).await;
+        drop(first);
+        let unblocked = tokio::time::timeout(Duration::from_millis(50), limiter.acquire(1)).await;
+
+        // Assert
+        assert!(blocked.is_err());
+        assert!(unblocked.is_ok());
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+
+use reqwest::{Client, Url};
+use secrecy::{ExposeSecret, Secret};
+
+use super::{
+    error_for_status, http_client, outgoing_headers, EmailHeader, EmailSender, SendEmailError,
+};
+use crate::domain::SubscriberEmail;
+
+/// SendGrid's v3 mail send API, authenticated with an API key
+pub struct SendGridClient {
+    http_client: Client,
+    base_url: Url,
+    sender: SubscriberEmail,
+    api_key: Secret<String>,
+}
+
+impl SendGridClient {
+    pub fn new(
+        base_url: Url,
+        sender: SubscriberEmail,
+        api_key: Secret<String>,
+        timeout: std::time::Duration,
+    ) -> Self {
+        Self {
+            http_client: http_client(timeout),
+            base_url,
+            sender,
+            api_key,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SendGridClient {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<(), SendEmailError> {
+        let url = self
+            .base_url
+            .join("v3/mail/send")
+            .expect("Unable to join base url");
+        let request_body = SendEmailRequest {
+            personalizations: [Personalization {
+                to: [Address {
+                    email: recipient.as_ref(),
+                }],
+            }],
+            from: Address {
+                email: self.sender.as_ref(),
+            },
+            subject,
+            // SendGrid wants the plain text part first
+            content: [
+                Content {
+                    r#type: "text/plain",
+                    value: text_content,
+                },
+                Content {
+                    r#type: "text/html",
+                    value: html_content,
+                },
+            ],
+            headers: headers
+                .iter()
+                .map(|h| (h.name.as_str(), h.value.as_str()))
+                .collect(),
+        };
+        error_for_status(
+            self.http_client
+                .post(url)
+                .headers(outgoing_headers())
+                .bearer_auth(self.api_key.expose_secret())
+                .json(&request_body)
+                .send()
+                .await?,
+        )?;
+
+        Ok(())
+    }
+}
+
+#[derive(serde::Serialize)]
+struct SendEmailRequest<'a> {
+    personalizations: [Personalization<'a>; 1],
+    from: Address<'a>,
+    subject: &'a str,
+    content: [Content<'a>; 2],
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    headers: BTreeMap<&'a str, &'a str>,
+}
+
+#[derive(serde::Serialize)]
+struct Personalization<'a> {
+    to: [Address<'a>; 1],
+}
+
+#[derive(serde::Serialize)]
+struct Address<'a> {
+    email: &'a str,
+}
+
+#[derive(serde::Serialize)]
+struct Content<'a> {
+    r#type: &'a str,
+    value: &'a str,
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+    use reqwest::Url;
+    use secrecy::Secret;
+    use wiremock::{
+        matchers::{any, body_partial_json, header, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::SendGridClient;
+    use crate::{
+        domain::SubscriberEmail,
+        email_client::{
+            test_data::{content, email, subject},
+            EmailHeader, EmailSender,
+        },
+    };
+
+    /// Get a test instance of `SendGridClient`
+    fn email_client(base_url: String) -> SendGridClient {
+        SendGridClient::new(
+            Url::parse(&base_url).unwrap(),
+            SubscriberEmail::parse("sender@example.com".into()).unwrap(),
+            Secret::new("my-api-key".into()),
+            std::time::Duration::from_millis(200),
+        )
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_the_expected_request() {
+        // Arrange mock server
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipient = email();
+        let subject = subject();
+
+        Mock::given(path("/v3/mail/send"))
+            .and(method("POST"))
+            .and(header("Authorization", "Bearer my-api-key"))
+            .and(header("Content-Type", "application/json"))
+            .and(body_partial_json(serde_json::json!({
+                "personalizations": [{ "to": [{ "email": recipient.as_ref() }] }],
+                "from": { "email": "sender@example.com" },
+                "subject": subject,
+                "content": [{ "type": "text/plain" }, { "type": "text/html" }],
+                "headers": { "List-Unsubscribe": "<https://example.com/unsubscribe>" },
+            })))
+            // SendGrid accepts messages with a 202
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let headers = [EmailHeader::new(
+            "List-Unsubscribe",
+            "<https://example.com/unsubscribe>",
+        )];
+        let outcome = email_client
+            .send_email_with_headers(&recipient, &subject, &content(), &content(), &headers)
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        // Arrange mock server
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+}
@@ -0,0 +1,402 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::{Client, Url};
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+
+use super::{
+    error_for_status, http_client, outgoing_headers, EmailHeader, EmailSender, SendEmailError,
+};
+use crate::domain::SubscriberEmail;
+
+/// Amazon SES v2 `SendEmail` API, requests are signed with AWS Signature Version 4
+pub struct SesClient {
+    http_client: Client,
+    base_url: Url,
+    region: String,
+    sender: SubscriberEmail,
+    access_key_id: String,
+    secret_access_key: Secret<String>,
+}
+
+impl SesClient {
+    pub fn new(
+        base_url: Url,
+        region: String,
+        sender: SubscriberEmail,
+        access_key_id: String,
+        secret_access_key: Secret<String>,
+        timeout: std::time::Duration,
+    ) -> Self {
+        Self {
+            http_client: http_client(timeout),
+            base_url,
+            region,
+            sender,
+            access_key_id,
+            secret_access_key,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SesClient {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<(), SendEmailError> {
+        let url = self
+            .base_url
+            .join("v2/email/outbound-emails")
+            .expect("Unable to join base url");
+        let request_body = SendEmailRequest {
+            from_email_address: self.sender.as_ref(),
+            destination: Destination {
+                to_addresses: [recipient.as_ref()],
+            },
+            content: Content {
+                simple: SimpleMessage {
+                    subject: Data { data: subject },
+                    body: Body {
+                        text: Data { data: text_content },
+                        html: Data { data: html_content },
+                    },
+                    headers,
+                },
+            },
+        };
+        let payload =
+            serde_json::to_vec(&request_body).expect("Failed to serialize SES request body");
+
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let authorization = authorization_header(
+            &SigningParams {
+                access_key_id: &self.access_key_id,
+                secret_access_key: &self.secret_access_key,
+                region: &self.region,
+                service: "ses",
+                timestamp: now,
+            },
+            "POST",
+            url.path(),
+            &[
+                ("content-type", "application/json"),
+                ("host", &host),
+                ("x-amz-date", &amz_date),
+            ],
+            &payload,
+        );
+
+        error_for_status(
+            self.http_client
+                .post(url)
+                .headers(outgoing_headers())
+                .header("Content-Type", "application/json")
+                .header("X-Amz-Date", amz_date)
+                .header("Authorization", authorization)
+                .body(payload)
+                .send()
+                .await?,
+        )?;
+
+        Ok(())
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from_email_address: &'a str,
+    destination: Destination<'a>,
+    content: Content<'a>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Destination<'a> {
+    to_addresses: [&'a str; 1],
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Content<'a> {
+    simple: SimpleMessage<'a>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SimpleMessage<'a> {
+    subject: Data<'a>,
+    body: Body<'a>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    headers: &'a [EmailHeader],
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Body<'a> {
+    text: Data<'a>,
+    html: Data<'a>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Data<'a> {
+    data: &'a str,
+}
+
+struct SigningParams<'a> {
+    access_key_id: &'a str,
+    secret_access_key: &'a Secret<String>,
+    region: &'a str,
+    service: &'a str,
+    timestamp: DateTime<Utc>,
+}
+
+/// The `Authorization` header of a request signed with AWS Signature Version 4
+///
+/// `headers` must be lowercase, sorted by name and include `host` and `x-amz-date`.
+/// The query string is assumed to be empty
+fn authorization_header(
+    params: &SigningParams,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    payload: &[u8],
+) -> String {
+    let date = params.timestamp.format("%Y%m%d").to_string();
+    let amz_date = params.timestamp.format("%Y%m%dT%H%M%SZ").to_string();
+    let scope = format!("{}/{}/{}/aws4_request", date, params.region, params.service);
+
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "{}\n{}\n\n{}\n{}\n{}",
+        method,
+        path,
+        canonical_headers,
+        signed_headers,
+        hex::encode(Sha256::digest(payload))
+    );
+
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let key = signing_key(
+        params.secret_access_key,
+        &date,
+        params.region,
+        params.service,
+    );
+    let signature = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()));
+
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        params.access_key_id, scope, signed_headers, signature
+    )
+}
+
+fn signing_key(
+    secret_access_key: &Secret<String>,
+    date: &str,
+    region: &str,
+    service: &str,
+) -> Vec<u8> {
+    let k_secret = format!("AWS4{}", secret_access_key.expose_secret());
+    let k_date = hmac_sha256(k_secret.as_bytes(), date.as_bytes());
+    let k_region = hmac_sha256(&k_date, region.as_bytes());
+    let k_service = hmac_sha256(&k_region, service.as_bytes());
+    hmac_sha256(&k_service, b"aws4_request")
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC can take a key of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use claims::{assert_err, assert_ok};
+    use reqwest::Url;
+    use secrecy::Secret;
+    use wiremock::{
+        matchers::{any, body_partial_json, header, header_exists, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::{authorization_header, signing_key, SesClient, SigningParams};
+    use crate::{
+        domain::SubscriberEmail,
+        email_client::{
+            test_data::{content, email, subject},
+            EmailHeader, EmailSender,
+        },
+    };
+
+    const EXAMPLE_SECRET_KEY: &str = "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY";
+
+    // Example taken from the AWS documentation on deriving the signing key
+    #[test]
+    fn signing_key_matches_the_aws_example() {
+        let key = signing_key(
+            &Secret::new(EXAMPLE_SECRET_KEY.into()),
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex::encode(key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+
+    // `get-vanilla` case of the AWS Signature Version 4 test suite
+    #[test]
+    fn authorization_header_matches_the_aws_test_suite() {
+        let secret = Secret::new(EXAMPLE_SECRET_KEY.into());
+        let params = SigningParams {
+            access_key_id: "AKIDEXAMPLE",
+            secret_access_key: &secret,
+            region: "us-east-1",
+            service: "service",
+            timestamp: Utc.with_ymd_and_hms(2015, 8, 30, 12, 36, 0).unwrap(),
+        };
+
+        let authorization = authorization_header(
+            &params,
+            "GET",
+            "/",
+            &[
+                ("host", "example.amazonaws.com"),
+                ("x-amz-date", "20150830T123600Z"),
+            ],
+            b"",
+        );
+
+        assert_eq!(
+            authorization,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+            SignedHeaders=host;x-amz-date, \
+            Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+    }
+
+    /// Get a test instance of `SesClient`
+    fn email_client(base_url: String) -> SesClient {
+        SesClient::new(
+            Url::parse(&base_url).unwrap(),
+            "eu-west-1".into(),
+            SubscriberEmail::parse("sender@example.com".into()).unwrap(),
+            "AKIDEXAMPLE".into(),
+            Secret::new(EXAMPLE_SECRET_KEY.into()),
+            std::time::Duration::from_millis(200),
+        )
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_the_expected_request() {
+        // Arrange mock server
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipient = email();
+
+        Mock::given(path("/v2/email/outbound-emails"))
+            .and(method("POST"))
+            .and(header("Content-Type", "application/json"))
+            .and(header_exists("X-Amz-Date"))
+            .and(SignedForSes)
+            .and(body_partial_json(serde_json::json!({
+                "FromEmailAddress": "sender@example.com",
+                "Destination": { "ToAddresses": [recipient.as_ref()] },
+                "Content": {
+                    "Simple": {
+                        "Headers": [{
+                            "Name": "List-Unsubscribe",
+                            "Value": "<https://example.com/unsubscribe>",
+                        }]
+                    }
+                },
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let headers = [EmailHeader::new(
+            "List-Unsubscribe",
+            "<https://example.com/unsubscribe>",
+        )];
+        let outcome = email_client
+            .send_email_with_headers(&recipient, &subject(), &content(), &content(), &headers)
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        // Arrange mock server
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    /// Checks the request is signed for SES in the configured region
+    struct SignedForSes;
+
+    impl wiremock::Match for SignedForSes {
+        fn matches(&self, request: &wiremock::Request) -> bool {
+            request
+                .headers
+                .get(&"Authorization".into())
+                .map(|values| {
+                    // Values are split on commas, put them back together
+                    let authorization = values
+                        .iter()
+                        .map(|v| v.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    authorization.starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/")
+                        && authorization.contains("/eu-west-1/ses/aws4_request")
+                        && authorization.contains("SignedHeaders=content-type;host;x-amz-date")
+                })
+                .unwrap_or(false)
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use lettre::{
+    message::{
+        header::{HeaderName, HeaderValue, Headers},
+        Mailbox, MultiPart,
+    },
+    transport::smtp::{
+        authentication::{Credentials, Mechanism},
+        PoolConfig,
+    },
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use secrecy::{ExposeSecret, Secret};
+
+use super::{EmailHeader, EmailSender, SendEmailError};
+use crate::domain::SubscriberEmail;
+
+/// How the connection to the SMTP server is secured
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Plain text, only meant for a relay on a trusted network
+    None,
+    /// Upgrade a plain text connection with `STARTTLS`, usually on port 587
+    StartTls,
+    /// TLS from the start of the connection, usually on port 465
+    Tls,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpAuthMechanism {
+    Plain,
+    Login,
+}
+
+/// Everything needed to open (pooled) connections to an SMTP server
+pub struct SmtpConnection {
+    pub host: String,
+    pub port: u16,
+    pub tls: SmtpTls,
+    /// Authenticate only when a username is set
+    pub credentials: Option<(String, Secret<String>)>,
+    pub auth_mechanism: SmtpAuthMechanism,
+    pub pool_max_size: u32,
+}
+
+/// Delivery through an SMTP server, e.g. an internal MTA
+pub struct SmtpClient {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+}
+
+impl SmtpClient {
+    pub fn new(
+        connection: SmtpConnection,
+        sender: SubscriberEmail,
+        timeout: std::time::Duration,
+    ) -> Result<Self, lettre::transport::smtp::Error> {
+        let builder = match connection.tls {
+            SmtpTls::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&connection.host)
+            }
+            SmtpTls::StartTls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&connection.host)?
+            }
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&connection.host)?,
+        };
+        let mut builder = builder
+            .port(connection.port)
+            .timeout(Some(timeout))
+            .pool_config(PoolConfig::new().max_size(connection.pool_max_size));
+
+        if let Some((username, password)) = connection.credentials {
+            let mechanism = match connection.auth_mechanism {
+                SmtpAuthMechanism::Plain => Mechanism::Plain,
+                SmtpAuthMechanism::Login => Mechanism::Login,
+            };
+            builder = builder
+                .credentials(Credentials::new(
+                    username,
+                    password.expose_secret().to_owned(),
+                ))
+                .authentication(vec![mechanism]);
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+            sender,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SmtpClient {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<(), SendEmailError> {
+        let message = build_message(&self.sender, recipient, subject, html_content, text_content)?;
+
+        // `MessageBuilder` only takes typed headers, custom ones are
+        // prepended to the formatted message instead
+        let mut raw = extra_headers(headers)?.to_string().into_bytes();
+        raw.extend(message.formatted());
+
+        self.transport.send_raw(message.envelope(), &raw).await?;
+
+        Ok(())
+    }
+
+    /// Connect and say hello, the way a send would start
+    async fn ping(&self) -> Option<Result<(), SendEmailError>> {
+        Some(match self.transport.test_connection().await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(SendEmailError::Io(std::io::Error::other(
+                "The SMTP server did not answer the connection test",
+            ))),
+            Err(e) => Err(e.into()),
+        })
+    }
+}
+
+/// A `multipart/alternative` message, mail clients pick the HTML part if they can
+pub(super) fn build_message(
+    sender: &SubscriberEmail,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+) -> Result<Message, SendEmailError> {
+    let mailbox = |email: &SubscriberEmail| {
+        email
+            .as_ref()
+            .parse::<Mailbox>()
+            .map_err(|e| SendEmailError::InvalidMessage(e.to_string()))
+    };
+
+    Message::builder()
+        .from(mailbox(sender)?)
+        .to(mailbox(recipient)?)
+        .subject(subject)
+        .multipart(MultiPart::alternative_plain_html(
+            text_content.to_owned(),
+            html_content.to_owned(),
+        ))
+        .map_err(|e| SendEmailError::InvalidMessage(e.to_string()))
+}
+
+pub(super) fn extra_headers(headers: &[EmailHeader]) -> Result<Headers, SendEmailError> {
+    let mut extra = Headers::new();
+    for header in headers {
+        let name = HeaderName::new_from_ascii(header.name.clone())
+            .map_err(|e| SendEmailError::InvalidMessage(e.to_string()))?;
+        extra.insert_raw(HeaderValue::new(name, header.value.clone()));
+    }
+    Ok(extra)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use claims::{assert_err, assert_ok};
+    use secrecy::Secret;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    use super::{SmtpAuthMechanism, SmtpClient, SmtpConnection, SmtpTls};
+    use crate::{
+        domain::SubscriberEmail,
+        email_client::{
+            test_data::{email, subject},
+            EmailHeader, EmailSender,
+        },
+    };
+
+    /// A bare bones SMTP server recording every line it receives
+    ///
+    /// It accepts any credentials and any message, unless told to
+    /// reject recipients with a permanent error
+    struct SmtpSink {
+        port: u16,
+        transcript: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl SmtpSink {
+        async fn start(reject_recipients: bool) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let transcript = Arc::new(Mutex::new(Vec::new()));
+
+            let lines = transcript.clone();
+            tokio::spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let lines = lines.clone();
+                    tokio::spawn(async move {
+                        let (reader, mut writer) = stream.into_split();
+                        let mut reader = BufReader::new(reader);
+                        writer.write_all(b"220 sink ESMTP\r\n").await.unwrap();
+
+                        let mut in_data = false;
+                        let mut line = String::new();
+                        while reader.read_line(&mut line).await.unwrap_or(0) > 0 {
+                            let received = line.trim_end_matches("\r\n").to_owned();
+                            line.clear();
+                            lines.lock().unwrap().push(received.clone());
+
+                            let reply: &[u8] = if in_data {
+                                if received != "." {
+                                    continue;
+                                }
+                                in_data = false;
+                                b"250 queued\r\n"
+                            } else {
+                                let command = received.to_uppercase();
+                                if command.starts_with("EHLO") {
+                                    b"250-sink\r\n250-AUTH PLAIN LOGIN\r\n250 8BITMIME\r\n"
+                                } else if command == "AUTH LOGIN" {
+                                    // base64 of `Username:`
+                                    b"334 VXNlcm5hbWU6\r\n"
+                                } else if received == "dXNlcg==" {
+                                    // base64 of `Password:`
+                                    b"334 UGFzc3dvcmQ6\r\n"
+                                } else if command.starts_with("AUTH") || received == "cGFzcw==" {
+                                    b"235 authenticated\r\n"
+                                } else if command.starts_with("RCPT") && reject_recipients {
+                                    b"550 no such user\r\n"
+                                } else if command == "DATA" {
+                                    in_data = true;
+                                    b"354 go ahead\r\n"
+                                } else if command == "QUIT" {
+                                    writer.write_all(b"221 bye\r\n").await.unwrap();
+                                    break;
+                                } else {
+                                    b"250 ok\r\n"
+                                }
+                            };
+                            writer.write_all(reply).await.unwrap();
+                        }
+                    });
+                }
+            });
+
+            Self { port, transcript }
+        }
+
+        fn transcript(&self) -> Vec<String> {
+            self.transcript.lock().unwrap().clone()
+        }
+    }
+
+    fn smtp_client(port: u16, auth_mechanism: SmtpAuthMechanism) -> SmtpClient {
+        SmtpClient::new(
+            SmtpConnection {
+                host: "127.0.0.1".into(),
+                port,
+                tls: SmtpTls::None,
+                credentials: Some(("user".into(), Secret::new("pass".into()))),
+                auth_mechanism,
+                pool_max_size: 2,
+            },
+            SubscriberEmail::parse("sender@example.com".into()).unwrap(),
+            std::time::Duration::from_secs(2),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_delivers_a_multipart_message_with_the_extra_headers() {
+        // Arrange
+        let sink = SmtpSink::start(false).await;
+        let email_client = smtp_client(sink.port, SmtpAuthMechanism::Plain);
+        let recipient = email();
+
+        // Act
+        let headers = [EmailHeader::new(
+            "List-Unsubscribe",
+            "<https://example.com/unsubscribe>",
+        )];
+        let outcome = email_client
+            .send_email_with_headers(
+                &recipient,
+                &subject(),
+                "<p>Hello in HTML</p>",
+                "Hello in plain text",
+                &headers,
+            )
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let transcript = sink.transcript();
+        // base64 of `\0user\0pass`
+        assert!(transcript.contains(&"AUTH PLAIN AHVzZXIAcGFzcw==".to_string()));
+        assert!(transcript.contains(&"MAIL FROM:<sender@example.com>".to_string()));
+        assert!(transcript.contains(&format!("RCPT TO:<{}>", recipient.as_ref())));
+        assert!(
+            transcript.contains(&"List-Unsubscribe: <https://example.com/unsubscribe>".to_string())
+        );
+        assert!(transcript
+            .iter()
+            .any(|l| l.starts_with("Content-Type: multipart/alternative")));
+        assert!(transcript.contains(&"Hello in plain text".to_string()));
+        assert!(transcript.contains(&"<p>Hello in HTML</p>".to_string()));
+    }
+
+    #[tokio::test]
+    async fn send_email_can_authenticate_with_login() {
+        // Arrange
+        let sink = SmtpSink::start(false).await;
+        let email_client = smtp_client(sink.port, SmtpAuthMechanism::Login);
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), "<p>Hi</p>", "Hi")
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let transcript = sink.transcript();
+        assert!(transcript.contains(&"AUTH LOGIN".to_string()));
+        // base64 of `user` and `pass`
+        assert!(transcript.contains(&"dXNlcg==".to_string()));
+        assert!(transcript.contains(&"cGFzcw==".to_string()));
+    }
+
+    #[tokio::test]
+    async fn a_rejected_recipient_is_a_permanent_error() {
+        // Arrange
+        let sink = SmtpSink::start(true).await;
+        let email_client = smtp_client(sink.port, SmtpAuthMechanism::Plain);
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), "<p>Hi</p>", "Hi")
+            .await;
+
+        // Assert
+        assert_err!(&outcome);
+        assert!(!outcome.unwrap_err().is_transient());
+    }
+
+    #[tokio::test]
+    async fn an_unreachable_server_is_a_transient_error() {
+        // Arrange, grab a free port and close it right away
+        let port = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let email_client = smtp_client(port, SmtpAuthMechanism::Plain);
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), "<p>Hi</p>", "Hi")
+            .await;
+
+        // Assert
+        assert!(outcome.unwrap_err().is_transient());
+    }
+}
@@ -0,0 +1,160 @@
+use anyhow::Context;
+use axum::{
+    body::{boxed, Full},
+    http::{
+        self,
+        header::{HeaderName, HeaderValue},
+    },
+    response::{IntoResponse, Response},
+};
+use sqlx::{postgres::PgHasArrayType, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::IdempotencyKey;
+
+#[derive(Debug, sqlx::Type)]
+#[sqlx(type_name = "header_pair")]
+struct HeaderPairRecord {
+    name: String,
+    value: Vec<u8>,
+}
+
+// `sqlx` needs to be told the name of the array type of our composite type
+impl PgHasArrayType for HeaderPairRecord {
+    fn array_type_info() -> sqlx::postgres::PgTypeInfo {
+        sqlx::postgres::PgTypeInfo::with_name("_header_pair")
+    }
+}
+
+// Only ever lives on the stack for the duration of a request, no need to box
+#[allow(clippy::large_enum_variant)]
+pub enum NextAction {
+    /// First time we see this key: go ahead and save the response with
+    /// `save_response` once done. The transaction holds the row lock that
+    /// makes concurrent duplicates wait for us
+    StartProcessing(Transaction<'static, Postgres>),
+    /// The request was already processed, replay the saved response
+    ReturnSavedResponse(Response),
+}
+
+#[tracing::instrument(name = "Try processing idempotent request", skip(pool), err(Debug))]
+pub async fn try_processing(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<NextAction, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+
+    // A concurrent request with the same key blocks here until
+    // the transaction that inserted the row commits or rolls back
+    let n_inserted_rows = sqlx::query!(
+        r#"
+        INSERT INTO idempotency (user_id, idempotency_key, created_at)
+        VALUES ($1, $2, now())
+        ON CONFLICT DO NOTHING
+        "#,
+        user_id,
+        idempotency_key.as_ref()
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+
+    if n_inserted_rows > 0 {
+        Ok(NextAction::StartProcessing(transaction))
+    } else {
+        let saved_response = get_saved_response(pool, idempotency_key, user_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("We expected a saved response, we didn't find it"))?;
+
+        Ok(NextAction::ReturnSavedResponse(saved_response))
+    }
+}
+
+async fn get_saved_response(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<Option<Response>, anyhow::Error> {
+    let saved_response = sqlx::query!(
+        r#"
+        SELECT
+          response_status_code as "response_status_code!",
+          response_headers as "response_headers!: Vec<HeaderPairRecord>",
+          response_body as "response_body!"
+        FROM idempotency
+        WHERE user_id = $1 AND idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref()
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    if let Some(r) = saved_response {
+        let status_code = http::StatusCode::from_u16(r.response_status_code.try_into()?)?;
+        let mut response = (status_code, r.response_body).into_response();
+        for HeaderPairRecord { name, value } in r.response_headers {
+            response
+                .headers_mut()
+                .append(HeaderName::try_from(name)?, HeaderValue::try_from(value)?);
+        }
+
+        Ok(Some(response))
+    } else {
+        Ok(None)
+    }
+}
+
+#[tracing::instrument(
+    name = "Save response of idempotent request",
+    skip(transaction, http_response),
+    err(Debug)
+)]
+pub async fn save_response(
+    mut transaction: Transaction<'static, Postgres>,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    http_response: Response,
+) -> Result<Response, anyhow::Error> {
+    let (response_head, body) = http_response.into_parts();
+    // The body has to be buffered in memory to be stored
+    let body = hyper::body::to_bytes(body)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to read response body: {}", e))?;
+
+    let status_code = response_head.status.as_u16() as i16;
+    let headers = response_head
+        .headers
+        .iter()
+        .map(|(name, value)| HeaderPairRecord {
+            name: name.as_str().to_owned(),
+            value: value.as_bytes().to_owned(),
+        })
+        .collect::<Vec<_>>();
+
+    // `query_unchecked!` because `sqlx` can't check custom array types at compile time
+    sqlx::query_unchecked!(
+        r#"
+        UPDATE idempotency
+        SET
+          response_status_code = $3,
+          response_headers = $4,
+          response_body = $5
+        WHERE user_id = $1 AND idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+        status_code,
+        headers,
+        body.as_ref()
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to save the response of an idempotent request.")?;
+    transaction.commit().await?;
+
+    // Rebuild the response from the buffered body
+    let http_response = Response::from_parts(response_head, boxed(Full::from(body)));
+    Ok(http_response)
+}
@@ -0,0 +1,443 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::Utc;
+use rand::Rng;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::Span;
+use uuid::Uuid;
+
+use crate::{
+    domain::SubscriberEmail,
+    email_client::{EmailHeader, EmailSender, OutgoingEmail, SendEmailError},
+    merge_fields::MergeFields,
+    unsubscribe_links::UnsubscribeLinks,
+};
+
+/// Deliveries are dead-lettered once they failed this many times
+pub const MAX_DELIVERY_ATTEMPTS: i16 = 6;
+/// Delay before the first retry, doubled on every further attempt
+const BASE_RETRY_DELAY: Duration = Duration::from_secs(30);
+/// Upper bound for the delay between two attempts
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+pub enum ExecutionOutcome {
+    /// A batch of tasks was processed, more may be waiting
+    TaskCompleted,
+    EmptyQueue,
+    /// The email provider's circuit breaker is open, the tasks were left untouched
+    ProviderUnavailable,
+}
+
+/// Keep delivering queued newsletter issues forever
+///
+/// Several workers (e.g. one per running instance) can share the same
+/// queue, rows being processed are locked and skipped by the others
+pub async fn run_worker_until_stopped(
+    pool: PgPool,
+    email_client: Arc<dyn EmailSender>,
+    unsubscribe_links: UnsubscribeLinks,
+) {
+    loop {
+        match try_execute_task(&pool, email_client.as_ref(), &unsubscribe_links).await {
+            Ok(ExecutionOutcome::EmptyQueue | ExecutionOutcome::ProviderUnavailable) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                // Most likely a database hiccup, back off a bit
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+/// Why a delivery attempt failed
+struct DeliveryFailure {
+    /// Worth trying again later, e.g. a 5xx or a timeout
+    transient: bool,
+    /// The provider asked us not to come back before then
+    retry_after: Option<Duration>,
+    message: String,
+}
+
+impl From<&SendEmailError> for DeliveryFailure {
+    fn from(e: &SendEmailError) -> Self {
+        Self {
+            transient: e.is_transient(),
+            retry_after: e.retry_after(),
+            message: e.to_string(),
+        }
+    }
+}
+
+/// Deliver a batch of due tasks, as large as the email provider allows
+///
+/// The tasks stay locked until every outcome is recorded,
+/// everything is committed at once at the end
+#[tracing::instrument(skip_all, fields(n_tasks = tracing::field::Empty), err(Debug))]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &dyn EmailSender,
+    unsubscribe_links: &UnsubscribeLinks,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let batch_size = email_client.max_batch_size().max(1) as i64;
+    let (mut transaction, tasks) = match dequeue_tasks(pool, batch_size).await? {
+        Some(dequeued) => dequeued,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    Span::current().record("n_tasks", tasks.len());
+
+    let mut issues: HashMap<Uuid, NewsletterIssue> = HashMap::new();
+    let mut deliveries = Vec::with_capacity(tasks.len());
+    let mut emails = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        // The subscriber left the list (or vanished) after the issue was queued
+        let subscriber_id = match (task.subscriber_id, task.subscriber_status.as_deref()) {
+            (Some(subscriber_id), Some("confirmed")) => subscriber_id,
+            _ => {
+                tracing::info!(
+                    newsletter_issue_id = %task.newsletter_issue_id,
+                    subscriber_email = %task.subscriber_email,
+                    "Skipping delivery to a subscriber that is no longer confirmed."
+                );
+                delete_task(&mut transaction, &task).await?;
+                continue;
+            }
+        };
+
+        let recipient = match SubscriberEmail::parse(task.subscriber_email.clone()) {
+            Ok(recipient) => recipient,
+            // Retrying won't fix invalid contact details
+            Err(e) => {
+                let failure = DeliveryFailure {
+                    transient: false,
+                    retry_after: None,
+                    message: e,
+                };
+                record_failure(&mut transaction, &task, failure).await?;
+                continue;
+            }
+        };
+
+        let issue = match issues.entry(task.newsletter_issue_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(get_issue(pool, task.newsletter_issue_id).await?),
+        };
+        let unsubscribe_link = unsubscribe_links.link(subscriber_id, task.list_id);
+        let merge_fields = MergeFields {
+            name: task.subscriber_name.as_deref().unwrap_or_default(),
+            email: &task.subscriber_email,
+            attributes: task.subscriber_attributes.as_ref(),
+        };
+
+        emails.push(OutgoingEmail {
+            recipient,
+            subject: merge_fields.render_text(&issue.title),
+            html_content: with_html_unsubscribe_footer(
+                &merge_fields.render_html(&issue.html_content),
+                &unsubscribe_link,
+            ),
+            text_content: with_text_unsubscribe_footer(
+                &merge_fields.render_text(&issue.text_content),
+                &unsubscribe_link,
+            ),
+            headers: list_unsubscribe_headers(&unsubscribe_link).to_vec(),
+        });
+        deliveries.push(task);
+    }
+
+    if !emails.is_empty() {
+        match email_client.send_batch(&emails).await {
+            Ok(results) => {
+                for (task, result) in deliveries.iter().zip(results) {
+                    match result {
+                        Ok(()) => delete_task(&mut transaction, task).await?,
+                        Err(e) => record_failure(&mut transaction, task, (&e).into()).await?,
+                    }
+                }
+            }
+            // Not an attempt, don't use up the retries of the deliveries
+            Err(SendEmailError::CircuitOpen) => {
+                transaction.rollback().await?;
+                return Ok(ExecutionOutcome::ProviderUnavailable);
+            }
+            // Nothing went through, every delivery failed the same way
+            Err(e) => {
+                for task in &deliveries {
+                    record_failure(&mut transaction, task, (&e).into()).await?;
+                }
+            }
+        }
+    }
+
+    transaction.commit().await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// Retry the delivery later if it is worth it, dead-letter it otherwise
+async fn record_failure(
+    transaction: &mut PgTransaction,
+    task: &Task,
+    failure: DeliveryFailure,
+) -> Result<(), anyhow::Error> {
+    let n_attempts = task.n_attempts + 1;
+    if failure.transient && n_attempts < MAX_DELIVERY_ATTEMPTS {
+        let retry_in = retry_delay(n_attempts).max(failure.retry_after.unwrap_or_default());
+        tracing::warn!(
+            newsletter_issue_id = %task.newsletter_issue_id,
+            subscriber_email = %task.subscriber_email,
+            n_attempts,
+            error.message = %failure.message,
+            "Failed to deliver issue to a confirmed subscriber. \
+            Retrying in {} seconds.",
+            retry_in.as_secs(),
+        );
+        reschedule_task(transaction, task, n_attempts, retry_in).await
+    } else {
+        tracing::error!(
+            newsletter_issue_id = %task.newsletter_issue_id,
+            subscriber_email = %task.subscriber_email,
+            n_attempts,
+            error.message = %failure.message,
+            "Failed to deliver issue to a confirmed subscriber. \
+            Moving it to the dead letters.",
+        );
+        dead_letter_task(transaction, task, n_attempts, &failure.message).await
+    }
+}
+
+/// Exponential backoff with jitter, so failed deliveries don't all
+/// hit the provider again at the same moment
+///
+/// Returns a delay between half and all of `BASE_RETRY_DELAY * 2^(n_attempts - 1)`,
+/// capped to `MAX_RETRY_DELAY`
+fn retry_delay(n_attempts: i16) -> Duration {
+    let exponent = n_attempts.saturating_sub(1).clamp(0, 16) as u32;
+    let ceiling = BASE_RETRY_DELAY
+        .saturating_mul(2u32.pow(exponent))
+        .min(MAX_RETRY_DELAY);
+    let half = ceiling / 2;
+
+    half + half.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+}
+
+/// Append the unsubscribe link to the HTML body of an issue
+fn with_html_unsubscribe_footer(html_content: &str, unsubscribe_link: &str) -> String {
+    format!(
+        r#"{html_content}<hr /><p>Don't want these emails anymore? <a href="{}">Unsubscribe</a></p>"#,
+        htmlescape::encode_minimal(unsubscribe_link)
+    )
+}
+
+/// Append the unsubscribe link to the plain text body of an issue
+fn with_text_unsubscribe_footer(text_content: &str, unsubscribe_link: &str) -> String {
+    format!("{text_content}\n\n--\nUnsubscribe: {unsubscribe_link}")
+}
+
+/// RFC 8058 one-click unsubscribe headers
+///
+/// Mail clients `POST` `List-Unsubscribe=One-Click` to the link,
+/// which the unsubscribe endpoint accepts as is
+fn list_unsubscribe_headers(unsubscribe_link: &str) -> [EmailHeader; 2] {
+    [
+        EmailHeader::new("List-Unsubscribe", format!("<{unsubscribe_link}>")),
+        EmailHeader::new("List-Unsubscribe-Post", "List-Unsubscribe=One-Click"),
+    ]
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct Task {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_attempts: i16,
+    list_id: Uuid,
+    subscriber_id: Option<Uuid>,
+    /// On the list of the issue
+    subscriber_status: Option<String>,
+    subscriber_name: Option<String>,
+    subscriber_attributes: Option<serde_json::Value>,
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_tasks(
+    pool: &PgPool,
+    limit: i64,
+) -> Result<Option<(PgTransaction, Vec<Task>)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+
+    // The rows stay locked until the transaction ends,
+    // `SKIP LOCKED` lets concurrent workers move on to other rows
+    let tasks = sqlx::query_as!(
+        Task,
+        r#"
+        SELECT
+          q.newsletter_issue_id,
+          q.subscriber_email,
+          q.n_attempts,
+          i.list_id,
+          s.id AS "subscriber_id?",
+          m.status AS "subscriber_status?",
+          s.name AS "subscriber_name?",
+          s.attributes AS "subscriber_attributes?"
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i ON i.newsletter_issue_id = q.newsletter_issue_id
+        LEFT JOIN subscriptions s ON s.email = q.subscriber_email
+        LEFT JOIN list_memberships m ON m.subscriber_id = s.id AND m.list_id = i.list_id
+        WHERE q.next_attempt_at <= now()
+        ORDER BY q.next_attempt_at
+        FOR UPDATE OF q
+        SKIP LOCKED
+        LIMIT $1
+        "#,
+        limit
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+
+    if tasks.is_empty() {
+        // Nothing to do, release the connection right away
+        transaction.rollback().await?;
+        return Ok(None);
+    }
+    Ok(Some((transaction, tasks)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(transaction: &mut PgTransaction, task: &Task) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE
+          newsletter_issue_id = $1 AND
+          subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn reschedule_task(
+    transaction: &mut PgTransaction,
+    task: &Task,
+    n_attempts: i16,
+    retry_in: Duration,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+          n_attempts = $3,
+          next_attempt_at = $4
+        WHERE
+          newsletter_issue_id = $1 AND
+          subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        n_attempts,
+        Utc::now() + chrono::Duration::from_std(retry_in)?
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn dead_letter_task(
+    transaction: &mut PgTransaction,
+    task: &Task,
+    n_attempts: i16,
+    last_error: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_dead_letters (
+          newsletter_issue_id,
+          subscriber_email,
+          n_attempts,
+          last_error,
+          failed_at
+        )
+        VALUES ($1, $2, $3, $4, now())
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        n_attempts,
+        last_error
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    delete_task(transaction, task).await
+}
+
+struct NewsletterIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT title, text_content, html_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(issue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        retry_delay, with_html_unsubscribe_footer, with_text_unsubscribe_footer, BASE_RETRY_DELAY,
+        MAX_RETRY_DELAY,
+    };
+
+    #[test]
+    fn the_unsubscribe_link_is_appended_to_both_bodies() {
+        let link = "https://example.com/subscriptions/unsubscribe?token=a.b";
+        assert!(with_html_unsubscribe_footer("<p>Hi</p>", link)
+            .ends_with(&format!(r#"<a href="{link}">Unsubscribe</a></p>"#)));
+        assert!(with_text_unsubscribe_footer("Hi", link).ends_with(link));
+    }
+
+    #[test]
+    fn the_first_retry_waits_at_most_the_base_delay() {
+        let delay = retry_delay(1);
+        assert!(delay >= BASE_RETRY_DELAY / 2);
+        assert!(delay <= BASE_RETRY_DELAY);
+    }
+
+    #[test]
+    fn the_retry_delay_grows_exponentially() {
+        let delay = retry_delay(4);
+        assert!(delay >= BASE_RETRY_DELAY * 4);
+        assert!(delay <= BASE_RETRY_DELAY * 8);
+    }
+
+    #[test]
+    fn the_retry_delay_is_capped() {
+        for n_attempts in [12, 100, i16::MAX] {
+            assert!(retry_delay(n_attempts) <= MAX_RETRY_DELAY);
+        }
+    }
+}
@@ -2,7 +2,18 @@ pub mod authentication;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod mailing_lists;
+pub mod merge_fields;
+pub mod metrics;
+pub mod problem;
+pub mod request_id;
 pub mod routes;
+pub mod segments;
+pub mod session_state;
 pub mod startup;
+pub mod subscriber_attributes;
 pub mod telemetry;
+pub mod unsubscribe_links;
 pub mod util;
@@ -0,0 +1,60 @@
+//! Mailing lists, which subscribers join one by one
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::domain::ListSlug;
+
+pub struct MailingList {
+    pub list_id: Uuid,
+    pub slug: String,
+    pub name: String,
+}
+
+#[tracing::instrument(name = "Find a mailing list", skip(executor), err(Debug))]
+pub async fn find_list(
+    executor: impl PgExecutor<'_>,
+    slug: &ListSlug,
+) -> Result<Option<MailingList>, sqlx::Error> {
+    sqlx::query_as!(
+        MailingList,
+        r#"SELECT list_id, slug, name FROM lists WHERE slug = $1"#,
+        slug.as_ref()
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+#[tracing::instrument(name = "Get all mailing lists", skip(pool), err(Debug))]
+pub async fn get_lists(pool: &PgPool) -> Result<Vec<MailingList>, sqlx::Error> {
+    sqlx::query_as!(
+        MailingList,
+        r#"SELECT list_id, slug, name FROM lists ORDER BY created_at"#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Returns `None` if a list with the same slug already exists
+#[tracing::instrument(name = "Create a mailing list", skip(pool), err(Debug))]
+pub async fn create_list(
+    pool: &PgPool,
+    slug: &ListSlug,
+    name: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let list_id = Uuid::new_v4();
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO lists (list_id, slug, name, created_at)
+        VALUES ($1, $2, $3, now())
+        ON CONFLICT (slug) DO NOTHING
+        "#,
+        list_id,
+        slug.as_ref(),
+        name
+    )
+    .execute(pool)
+    .await?
+    .rows_affected()
+        == 1;
+    Ok(inserted.then_some(list_id))
+}
@@ -1,18 +1,28 @@
 use zero2prod::{
     configuration::get_configuration,
     startup::build,
-    telemetry::{get_subscriber, init_subscriber},
+    telemetry::{get_subscriber, get_tracer, init_subscriber},
 };
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
+    // Read configuration settings from config files
+    let configuration = get_configuration().expect("Failed to read configuration.");
+
     // Setup tracing layers and initialize the tracing subscriber
-    let subscriber = get_subscriber("zero2prod".to_string(), "info".to_string(), std::io::stdout);
+    let subscriber = get_subscriber(
+        "zero2prod".to_string(),
+        "info".to_string(),
+        std::io::stdout,
+        Some(get_tracer(&configuration.telemetry)),
+    );
     init_subscriber(subscriber);
 
-    // Read configuration settings from config files and then build and start the server
-    let configuration = get_configuration().expect("Failed to read configuration.");
+    // Build and start the server
     let server = build(configuration).await?;
     server.await.unwrap();
+
+    // Export the spans still waiting in the batch
+    opentelemetry::global::shutdown_tracer_provider();
     Ok(())
 }
@@ -0,0 +1,125 @@
+//! Per-subscriber placeholders in newsletter issues
+//!
+//! `{{ name }}`, `{{ email }}` and `{{ attributes.<key> }}` are replaced
+//! by the values of each recipient. A fallback can follow a pipe,
+//! e.g. `{{ attributes.company | your company }}`, for when the value is missing
+use serde_json::Value;
+
+use crate::subscriber_attributes::display_value;
+
+pub struct MergeFields<'a> {
+    pub name: &'a str,
+    pub email: &'a str,
+    /// The JSONB attributes of the subscriber
+    pub attributes: Option<&'a Value>,
+}
+
+impl MergeFields<'_> {
+    fn lookup(&self, field: &str) -> Option<String> {
+        match field {
+            "name" => Some(self.name.to_owned()),
+            "email" => Some(self.email.to_owned()),
+            _ => field
+                .strip_prefix("attributes.")
+                .and_then(|key| self.attributes?.get(key))
+                .map(display_value),
+        }
+    }
+
+    /// For subjects and plain text bodies
+    pub fn render_text(&self, template: &str) -> String {
+        render(template, |field| self.lookup(field))
+    }
+
+    /// For HTML bodies, values are escaped while fallbacks are kept as written
+    pub fn render_html(&self, template: &str) -> String {
+        render(template, |field| {
+            self.lookup(field)
+                .map(|value| htmlescape::encode_minimal(&value))
+        })
+    }
+}
+
+/// Unknown fields and missing values render as their fallback, or as nothing
+fn render(template: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(length) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        let placeholder = &rest[start + 2..start + 2 + length];
+        let (field, fallback) = placeholder.split_once('|').unwrap_or((placeholder, ""));
+        let value = lookup(field.trim()).filter(|value| !value.is_empty());
+
+        rendered.push_str(&rest[..start]);
+        rendered.push_str(value.as_deref().unwrap_or(fallback.trim()));
+        rest = &rest[start + 2 + length + 2..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::MergeFields;
+
+    #[test]
+    fn placeholders_are_replaced_with_the_subscriber_values() {
+        let attributes = json!({ "company": "Acme", "employees": 12 });
+        let fields = MergeFields {
+            name: "Ursula",
+            email: "ursula@example.com",
+            attributes: Some(&attributes),
+        };
+
+        assert_eq!(
+            fields.render_text(
+                "Hi {{name}}, how are the {{ attributes.employees }} of {{ attributes.company }}?"
+            ),
+            "Hi Ursula, how are the 12 of Acme?"
+        );
+    }
+
+    #[test]
+    fn missing_values_fall_back() {
+        let fields = MergeFields {
+            name: "Ursula",
+            email: "ursula@example.com",
+            attributes: None,
+        };
+
+        assert_eq!(
+            fields.render_text("At {{ attributes.company | your company }}{{ unknown }}!"),
+            "At your company!"
+        );
+    }
+
+    #[test]
+    fn values_are_escaped_in_html() {
+        let attributes = json!({ "company": "<b>Acme</b>" });
+        let fields = MergeFields {
+            name: "Ursula",
+            email: "ursula@example.com",
+            attributes: Some(&attributes),
+        };
+
+        assert_eq!(
+            fields.render_html("<p>{{ attributes.company }}</p>"),
+            "<p>&lt;b&gt;Acme&lt;/b&gt;</p>"
+        );
+    }
+
+    #[test]
+    fn unclosed_placeholders_are_left_as_they_are() {
+        let fields = MergeFields {
+            name: "Ursula",
+            email: "ursula@example.com",
+            attributes: None,
+        };
+
+        assert_eq!(fields.render_text("{{ name }} {{ name"), "Ursula {{ name");
+    }
+}
@@ -0,0 +1,342 @@
+//! Prometheus metrics, scraped at `/metrics`
+use std::{sync::Arc, time::Instant};
+
+use axum::{
+    extract::{MatchedPath, State},
+    http::{self, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use sqlx::PgPool;
+
+use crate::{
+    domain::SubscriberEmail,
+    email_client::{EmailHeader, EmailSender, OutgoingEmail, SendEmailError},
+    startup::AppState,
+};
+
+/// Every metric of the application, registered in a registry of their own
+///
+/// Built once in `startup::build` and shared, like the email client
+pub struct Metrics {
+    registry: Registry,
+    http_requests_total: IntCounterVec,
+    http_request_duration_seconds: HistogramVec,
+    db_pool_connections: IntGauge,
+    db_pool_idle_connections: IntGauge,
+    emails_sent_total: IntCounterVec,
+    emails_failed_total: IntCounterVec,
+    subscriptions_created_total: IntCounter,
+    subscriptions_confirmed_total: IntCounter,
+    logins_total: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let http_requests_total = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests served"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_request_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time spent serving HTTP requests",
+            ),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let db_pool_connections = IntGauge::new(
+            "db_pool_connections",
+            "Connections currently open in the Postgres pool",
+        )
+        .unwrap();
+        let db_pool_idle_connections = IntGauge::new(
+            "db_pool_idle_connections",
+            "Idle connections in the Postgres pool",
+        )
+        .unwrap();
+        let emails_sent_total = IntCounterVec::new(
+            Opts::new("emails_sent_total", "Emails accepted by the email provider"),
+            &["provider"],
+        )
+        .unwrap();
+        let emails_failed_total = IntCounterVec::new(
+            Opts::new(
+                "emails_failed_total",
+                "Emails the email provider failed to accept",
+            ),
+            &["provider"],
+        )
+        .unwrap();
+        let subscriptions_created_total = IntCounter::new(
+            "subscriptions_created_total",
+            "New subscribers, pending confirmation",
+        )
+        .unwrap();
+        let subscriptions_confirmed_total = IntCounter::new(
+            "subscriptions_confirmed_total",
+            "Subscriptions confirmed through their confirmation link",
+        )
+        .unwrap();
+        let logins_total =
+            IntCounterVec::new(Opts::new("logins_total", "Login attempts"), &["outcome"]).unwrap();
+
+        let registry = Registry::new();
+        registry
+            .register(Box::new(http_requests_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(http_request_duration_seconds.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(db_pool_connections.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(db_pool_idle_connections.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(emails_sent_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(emails_failed_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(subscriptions_created_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(subscriptions_confirmed_total.clone()))
+            .unwrap();
+        registry.register(Box::new(logins_total.clone())).unwrap();
+
+        Self {
+            registry,
+            http_requests_total,
+            http_request_duration_seconds,
+            db_pool_connections,
+            db_pool_idle_connections,
+            emails_sent_total,
+            emails_failed_total,
+            subscriptions_created_total,
+            subscriptions_confirmed_total,
+            logins_total,
+        }
+    }
+
+    pub fn subscription_created(&self) {
+        self.subscriptions_created_total.inc();
+    }
+
+    pub fn subscription_confirmed(&self) {
+        self.subscriptions_confirmed_total.inc();
+    }
+
+    pub fn login_succeeded(&self) {
+        self.logins_total.with_label_values(&["success"]).inc();
+    }
+
+    pub fn login_failed(&self) {
+        self.logins_total.with_label_values(&["failure"]).inc();
+    }
+
+    /// Everything in the Prometheus text format, pool stats taken right now
+    pub fn render(&self, pool: &PgPool) -> String {
+        self.db_pool_connections.set(pool.size() as i64);
+        self.db_pool_idle_connections.set(pool.num_idle() as i64);
+
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("Failed to encode metrics");
+        String::from_utf8(buffer).expect("Metrics are not valid UTF-8")
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[tracing::instrument(name = "Render metrics", skip(app_state))]
+pub async fn get_metrics(State(app_state): State<AppState>) -> impl IntoResponse {
+    (
+        [(http::header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        app_state.metrics.render(&app_state.pool),
+    )
+}
+
+/// Count and time every request, by route template rather than actual path
+/// so that ids in paths don't blow up the number of series
+pub async fn track_requests<B>(
+    State(app_state): State<AppState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let started_at = Instant::now();
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_else(|| "unmatched".into());
+
+    let response = next.run(request).await;
+
+    let status = response.status().as_u16().to_string();
+    let labels = [method.as_str(), route.as_str(), status.as_str()];
+    let metrics = &app_state.metrics;
+    metrics.http_requests_total.with_label_values(&labels).inc();
+    metrics
+        .http_request_duration_seconds
+        .with_label_values(&labels)
+        .observe(started_at.elapsed().as_secs_f64());
+
+    response
+}
+
+/// Wraps an email provider, counting the messages it accepts or not
+pub struct MeteredSender {
+    inner: Arc<dyn EmailSender>,
+    provider: &'static str,
+    metrics: Arc<Metrics>,
+}
+
+impl MeteredSender {
+    pub fn new(inner: Arc<dyn EmailSender>, provider: &'static str, metrics: Arc<Metrics>) -> Self {
+        Self {
+            inner,
+            provider,
+            metrics,
+        }
+    }
+
+    fn record(&self, sent: usize, failed: usize) {
+        self.metrics
+            .emails_sent_total
+            .with_label_values(&[self.provider])
+            .inc_by(sent as u64);
+        self.metrics
+            .emails_failed_total
+            .with_label_values(&[self.provider])
+            .inc_by(failed as u64);
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for MeteredSender {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<(), SendEmailError> {
+        let outcome = self
+            .inner
+            .send_email_with_headers(recipient, subject, html_content, text_content, headers)
+            .await;
+        match outcome {
+            Ok(()) => self.record(1, 0),
+            Err(_) => self.record(0, 1),
+        }
+        outcome
+    }
+
+    async fn ping(&self) -> Option<Result<(), SendEmailError>> {
+        self.inner.ping().await
+    }
+
+    fn max_batch_size(&self) -> usize {
+        self.inner.max_batch_size()
+    }
+
+    async fn send_batch(
+        &self,
+        emails: &[OutgoingEmail],
+    ) -> Result<Vec<Result<(), SendEmailError>>, SendEmailError> {
+        let outcome = self.inner.send_batch(emails).await;
+        match &outcome {
+            Ok(results) => {
+                let sent = results.iter().filter(|result| result.is_ok()).count();
+                self.record(sent, results.len() - sent);
+            }
+            Err(_) => self.record(0, emails.len()),
+        }
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Accepts every message but the ones sent to `rejected`
+    struct FakeSender {
+        rejected: SubscriberEmail,
+    }
+
+    #[async_trait::async_trait]
+    impl EmailSender for FakeSender {
+        async fn send_email_with_headers(
+            &self,
+            recipient: &SubscriberEmail,
+            _subject: &str,
+            _html_content: &str,
+            _text_content: &str,
+            _headers: &[EmailHeader],
+        ) -> Result<(), SendEmailError> {
+            if recipient.as_ref() == self.rejected.as_ref() {
+                return Err(SendEmailError::Rejected {
+                    code: 406,
+                    message: "Inactive recipient".into(),
+                });
+            }
+            Ok(())
+        }
+    }
+
+    fn email(address: &str) -> OutgoingEmail {
+        OutgoingEmail {
+            recipient: SubscriberEmail::parse(address.into()).unwrap(),
+            subject: "Subject".into(),
+            html_content: "<p>Content</p>".into(),
+            text_content: "Content".into(),
+            headers: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn emails_are_counted_per_provider_and_outcome() {
+        // Arrange
+        let metrics = Arc::new(Metrics::new());
+        let sender = MeteredSender::new(
+            Arc::new(FakeSender {
+                rejected: SubscriberEmail::parse("inactive@example.com".into()).unwrap(),
+            }),
+            "postmark",
+            metrics.clone(),
+        );
+
+        // Act
+        sender
+            .send_batch(&[
+                email("ursula@example.com"),
+                email("inactive@example.com"),
+                email("le_guin@example.com"),
+            ])
+            .await
+            .unwrap();
+
+        // Assert
+        let sent = metrics.emails_sent_total.with_label_values(&["postmark"]);
+        let failed = metrics.emails_failed_total.with_label_values(&["postmark"]);
+        assert_eq!(sent.get(), 2);
+        assert_eq!(failed.get(), 1);
+    }
+}
@@ -0,0 +1,151 @@
+//! Error bodies for API clients, as RFC 7807 problem details
+use std::borrow::Cow;
+
+use axum::{
+    http::{self, header, HeaderValue},
+    response::{IntoResponse, Response},
+};
+
+use crate::request_id::RequestId;
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// An `application/problem+json` body
+///
+/// `code` is stable and meant for machines, `detail` for humans.
+/// Unexpected errors get a generic problem: their cause is only logged,
+/// the request id is there to find it again
+#[derive(Debug, serde::Serialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    type_: &'static str,
+    title: &'static str,
+    #[serde(serialize_with = "serialize_status")]
+    status: http::StatusCode,
+    code: &'static str,
+    detail: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<FieldError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+fn serialize_status<S: serde::Serializer>(
+    status: &http::StatusCode,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u16(status.as_u16())
+}
+
+impl Problem {
+    pub fn new(status: http::StatusCode, code: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            // No documentation per problem type, `code` tells them apart
+            type_: "about:blank",
+            title: status.canonical_reason().unwrap_or("Error"),
+            status,
+            code,
+            detail: detail.into(),
+            errors: vec![],
+            request_id: RequestId::current().map(|id| id.to_string()),
+        }
+    }
+
+    /// 400, listing every field that failed validation
+    pub fn validation(errors: ValidationErrors) -> Self {
+        Self {
+            errors: errors.0,
+            ..Self::new(
+                http::StatusCode::BAD_REQUEST,
+                "validation_failed",
+                "Some fields are invalid.",
+            )
+        }
+    }
+
+    pub fn internal_error() -> Self {
+        Self::new(
+            http::StatusCode::INTERNAL_SERVER_ERROR,
+            "internal_error",
+            "Something went wrong on our side.",
+        )
+    }
+}
+
+impl IntoResponse for Problem {
+    fn into_response(self) -> Response {
+        let body = serde_json::to_vec(&self).expect("Problems always serialize");
+        (
+            self.status,
+            [(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON))],
+            body,
+        )
+            .into_response()
+    }
+}
+
+/// Why a single field of a request was rejected
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct FieldError {
+    /// Nested fields are dotted, e.g. `attributes.company`
+    pub field: Cow<'static, str>,
+    pub code: &'static str,
+    pub detail: String,
+}
+
+impl FieldError {
+    pub fn new(
+        field: impl Into<Cow<'static, str>>,
+        code: &'static str,
+        detail: impl Into<String>,
+    ) -> Self {
+        Self {
+            field: field.into(),
+            code,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Every field error of a request, so clients can fix them all at once
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationErrors(pub Vec<FieldError>);
+
+impl std::fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let details: Vec<_> = self.0.iter().map(|error| error.detail.as_str()).collect();
+        f.write_str(&details.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn problems_serialize_with_their_field_errors() {
+        let problem = Problem::validation(ValidationErrors(vec![FieldError::new(
+            "email",
+            "invalid_email",
+            "not-an-email is not a valid subscriber email.",
+        )]));
+
+        let body = serde_json::to_value(&problem).unwrap();
+
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "type": "about:blank",
+                "title": "Bad Request",
+                "status": 400,
+                "code": "validation_failed",
+                "detail": "Some fields are invalid.",
+                "errors": [{
+                    "field": "email",
+                    "code": "invalid_email",
+                    "detail": "not-an-email is not a valid subscriber email.",
+                }],
+            })
+        );
+    }
+}
@@ -0,0 +1,146 @@
+//! Correlate requests across logs, responses and the services we call
+use axum::{
+    body::{self, HttpBody},
+    http::{self, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest request id accepted from callers
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static CURRENT_REQUEST_ID: RequestId;
+}
+
+/// Identifies a request, either sent by the caller or generated by us
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// Keep the caller's id when it is sane, make up one otherwise
+    pub fn from_header(value: Option<&HeaderValue>) -> Self {
+        value
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| Self::parse(value).ok())
+            .unwrap_or_else(Self::generate)
+    }
+
+    /// Ids end up in logs and in headers sent to other services,
+    /// only short ids made of unambiguous characters are accepted
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let is_valid_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.');
+        if s.is_empty() || s.len() > MAX_REQUEST_ID_LENGTH || !s.chars().all(is_valid_char) {
+            return Err(format!("{} is not a valid request id.", s));
+        }
+        Ok(Self(s.to_owned()))
+    }
+
+    pub fn generate() -> Self {
+        Self(Uuid::new_v4().to_string())
+    }
+
+    /// The id of the request being served by the current task, if any
+    pub fn current() -> Option<Self> {
+        CURRENT_REQUEST_ID.try_with(Clone::clone).ok()
+    }
+}
+
+impl AsRef<str> for RequestId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Settle on the id of the request before anything else looks at it
+///
+/// The id is written back on the request for the tracing span, made available
+/// to the handler through `RequestId::current`, echoed in the response and
+/// added to empty 500 bodies so users have something to quote to support
+pub async fn propagate_request_id<B>(mut request: Request<B>, next: Next<B>) -> Response {
+    let request_id = RequestId::from_header(request.headers().get(REQUEST_ID_HEADER));
+    let header_value = HeaderValue::from_str(request_id.as_ref())
+        .expect("Request ids are always valid header values");
+    request
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER, header_value.clone());
+    request.extensions_mut().insert(request_id.clone());
+
+    let mut response = CURRENT_REQUEST_ID
+        .scope(request_id.clone(), next.run(request))
+        .await;
+
+    if response.status() == http::StatusCode::INTERNAL_SERVER_ERROR
+        && response.body().size_hint().exact() == Some(0)
+    {
+        // The length of the empty body was set by the handler
+        response.headers_mut().remove(http::header::CONTENT_LENGTH);
+        *response.body_mut() = body::boxed(body::Full::from(format!(
+            "Something went wrong. If it keeps happening, contact us with this request id: {}",
+            request_id
+        )));
+    }
+    response
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER, header_value);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+
+    use super::*;
+
+    #[test]
+    fn uuids_and_short_tokens_are_valid_request_ids() {
+        assert_ok!(RequestId::parse(&Uuid::new_v4().to_string()));
+        assert_ok!(RequestId::parse("req_01H2.abc-9"));
+    }
+
+    #[test]
+    fn empty_request_ids_are_rejected() {
+        assert_err!(RequestId::parse(""));
+    }
+
+    #[test]
+    fn request_ids_that_are_too_long_are_rejected() {
+        assert_ok!(RequestId::parse(&"a".repeat(MAX_REQUEST_ID_LENGTH)));
+        assert_err!(RequestId::parse(&"a".repeat(MAX_REQUEST_ID_LENGTH + 1)));
+    }
+
+    #[test]
+    fn request_ids_with_unexpected_characters_are_rejected() {
+        for id in ["with space", "new\nline", "quote\"", "<script>", "ünicode"] {
+            assert_err!(RequestId::parse(id));
+        }
+    }
+
+    #[test]
+    fn an_invalid_header_gets_a_generated_id() {
+        let header = HeaderValue::from_static("not valid!");
+        let request_id = RequestId::from_header(Some(&header));
+        assert_ok!(Uuid::parse_str(request_id.as_ref()));
+    }
+
+    #[tokio::test]
+    async fn the_current_request_id_is_only_set_while_serving_a_request() {
+        let request_id = RequestId::generate();
+
+        let current = CURRENT_REQUEST_ID
+            .scope(request_id.clone(), async { RequestId::current() })
+            .await;
+
+        assert_eq!(current, Some(request_id));
+        assert_eq!(RequestId::current(), None);
+    }
+}
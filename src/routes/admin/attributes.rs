@@ -0,0 +1,205 @@
+use anyhow::Context;
+use axum::{
+    extract::{Form, State},
+    http,
+    response::{Html, IntoResponse, Redirect},
+};
+use axum_extra::extract::cookie::SignedCookieJar;
+
+use crate::{
+    authentication::UserId,
+    domain::AttributeKey,
+    startup::AppState,
+    subscriber_attributes::{
+        create_attribute_definition, get_attribute_schema, AttributeKind, NewAttributeDefinition,
+    },
+    util::{error_chain_fmt, flash_cookie, take_flash_html},
+};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    key: String,
+    label: String,
+    kind: String,
+    /// Comma separated, only for choices
+    #[serde(default)]
+    options: String,
+    /// Checkboxes are only sent when ticked
+    #[serde(default)]
+    required: Option<String>,
+}
+
+impl TryFrom<FormData> for NewAttributeDefinition {
+    type Error = String;
+
+    fn try_from(form: FormData) -> Result<Self, Self::Error> {
+        let key = AttributeKey::parse(form.key)?;
+        let label = form.label.trim().to_owned();
+        if label.is_empty() {
+            return Err("The attribute needs a label.".into());
+        }
+        let kind = AttributeKind::parse(&form.kind)?;
+        let mut options: Vec<String> = vec![];
+        if kind == AttributeKind::Choice {
+            for option in form.options.split(',').map(str::trim) {
+                if !option.is_empty() && !options.iter().any(|o| o == option) {
+                    options.push(option.to_owned());
+                }
+            }
+            if options.is_empty() {
+                return Err("A choice needs at least one option.".into());
+            }
+        }
+        Ok(Self {
+            key,
+            label,
+            kind,
+            options,
+            required: form.required.is_some(),
+        })
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum SubscriberAttributesError {
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SubscriberAttributesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for SubscriberAttributesError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            SubscriberAttributesError::Unexpected(_) => {
+                http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "List subscriber attributes",
+    skip(app_state, jar, user_id),
+    fields(user_id=%*user_id),
+    err(Debug)
+)]
+pub async fn subscriber_attributes(
+    State(app_state): State<AppState>,
+    jar: SignedCookieJar,
+    user_id: UserId,
+) -> Result<impl IntoResponse, SubscriberAttributesError> {
+    let schema = get_attribute_schema(&app_state.pool)
+        .await
+        .context("Failed to retrieve the subscriber attribute schema.")?;
+    let (jar, flash_html) = take_flash_html(jar);
+
+    let rows: String = schema
+        .definitions()
+        .iter()
+        .map(|d| {
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                htmlescape::encode_minimal(&d.key),
+                htmlescape::encode_minimal(&d.label),
+                d.kind.as_str(),
+                htmlescape::encode_minimal(&d.options.join(", ")),
+                if d.required { "yes" } else { "no" },
+            )
+        })
+        .collect();
+    let kind_options: String = AttributeKind::ALL
+        .iter()
+        .map(|kind| format!(r#"<option value="{0}">{0}</option>"#, kind.as_str()))
+        .collect();
+
+    Ok((
+        jar,
+        Html(format!(
+            r#"<!DOCTYPE html>
+      <html lang="en">
+        <head>
+          <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+          <title>Subscriber attributes</title>
+        </head>
+        <body>
+          {flash_html}
+          <table>
+            <thead>
+              <tr><th>Key</th><th>Label</th><th>Kind</th><th>Options</th><th>Required</th></tr>
+            </thead>
+            <tbody>
+              {rows}
+            </tbody>
+          </table>
+          <form action="/admin/attributes" method="post">
+            <label
+              >Key
+              <input type="text" placeholder="e.g. company" name="key" />
+            </label>
+            <label
+              >Label
+              <input type="text" placeholder="e.g. Company" name="label" />
+            </label>
+            <label
+              >Kind
+              <select name="kind">{kind_options}</select>
+            </label>
+            <br />
+            <label
+              >Options, for choices
+              <input type="text" placeholder="e.g. en, fr, de" name="options" />
+            </label>
+            <label
+              ><input type="checkbox" name="required" />
+              Required
+            </label>
+            <br />
+            <button type="submit">Create attribute</button>
+          </form>
+          <p><a href="/admin/dashboard">&lt;- Back</a></p>
+        </body>
+      </html>
+      "#
+        )),
+    ))
+}
+
+#[tracing::instrument(
+    name = "Create a subscriber attribute from the admin form",
+    skip(form, app_state, jar, user_id),
+    fields(user_id=%*user_id, key=%form.key),
+    err(Debug)
+)]
+pub async fn create_subscriber_attribute(
+    State(app_state): State<AppState>,
+    jar: SignedCookieJar,
+    user_id: UserId,
+    Form(form): Form<FormData>,
+) -> Result<impl IntoResponse, SubscriberAttributesError> {
+    let redirect_with_flash = |jar: SignedCookieJar, message: &str| {
+        (
+            jar.add(flash_cookie(message)),
+            Redirect::to("/admin/attributes"),
+        )
+    };
+
+    let definition = match NewAttributeDefinition::try_from(form) {
+        Ok(definition) => definition,
+        Err(e) => return Ok(redirect_with_flash(jar, &e)),
+    };
+
+    let message = if create_attribute_definition(&app_state.pool, &definition)
+        .await
+        .context("Failed to create a subscriber attribute.")?
+    {
+        format!("The {} attribute has been created.", definition.key)
+    } else {
+        format!("There already is a {} attribute.", definition.key)
+    };
+    Ok(redirect_with_flash(jar, &message))
+}
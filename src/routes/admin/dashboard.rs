@@ -0,0 +1,91 @@
+use anyhow::Context;
+use axum::{
+    extract::State,
+    http,
+    response::{Html, IntoResponse},
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{authentication::UserId, startup::AppState};
+
+#[derive(thiserror::Error)]
+pub enum DashboardError {
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for DashboardError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        crate::util::error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for DashboardError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            DashboardError::Unexpected(_) => {
+                http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Admin dashboard",
+    skip(app_state, user_id),
+    fields(user_id=%*user_id),
+    err(Debug)
+)]
+pub async fn admin_dashboard(
+    State(app_state): State<AppState>,
+    user_id: UserId,
+) -> Result<impl IntoResponse, DashboardError> {
+    let username = get_username(*user_id, &app_state.pool).await?;
+
+    Ok(Html(format!(
+        r#"<!DOCTYPE html>
+      <html lang="en">
+        <head>
+          <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+          <title>Admin dashboard</title>
+        </head>
+        <body>
+          <p>Welcome {}!</p>
+          <p>Available actions:</p>
+          <ol>
+            <li><a href="/admin/password">Change password</a></li>
+            <li><a href="/admin/subscribers">List subscribers</a></li>
+            <li><a href="/admin/lists">Manage mailing lists</a></li>
+            <li><a href="/admin/segments">Manage segments</a></li>
+            <li><a href="/admin/attributes">Manage subscriber attributes</a></li>
+            <li><a href="/admin/newsletters">Send a newsletter issue</a></li>
+            <li>
+              <form name="logoutForm" action="/admin/logout" method="post">
+                <input type="submit" value="Logout" />
+              </form>
+            </li>
+          </ol>
+        </body>
+      </html>
+      "#,
+        htmlescape::encode_minimal(&username)
+    )))
+}
+
+#[tracing::instrument(name = "Get username", skip(pool), err(Debug))]
+pub async fn get_username(user_id: Uuid, pool: &PgPool) -> Result<String, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT username
+        FROM users
+        WHERE user_id = $1
+        "#,
+        user_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to perform a query to retrieve a username.")?;
+
+    Ok(row.username)
+}
@@ -0,0 +1,143 @@
+use anyhow::Context;
+use axum::{
+    extract::{Form, State},
+    http,
+    response::{Html, IntoResponse, Redirect},
+};
+use axum_extra::extract::cookie::SignedCookieJar;
+
+use crate::{
+    authentication::UserId,
+    domain::ListSlug,
+    mailing_lists::{create_list, get_lists},
+    startup::AppState,
+    util::{error_chain_fmt, flash_cookie, take_flash_html},
+};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    slug: String,
+    name: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum MailingListsError {
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for MailingListsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for MailingListsError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            MailingListsError::Unexpected(_) => {
+                http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "List mailing lists",
+    skip(app_state, jar, user_id),
+    fields(user_id=%*user_id),
+    err(Debug)
+)]
+pub async fn mailing_lists(
+    State(app_state): State<AppState>,
+    jar: SignedCookieJar,
+    user_id: UserId,
+) -> Result<impl IntoResponse, MailingListsError> {
+    let lists = get_lists(&app_state.pool)
+        .await
+        .context("Failed to retrieve mailing lists.")?;
+    let (jar, flash_html) = take_flash_html(jar);
+
+    let rows: String = lists
+        .iter()
+        .map(|l| {
+            format!(
+                "<tr><td>{}</td><td>{}</td></tr>",
+                htmlescape::encode_minimal(&l.slug),
+                htmlescape::encode_minimal(&l.name),
+            )
+        })
+        .collect();
+
+    Ok((
+        jar,
+        Html(format!(
+            r#"<!DOCTYPE html>
+      <html lang="en">
+        <head>
+          <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+          <title>Mailing lists</title>
+        </head>
+        <body>
+          {flash_html}
+          <table>
+            <thead>
+              <tr><th>Slug</th><th>Name</th></tr>
+            </thead>
+            <tbody>
+              {rows}
+            </tbody>
+          </table>
+          <form action="/admin/lists" method="post">
+            <label
+              >Slug
+              <input type="text" placeholder="e.g. rust-weekly" name="slug" />
+            </label>
+            <label
+              >Name
+              <input type="text" placeholder="e.g. Rust Weekly" name="name" />
+            </label>
+            <button type="submit">Create list</button>
+          </form>
+          <p><a href="/admin/dashboard">&lt;- Back</a></p>
+        </body>
+      </html>
+      "#
+        )),
+    ))
+}
+
+#[tracing::instrument(
+    name = "Create a mailing list from the admin form",
+    skip(form, app_state, jar, user_id),
+    fields(user_id=%*user_id, slug=%form.slug),
+    err(Debug)
+)]
+pub async fn create_mailing_list(
+    State(app_state): State<AppState>,
+    jar: SignedCookieJar,
+    user_id: UserId,
+    Form(form): Form<FormData>,
+) -> Result<impl IntoResponse, MailingListsError> {
+    let redirect_with_flash = |jar: SignedCookieJar, message: &str| {
+        (jar.add(flash_cookie(message)), Redirect::to("/admin/lists"))
+    };
+
+    let slug = match ListSlug::parse(form.slug) {
+        Ok(slug) => slug,
+        Err(e) => return Ok(redirect_with_flash(jar, &e)),
+    };
+    let name = form.name.trim();
+    if name.is_empty() {
+        return Ok(redirect_with_flash(jar, "The list needs a name."));
+    }
+
+    let message = match create_list(&app_state.pool, &slug, name)
+        .await
+        .context("Failed to create a mailing list.")?
+    {
+        Some(_) => format!("The {slug} list has been created."),
+        None => format!("There already is a {slug} list."),
+    };
+    Ok(redirect_with_flash(jar, &message))
+}
@@ -0,0 +1,53 @@
+use axum::{
+    extract::State,
+    http,
+    response::{IntoResponse, Redirect},
+};
+use axum_extra::extract::cookie::SignedCookieJar;
+
+use crate::{
+    authentication::UserId,
+    session_state::{delete_session, session_id_from_jar, session_removal_cookie},
+    startup::AppState,
+    util::{error_chain_fmt, flash_cookie},
+};
+
+#[derive(thiserror::Error)]
+pub enum LogoutError {
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for LogoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for LogoutError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            LogoutError::Unexpected(_) => http::StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+}
+
+#[tracing::instrument(name = "Logout", skip(app_state, jar, user_id), fields(user_id=%*user_id), err(Debug))]
+pub async fn logout(
+    State(app_state): State<AppState>,
+    jar: SignedCookieJar,
+    user_id: UserId,
+) -> Result<impl IntoResponse, LogoutError> {
+    // `UserId` already made sure there is a live session behind the cookie
+    if let Some(session_id) = session_id_from_jar(&jar) {
+        delete_session(&app_state.pool, session_id)
+            .await
+            .map_err(anyhow::Error::new)?;
+    }
+
+    Ok((
+        jar.remove(session_removal_cookie())
+            .add(flash_cookie("You have successfully logged out.")),
+        Redirect::to("/login"),
+    ))
+}
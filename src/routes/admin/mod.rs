@@ -0,0 +1,17 @@
+mod attributes;
+mod dashboard;
+mod lists;
+mod logout;
+mod newsletters;
+mod password;
+mod segments;
+mod subscribers;
+
+pub use attributes::{create_subscriber_attribute, subscriber_attributes};
+pub use dashboard::admin_dashboard;
+pub use lists::{create_mailing_list, mailing_lists};
+pub use logout::logout;
+pub use newsletters::{publish_newsletter_form, publish_newsletter_from_form};
+pub use password::{change_password, change_password_form};
+pub use segments::{create_segment_from_form, segments};
+pub use subscribers::{export_subscribers, list_subscribers, set_subscriber_tags};
@@ -0,0 +1,138 @@
+use anyhow::Context;
+use axum::{
+    extract::State,
+    http,
+    response::{Html, IntoResponse},
+};
+use axum_extra::extract::cookie::SignedCookieJar;
+
+use crate::{
+    authentication::UserId,
+    domain::ListSlug,
+    mailing_lists::get_lists,
+    segments::get_segments,
+    startup::AppState,
+    util::{error_chain_fmt, take_flash_html},
+};
+
+#[derive(thiserror::Error)]
+pub enum PublishNewsletterFormError {
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PublishNewsletterFormError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for PublishNewsletterFormError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            PublishNewsletterFormError::Unexpected(_) => {
+                http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Publish newsletter form",
+    skip(app_state, jar, user_id),
+    fields(user_id=%*user_id),
+    err(Debug)
+)]
+pub async fn publish_newsletter_form(
+    State(app_state): State<AppState>,
+    jar: SignedCookieJar,
+    user_id: UserId,
+) -> Result<impl IntoResponse, PublishNewsletterFormError> {
+    let lists = get_lists(&app_state.pool)
+        .await
+        .context("Failed to retrieve mailing lists.")?;
+    let list_options: String = lists
+        .iter()
+        .map(|l| {
+            let selected = if l.slug == ListSlug::DEFAULT {
+                " selected"
+            } else {
+                ""
+            };
+            format!(
+                r#"<option value="{}"{selected}>{}</option>"#,
+                htmlescape::encode_attribute(&l.slug),
+                htmlescape::encode_minimal(&l.name),
+            )
+        })
+        .collect();
+    let segments = get_segments(&app_state.pool)
+        .await
+        .context("Failed to retrieve segments.")?;
+    let segment_options: String = segments
+        .iter()
+        .map(|s| {
+            format!(
+                r#"<option value="{}">{}</option>"#,
+                htmlescape::encode_attribute(&s.slug),
+                htmlescape::encode_minimal(&s.name),
+            )
+        })
+        .collect();
+    let (jar, flash_html) = take_flash_html(jar);
+    // Every rendering of the form gets a fresh key, so a double submit
+    // of the same form only publishes the issue once
+    let idempotency_key = uuid::Uuid::new_v4();
+
+    Ok((
+        jar,
+        Html(format!(
+            r#"<!DOCTYPE html>
+      <html lang="en">
+        <head>
+          <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+          <title>Publish Newsletter Issue</title>
+        </head>
+        <body>
+          {flash_html}
+          <form action="/admin/newsletters" method="post">
+            <label
+              >List:<br />
+              <select name="list">{list_options}</select>
+            </label>
+            <br />
+            <label
+              >Segment:<br />
+              <select name="segment"><option value="">Everyone</option>{segment_options}</select>
+            </label>
+            <br />
+            <label
+              >Title:<br />
+              <input type="text" placeholder="Enter the issue title" name="title" />
+            </label>
+            <br />
+            <label
+              >Plain text content:<br />
+              <textarea placeholder="Enter the content in plain text" name="text_content" rows="20" cols="50"></textarea>
+            </label>
+            <br />
+            <label
+              >HTML content:<br />
+              <textarea placeholder="Enter the content in HTML format" name="html_content" rows="20" cols="50"></textarea>
+            </label>
+            <br />
+            <p>
+              <code>{{{{ name }}}}</code>, <code>{{{{ email }}}}</code> and
+              <code>{{{{ attributes.&lt;key&gt; }}}}</code> are replaced for each subscriber,
+              e.g. <code>{{{{ attributes.company | your company }}}}</code>.
+            </p>
+            <input hidden type="text" name="idempotency_key" value="{idempotency_key}" />
+            <button type="submit">Publish</button>
+          </form>
+          <p><a href="/admin/dashboard">&lt;- Back</a></p>
+        </body>
+      </html>
+      "#
+        )),
+    ))
+}
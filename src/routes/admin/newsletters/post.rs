@@ -0,0 +1,126 @@
+use anyhow::Context;
+use axum::{
+    extract::{Form, State},
+    http,
+    response::{IntoResponse, Redirect, Response},
+};
+use axum_extra::extract::cookie::SignedCookieJar;
+
+use crate::{
+    authentication::UserId,
+    domain::{ListSlug, SegmentSlug},
+    idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+    mailing_lists::find_list,
+    routes::newsletters::enqueue_newsletter_issue,
+    segments::find_segment,
+    startup::AppState,
+    util::{error_chain_fmt, flash_cookie},
+};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    title: String,
+    text_content: String,
+    html_content: String,
+    idempotency_key: String,
+    /// Slug of the list to publish to, the default list if missing
+    #[serde(default)]
+    list: Option<String>,
+    /// Slug of a saved segment, everyone on the list if missing or empty
+    #[serde(default)]
+    segment: Option<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum PublishFormError {
+    #[error("{0}")]
+    Validation(String),
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PublishFormError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for PublishFormError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            PublishFormError::Validation(_) => http::StatusCode::BAD_REQUEST.into_response(),
+            PublishFormError::Unexpected(_) => {
+                http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Publish a newsletter issue from the admin form",
+    skip(form, app_state, jar, user_id),
+    fields(user_id=%*user_id),
+    err(Debug)
+)]
+pub async fn publish_newsletter_from_form(
+    State(app_state): State<AppState>,
+    jar: SignedCookieJar,
+    user_id: UserId,
+    Form(form): Form<FormData>,
+) -> Result<Response, PublishFormError> {
+    let FormData {
+        title,
+        text_content,
+        html_content,
+        idempotency_key,
+        list,
+        segment,
+    } = form;
+    let idempotency_key: IdempotencyKey = idempotency_key
+        .try_into()
+        .map_err(PublishFormError::Validation)?;
+    let list = list
+        .map_or_else(|| Ok(ListSlug::default()), ListSlug::parse)
+        .map_err(PublishFormError::Validation)?;
+    let list = find_list(&app_state.pool, &list)
+        .await
+        .context("Failed to look up the mailing list.")?
+        .ok_or_else(|| PublishFormError::Validation(format!("There is no {list} list.")))?;
+    let segment_id = match segment.filter(|segment| !segment.is_empty()) {
+        None => None,
+        Some(segment) => {
+            let segment = SegmentSlug::parse(segment).map_err(PublishFormError::Validation)?;
+            let found = find_segment(&app_state.pool, &segment)
+                .await
+                .context("Failed to look up the segment.")?
+                .ok_or_else(|| {
+                    PublishFormError::Validation(format!("There is no {segment} segment."))
+                })?;
+            Some(found.segment_id)
+        }
+    };
+
+    let mut transaction = match try_processing(&app_state.pool, &idempotency_key, *user_id).await? {
+        NextAction::StartProcessing(transaction) => transaction,
+        NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+    };
+
+    enqueue_newsletter_issue(
+        &mut transaction,
+        list.list_id,
+        segment_id,
+        &title,
+        &html_content,
+        &text_content,
+    )
+    .await?;
+
+    let response = (
+        jar.add(flash_cookie(
+            "The newsletter issue has been accepted - emails will go out shortly.",
+        )),
+        Redirect::to("/admin/newsletters"),
+    )
+        .into_response();
+    Ok(save_response(transaction, &idempotency_key, *user_id, response).await?)
+}
@@ -0,0 +1,45 @@
+use axum::response::{Html, IntoResponse};
+use axum_extra::extract::cookie::SignedCookieJar;
+
+use crate::{authentication::UserId, util::take_flash_html};
+
+#[tracing::instrument(name = "Change password form", skip(jar, user_id), fields(user_id=%*user_id))]
+pub async fn change_password_form(jar: SignedCookieJar, user_id: UserId) -> impl IntoResponse {
+    let (jar, flash_html) = take_flash_html(jar);
+
+    (
+        jar,
+        Html(format!(
+            r#"<!DOCTYPE html>
+      <html lang="en">
+        <head>
+          <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+          <title>Change Password</title>
+        </head>
+        <body>
+          {flash_html}
+          <form action="/admin/password" method="post">
+            <label
+              >Current password
+              <input type="password" placeholder="Enter current password" name="current_password" />
+            </label>
+            <br />
+            <label
+              >New password
+              <input type="password" placeholder="Enter new password" name="new_password" />
+            </label>
+            <br />
+            <label
+              >Confirm new password
+              <input type="password" placeholder="Type the new password again" name="new_password_check" />
+            </label>
+            <br />
+            <button type="submit">Change password</button>
+          </form>
+          <p><a href="/admin/dashboard">&lt;- Back</a></p>
+        </body>
+      </html>
+      "#
+        )),
+    )
+}
@@ -0,0 +1,105 @@
+use axum::{
+    extract::{Form, State},
+    http,
+    response::{IntoResponse, Redirect},
+};
+use axum_extra::extract::cookie::SignedCookieJar;
+use secrecy::{ExposeSecret, Secret};
+
+use crate::{
+    authentication::{self, validate_credentials, AuthError, Credentials, UserId},
+    routes::admin::dashboard::get_username,
+    startup::AppState,
+    util::{error_chain_fmt, flash_cookie},
+};
+
+/// OWASP recommends accepting passphrases at least 12 characters long
+const MIN_PASSWORD_LENGTH: usize = 12;
+/// Upper bound so hashing can't be abused as a DoS vector
+const MAX_PASSWORD_LENGTH: usize = 128;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    current_password: Secret<String>,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum ChangePasswordError {
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ChangePasswordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for ChangePasswordError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            ChangePasswordError::Unexpected(_) => {
+                http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Change password",
+    skip(form, app_state, jar, user_id),
+    fields(user_id=%*user_id),
+    err(Debug)
+)]
+pub async fn change_password(
+    State(app_state): State<AppState>,
+    jar: SignedCookieJar,
+    user_id: UserId,
+    Form(form): Form<FormData>,
+) -> Result<impl IntoResponse, ChangePasswordError> {
+    let redirect_with_flash = |jar: SignedCookieJar, message: &str| {
+        (
+            jar.add(flash_cookie(message)),
+            Redirect::to("/admin/password"),
+        )
+    };
+
+    if form.new_password.expose_secret() != form.new_password_check.expose_secret() {
+        return Ok(redirect_with_flash(
+            jar,
+            "You entered two different new passwords - the field values must match.",
+        ));
+    }
+
+    let new_password_length = form.new_password.expose_secret().chars().count();
+    if !(MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&new_password_length) {
+        return Ok(redirect_with_flash(
+            jar,
+            &format!(
+                "The new password must be between {} and {} characters long.",
+                MIN_PASSWORD_LENGTH, MAX_PASSWORD_LENGTH
+            ),
+        ));
+    }
+
+    let username = get_username(*user_id, &app_state.pool).await?;
+    let credentials = Credentials {
+        username,
+        password: form.current_password,
+    };
+    if let Err(e) = validate_credentials(credentials, &app_state.pool).await {
+        return match e {
+            AuthError::InvalidCredentials(_) => Ok(redirect_with_flash(
+                jar,
+                "The current password is incorrect.",
+            )),
+            AuthError::Unexpected(_) => Err(ChangePasswordError::Unexpected(e.into())),
+        };
+    }
+
+    authentication::change_password(*user_id, form.new_password, &app_state.pool).await?;
+
+    Ok(redirect_with_flash(jar, "Your password has been changed."))
+}
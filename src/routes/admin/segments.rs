@@ -0,0 +1,288 @@
+use anyhow::Context;
+use axum::{
+    extract::{Form, State},
+    http,
+    response::{Html, IntoResponse, Redirect},
+};
+use axum_extra::extract::cookie::SignedCookieJar;
+use chrono::{DateTime, Days, NaiveDate, Utc};
+
+use crate::{
+    authentication::UserId,
+    domain::{SegmentSlug, SubscriberTag},
+    problem::ValidationErrors,
+    segments::{create_segment, get_segments, NewSegment, Segment},
+    startup::AppState,
+    subscriber_attributes::{display_value, get_attribute_schema, AttributeSchema},
+    util::{error_chain_fmt, flash_cookie, take_flash_html},
+};
+
+/// Every filter is optional, empty fields are ignored
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    slug: String,
+    name: String,
+    #[serde(default)]
+    tags: String,
+    #[serde(default)]
+    excluded_tags: String,
+    /// `YYYY-MM-DD`, inclusive
+    #[serde(default)]
+    subscribed_from: String,
+    /// `YYYY-MM-DD`, inclusive
+    #[serde(default)]
+    subscribed_to: String,
+    #[serde(default)]
+    engaged_within_days: String,
+    /// One `key=value` per line
+    #[serde(default)]
+    attributes: String,
+}
+
+/// Attribute filters are checked against the admin-defined `schema`
+fn parse_new_segment(form: FormData, schema: &AttributeSchema) -> Result<NewSegment, String> {
+    let slug = SegmentSlug::parse(form.slug)?;
+    let name = form.name.trim().to_owned();
+    if name.is_empty() {
+        return Err("The segment needs a name.".into());
+    }
+    let subscribed_after = parse_date(&form.subscribed_from)?.map(start_of_day);
+    // The form's end date is inclusive, the stored bound is not
+    let subscribed_before = parse_date(&form.subscribed_to)?
+        .and_then(|date| date.checked_add_days(Days::new(1)))
+        .map(start_of_day);
+    let engaged_within_days = match form.engaged_within_days.trim() {
+        "" => None,
+        days => match days.parse::<i32>() {
+            Ok(days) if days > 0 => Some(days),
+            _ => return Err(format!("{days} is not a positive number of days.")),
+        },
+    };
+    let attributes = parse_attribute_filters(&form.attributes)?;
+    let attributes = schema
+        .validate_filter(attributes)
+        .map_err(|errors| ValidationErrors(errors).to_string())?;
+    Ok(NewSegment {
+        slug,
+        name,
+        tags: SubscriberTag::parse_list(&form.tags)?,
+        excluded_tags: SubscriberTag::parse_list(&form.excluded_tags)?,
+        subscribed_after,
+        subscribed_before,
+        engaged_within_days,
+        attributes,
+    })
+}
+
+/// `key=value` lines, blank lines are skipped
+fn parse_attribute_filters(s: &str) -> Result<Vec<(String, serde_json::Value)>, String> {
+    s.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("{} is not a key=value pair.", line.trim()))?;
+            Ok((key.trim().to_owned(), value.trim().into()))
+        })
+        .collect()
+}
+
+fn parse_date(s: &str) -> Result<Option<NaiveDate>, String> {
+    match s.trim() {
+        "" => Ok(None),
+        date => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map(Some)
+            .map_err(|_| format!("{date} is not a valid date.")),
+    }
+}
+
+fn start_of_day(date: NaiveDate) -> DateTime<Utc> {
+    let midnight = date.and_hms_opt(0, 0, 0).expect("Midnight is a valid time");
+    DateTime::from_utc(midnight, Utc)
+}
+
+/// A short summary of who is in a segment, for the admin table
+fn describe(segment: &Segment) -> String {
+    let mut filters = vec![];
+    if !segment.tags.is_empty() {
+        filters.push(format!("tagged {}", segment.tags.join(", ")));
+    }
+    if !segment.excluded_tags.is_empty() {
+        filters.push(format!("not tagged {}", segment.excluded_tags.join(", ")));
+    }
+    if let Some(after) = segment.subscribed_after {
+        filters.push(format!(
+            "subscribed on or after {}",
+            after.format("%Y-%m-%d")
+        ));
+    }
+    if let Some(before) = segment.subscribed_before {
+        filters.push(format!("subscribed before {}", before.format("%Y-%m-%d")));
+    }
+    if let Some(days) = segment.engaged_within_days {
+        filters.push(format!("engaged in the last {days} day(s)"));
+    }
+    if let Some(attributes) = segment.attributes.as_object() {
+        for (key, value) in attributes {
+            filters.push(format!("with {key}={}", display_value(value)));
+        }
+    }
+    if filters.is_empty() {
+        "everyone".into()
+    } else {
+        filters.join("; ")
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum SegmentsError {
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SegmentsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for SegmentsError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            SegmentsError::Unexpected(_) => http::StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "List segments",
+    skip(app_state, jar, user_id),
+    fields(user_id=%*user_id),
+    err(Debug)
+)]
+pub async fn segments(
+    State(app_state): State<AppState>,
+    jar: SignedCookieJar,
+    user_id: UserId,
+) -> Result<impl IntoResponse, SegmentsError> {
+    let segments = get_segments(&app_state.pool)
+        .await
+        .context("Failed to retrieve segments.")?;
+    let (jar, flash_html) = take_flash_html(jar);
+
+    let rows: String = segments
+        .iter()
+        .map(|s| {
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+                htmlescape::encode_minimal(&s.slug),
+                htmlescape::encode_minimal(&s.name),
+                htmlescape::encode_minimal(&describe(s)),
+            )
+        })
+        .collect();
+
+    Ok((
+        jar,
+        Html(format!(
+            r#"<!DOCTYPE html>
+      <html lang="en">
+        <head>
+          <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+          <title>Segments</title>
+        </head>
+        <body>
+          {flash_html}
+          <table>
+            <thead>
+              <tr><th>Slug</th><th>Name</th><th>Subscribers</th></tr>
+            </thead>
+            <tbody>
+              {rows}
+            </tbody>
+          </table>
+          <form action="/admin/segments" method="post">
+            <label
+              >Slug
+              <input type="text" placeholder="e.g. active-rustaceans" name="slug" />
+            </label>
+            <label
+              >Name
+              <input type="text" placeholder="e.g. Active Rustaceans" name="name" />
+            </label>
+            <br />
+            <label
+              >Tagged with all of
+              <input type="text" placeholder="e.g. rust, beta" name="tags" />
+            </label>
+            <label
+              >Tagged with none of
+              <input type="text" placeholder="e.g. churned" name="excluded_tags" />
+            </label>
+            <br />
+            <label
+              >Subscribed from
+              <input type="date" name="subscribed_from" />
+            </label>
+            <label
+              >to
+              <input type="date" name="subscribed_to" />
+            </label>
+            <br />
+            <label
+              >Engaged within the last
+              <input type="number" min="1" name="engaged_within_days" />
+              days
+            </label>
+            <br />
+            <label
+              >With attributes, one key=value per line<br />
+              <textarea name="attributes" rows="3" cols="40" placeholder="e.g. country=FR"></textarea>
+            </label>
+            <br />
+            <button type="submit">Create segment</button>
+          </form>
+          <p><a href="/admin/dashboard">&lt;- Back</a></p>
+        </body>
+      </html>
+      "#
+        )),
+    ))
+}
+
+#[tracing::instrument(
+    name = "Create a segment from the admin form",
+    skip(form, app_state, jar, user_id),
+    fields(user_id=%*user_id, slug=%form.slug),
+    err(Debug)
+)]
+pub async fn create_segment_from_form(
+    State(app_state): State<AppState>,
+    jar: SignedCookieJar,
+    user_id: UserId,
+    Form(form): Form<FormData>,
+) -> Result<impl IntoResponse, SegmentsError> {
+    let redirect_with_flash = |jar: SignedCookieJar, message: &str| {
+        (
+            jar.add(flash_cookie(message)),
+            Redirect::to("/admin/segments"),
+        )
+    };
+
+    let schema = get_attribute_schema(&app_state.pool)
+        .await
+        .context("Failed to load the subscriber attribute schema.")?;
+    let segment = match parse_new_segment(form, &schema) {
+        Ok(segment) => segment,
+        Err(e) => return Ok(redirect_with_flash(jar, &e)),
+    };
+
+    let message = match create_segment(&app_state.pool, &segment)
+        .await
+        .context("Failed to create a segment.")?
+    {
+        Some(_) => format!("The {} segment has been created.", segment.slug),
+        None => format!("There already is a {} segment.", segment.slug),
+    };
+    Ok(redirect_with_flash(jar, &message))
+}
@@ -0,0 +1,318 @@
+use anyhow::Context;
+use axum::{
+    extract::{Form, State},
+    http::{self, header},
+    response::{Html, IntoResponse, Redirect},
+};
+use axum_extra::extract::cookie::SignedCookieJar;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use crate::{
+    authentication::UserId,
+    domain::{SubscriberEmail, SubscriberTag, MAX_SUBSCRIBER_TAGS},
+    startup::AppState,
+    subscriber_attributes::{display_value, get_attribute_schema},
+    util::{flash_cookie, take_flash_html},
+};
+
+#[derive(serde::Deserialize)]
+pub struct TagsFormData {
+    email: String,
+    /// Comma separated, replaces every tag of the subscriber
+    tags: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum ListSubscribersError {
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ListSubscribersError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        crate::util::error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for ListSubscribersError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            ListSubscribersError::Unexpected(_) => {
+                http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "List subscribers",
+    skip(app_state, jar, user_id),
+    fields(user_id=%*user_id),
+    err(Debug)
+)]
+pub async fn list_subscribers(
+    State(app_state): State<AppState>,
+    jar: SignedCookieJar,
+    user_id: UserId,
+) -> Result<impl IntoResponse, ListSubscribersError> {
+    let subscribers = get_subscribers(&app_state.pool).await?;
+    let (jar, flash_html) = take_flash_html(jar);
+
+    let rows: String = subscribers
+        .iter()
+        .map(|s| {
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                htmlescape::encode_minimal(&s.email),
+                htmlescape::encode_minimal(&s.name),
+                htmlescape::encode_minimal(&s.list),
+                htmlescape::encode_minimal(&s.status),
+                s.subscribed_at.format("%Y-%m-%d %H:%M"),
+                htmlescape::encode_minimal(&s.tags.join(", ")),
+                htmlescape::encode_minimal(&describe_attributes(&s.attributes)),
+            )
+        })
+        .collect();
+
+    Ok((
+        jar,
+        Html(format!(
+            r#"<!DOCTYPE html>
+      <html lang="en">
+        <head>
+          <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+          <title>Subscribers</title>
+        </head>
+        <body>
+          {flash_html}
+          <p>{} subscription(s), <a href="/admin/subscribers/export">export as CSV</a></p>
+          <table>
+            <thead>
+              <tr><th>Email</th><th>Name</th><th>List</th><th>Status</th><th>Subscribed at</th><th>Tags</th><th>Attributes</th></tr>
+            </thead>
+            <tbody>
+              {rows}
+            </tbody>
+          </table>
+          <form action="/admin/subscribers/tags" method="post">
+            <label
+              >Email
+              <input type="email" name="email" />
+            </label>
+            <label
+              >Tags
+              <input type="text" placeholder="e.g. rust, beta" name="tags" />
+            </label>
+            <button type="submit">Set tags</button>
+          </form>
+          <p><a href="/admin/dashboard">&lt;- Back</a></p>
+        </body>
+      </html>
+      "#,
+            subscribers.len()
+        )),
+    ))
+}
+
+#[tracing::instrument(
+    name = "Set the tags of a subscriber",
+    skip(form, app_state, jar, user_id),
+    fields(user_id=%*user_id),
+    err(Debug)
+)]
+pub async fn set_subscriber_tags(
+    State(app_state): State<AppState>,
+    jar: SignedCookieJar,
+    user_id: UserId,
+    Form(form): Form<TagsFormData>,
+) -> Result<impl IntoResponse, ListSubscribersError> {
+    let redirect_with_flash = |jar: SignedCookieJar, message: &str| {
+        (
+            jar.add(flash_cookie(message)),
+            Redirect::to("/admin/subscribers"),
+        )
+    };
+
+    let email = match SubscriberEmail::parse(form.email) {
+        Ok(email) => email,
+        Err(e) => return Ok(redirect_with_flash(jar, &e)),
+    };
+    let tags = match SubscriberTag::parse_list(&form.tags) {
+        Ok(tags) => tags,
+        Err(e) => return Ok(redirect_with_flash(jar, &e)),
+    };
+    if tags.len() > MAX_SUBSCRIBER_TAGS {
+        let message = format!("A subscriber can have at most {MAX_SUBSCRIBER_TAGS} tags.");
+        return Ok(redirect_with_flash(jar, &message));
+    }
+
+    let updated = sqlx::query!(
+        r#"UPDATE subscriptions SET tags = $2 WHERE email = $1"#,
+        email.as_ref(),
+        &SubscriberTag::to_strings(&tags),
+    )
+    .execute(&app_state.pool)
+    .await
+    .context("Failed to update the tags of a subscriber.")?
+    .rows_affected()
+        == 1;
+
+    let message = if updated {
+        format!("The tags of {} have been updated.", email.as_ref())
+    } else {
+        format!("There is no subscriber with email {}.", email.as_ref())
+    };
+    Ok(redirect_with_flash(jar, &message))
+}
+
+struct SubscriberRow {
+    email: String,
+    name: String,
+    list: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+    tags: Vec<String>,
+    attributes: serde_json::Value,
+}
+
+/// `key=value` pairs, as typed in segment filters
+fn describe_attributes(attributes: &serde_json::Value) -> String {
+    let pairs: Vec<_> = attributes
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(key, value)| format!("{key}={}", display_value(value)))
+        .collect();
+    pairs.join(", ")
+}
+
+#[tracing::instrument(
+    name = "Export subscribers",
+    skip(app_state, user_id),
+    fields(user_id=%*user_id),
+    err(Debug)
+)]
+pub async fn export_subscribers(
+    State(app_state): State<AppState>,
+    user_id: UserId,
+) -> Result<impl IntoResponse, ListSubscribersError> {
+    let subscribers = get_subscribers(&app_state.pool).await?;
+    let schema = get_attribute_schema(&app_state.pool)
+        .await
+        .context("Failed to retrieve the subscriber attribute schema.")?;
+    let keys: Vec<&str> = schema
+        .definitions()
+        .iter()
+        .map(|d| d.key.as_str())
+        .collect();
+
+    // One column per attribute, after the fixed ones
+    let mut csv = csv_record(
+        ["email", "name", "list", "status", "subscribed_at", "tags"]
+            .into_iter()
+            .chain(keys.iter().copied())
+            .map(String::from),
+    );
+    for s in &subscribers {
+        let attributes = keys
+            .iter()
+            .map(|key| s.attributes.get(key).map(display_value).unwrap_or_default());
+        csv.push_str(&csv_record(
+            [
+                s.email.clone(),
+                s.name.clone(),
+                s.list.clone(),
+                s.status.clone(),
+                s.subscribed_at.to_rfc3339(),
+                s.tags.join(", "),
+            ]
+            .into_iter()
+            .chain(attributes),
+        ));
+    }
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                r#"attachment; filename="subscribers.csv""#,
+            ),
+        ],
+        csv,
+    ))
+}
+
+/// An RFC 4180 line, fields are quoted when they need to be
+///
+/// Fields a spreadsheet would read as a formula are prefixed with `'`, so
+/// that opening an export cannot run what a subscriber typed in
+fn csv_record(fields: impl IntoIterator<Item = String>) -> String {
+    let fields: Vec<_> = fields
+        .into_iter()
+        .map(|field| {
+            if field.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+                format!("'{}", field)
+            } else {
+                field
+            }
+        })
+        .map(|field| {
+            if field.contains([',', '"', '\r', '\n']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field
+            }
+        })
+        .collect();
+    format!("{}\r\n", fields.join(","))
+}
+
+#[tracing::instrument(name = "Get all subscribers", skip(pool), err(Debug))]
+async fn get_subscribers(pool: &PgPool) -> Result<Vec<SubscriberRow>, anyhow::Error> {
+    let subscribers = sqlx::query_as!(
+        SubscriberRow,
+        r#"
+        SELECT s.email, s.name, l.slug AS list, m.status, m.subscribed_at, s.tags, s.attributes
+        FROM list_memberships m
+        JOIN subscriptions s ON s.id = m.subscriber_id
+        JOIN lists l ON l.list_id = m.list_id
+        ORDER BY m.subscribed_at DESC
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve subscribers.")?;
+
+    Ok(subscribers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::csv_record;
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        let record = csv_record([
+            "plain".to_string(),
+            "rust, beta".into(),
+            r#"say "hi""#.into(),
+        ]);
+        assert_eq!(record, "plain,\"rust, beta\",\"say \"\"hi\"\"\"\r\n");
+    }
+
+    #[test]
+    fn csv_fields_are_never_read_as_formulas() {
+        let record = csv_record([
+            "=1+1".to_string(),
+            "+1".into(),
+            "-1".into(),
+            "@SUM(A1)".into(),
+            "\tx".into(),
+            "=A1,A2".into(),
+            "a=b".into(),
+        ]);
+        assert_eq!(record, "'=1+1,'+1,'-1,'@SUM(A1),'\tx,\"'=A1,A2\",a=b\r\n");
+    }
+}
@@ -0,0 +1,126 @@
+use axum::{
+    extract::{Path, State},
+    http,
+    response::{Html, IntoResponse},
+};
+use uuid::Uuid;
+
+use crate::{
+    email_client::{list_outbox_messages, read_outbox_message},
+    startup::AppState,
+};
+
+#[derive(thiserror::Error)]
+pub enum OutboxError {
+    #[error("The outbox listing is disabled")]
+    Disabled,
+    #[error("No such message in the outbox")]
+    NotFound,
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for OutboxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        crate::util::error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for OutboxError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            OutboxError::Disabled | OutboxError::NotFound => {
+                http::StatusCode::NOT_FOUND.into_response()
+            }
+            OutboxError::Unexpected(_) => http::StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+}
+
+/// List the emails written by the `outbox` email provider
+#[tracing::instrument(name = "List outbox", skip(app_state), err(Debug))]
+pub async fn outbox_listing(
+    State(app_state): State<AppState>,
+) -> Result<impl IntoResponse, OutboxError> {
+    let directory = app_state
+        .outbox_directory
+        .as_ref()
+        .ok_or(OutboxError::Disabled)?;
+    let messages = list_outbox_messages(directory).await?;
+
+    let rows: String = messages
+        .iter()
+        .map(|m| {
+            format!(
+                r#"<tr><td>{}</td><td>{}</td><td><a href="/dev/outbox/{}">{}</a></td></tr>"#,
+                m.created_at.format("%Y-%m-%d %H:%M:%S"),
+                htmlescape::encode_minimal(&m.to),
+                m.id,
+                htmlescape::encode_minimal(&m.subject),
+            )
+        })
+        .collect();
+
+    Ok(Html(format!(
+        r#"<!DOCTYPE html>
+      <html lang="en">
+        <head>
+          <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+          <title>Outbox</title>
+        </head>
+        <body>
+          <p>{} message(s) in the outbox</p>
+          <table>
+            <thead>
+              <tr><th>Sent at</th><th>To</th><th>Subject</th></tr>
+            </thead>
+            <tbody>
+              {rows}
+            </tbody>
+          </table>
+        </body>
+      </html>
+      "#,
+        messages.len()
+    )))
+}
+
+/// Show a single email of the outbox, links in its HTML body can be followed
+#[tracing::instrument(name = "Show outbox message", skip(app_state), err(Debug))]
+pub async fn outbox_message(
+    State(app_state): State<AppState>,
+    Path(message_id): Path<Uuid>,
+) -> Result<impl IntoResponse, OutboxError> {
+    let directory = app_state
+        .outbox_directory
+        .as_ref()
+        .ok_or(OutboxError::Disabled)?;
+    let message = read_outbox_message(directory, message_id)
+        .await?
+        .ok_or(OutboxError::NotFound)?;
+
+    Ok(Html(format!(
+        r#"<!DOCTYPE html>
+      <html lang="en">
+        <head>
+          <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+          <title>{subject}</title>
+        </head>
+        <body>
+          <p><a href="/dev/outbox">&lt;- Back to the outbox</a></p>
+          <p>From: {from}<br />To: {to}<br />Subject: {subject}</p>
+          <hr />
+          {html_content}
+          <hr />
+          <pre>{text_content}</pre>
+        </body>
+      </html>
+      "#,
+        subject = htmlescape::encode_minimal(&message.subject),
+        from = htmlescape::encode_minimal(&message.from),
+        to = htmlescape::encode_minimal(&message.to),
+        // Our own emails, rendered as they would be in a mail client
+        html_content = message.html_content,
+        text_content = htmlescape::encode_minimal(&message.text_content),
+    )))
+}
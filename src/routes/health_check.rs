@@ -1,6 +1,171 @@
-use axum::{http, response::IntoResponse};
+use std::{collections::HashSet, future::Future, time::Instant};
 
-#[tracing::instrument(name = "Health check")]
-pub async fn health_check() -> impl IntoResponse {
-    http::StatusCode::OK
+use axum::{extract::State, http, response::IntoResponse, Json};
+use sqlx::{migrate::Migrator, PgPool};
+
+use crate::{email_client::EmailSender, startup::AppState};
+
+/// The migrations this build expects to find applied
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// Liveness: always 200 while the process is able to answer,
+/// along with the state of the email provider's circuit breaker
+#[tracing::instrument(name = "Health check", skip(app_state))]
+pub async fn health_check(State(app_state): State<AppState>) -> impl IntoResponse {
+    Json(serde_json::json!({
+        "status": "ok",
+        "email_provider": {
+            "circuit_breaker": app_state.email_circuit_breaker.state(),
+        },
+    }))
+}
+
+#[derive(serde::Serialize)]
+struct ReadinessReport {
+    status: ReadinessStatus,
+    checks: ReadinessChecks,
+}
+
+#[derive(serde::Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum ReadinessStatus {
+    Ready,
+    NotReady,
+}
+
+#[derive(serde::Serialize)]
+struct ReadinessChecks {
+    database: CheckReport,
+    migrations: CheckReport,
+    email_provider: CheckReport,
+}
+
+#[derive(serde::Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum CheckStatus {
+    Ok,
+    Failing,
+    /// Not checked, e.g. the provider ping is disabled or not supported
+    Skipped,
+}
+
+/// Only whether the check passed and how long it took, why it failed
+/// is logged instead: database errors and migration versions are none
+/// of the caller's business
+#[derive(serde::Serialize)]
+struct CheckReport {
+    status: CheckStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_ms: Option<f64>,
+}
+
+impl CheckReport {
+    fn new(check: &'static str, outcome: Result<(), anyhow::Error>, started_at: Instant) -> Self {
+        let latency_ms = Some(started_at.elapsed().as_secs_f64() * 1000.0);
+        let status = match outcome {
+            Ok(()) => CheckStatus::Ok,
+            Err(e) => {
+                tracing::warn!(
+                    check,
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Readiness check failed."
+                );
+                CheckStatus::Failing
+            }
+        };
+        Self { status, latency_ms }
+    }
+
+    fn skipped() -> Self {
+        Self {
+            status: CheckStatus::Skipped,
+            latency_ms: None,
+        }
+    }
+}
+
+/// Readiness: 200 when this instance can serve traffic, 503 otherwise
+///
+/// The email provider is reported but doesn't make the instance unready,
+/// every instance shares it so taking them all out of rotation wouldn't help
+#[tracing::instrument(name = "Readiness check", skip(app_state))]
+pub async fn health_ready(State(app_state): State<AppState>) -> impl IntoResponse {
+    let database = timed("database", ping_database(&app_state.pool)).await;
+    let migrations = timed("migrations", check_migrations(&app_state.pool)).await;
+    let email_provider = if app_state.ping_email_provider_on_readiness {
+        ping_email_provider(app_state.email_client.as_ref()).await
+    } else {
+        CheckReport::skipped()
+    };
+
+    let status = if database.status == CheckStatus::Ok && migrations.status == CheckStatus::Ok {
+        ReadinessStatus::Ready
+    } else {
+        ReadinessStatus::NotReady
+    };
+    let status_code = match status {
+        ReadinessStatus::Ready => http::StatusCode::OK,
+        ReadinessStatus::NotReady => http::StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    let report = ReadinessReport {
+        status,
+        checks: ReadinessChecks {
+            database,
+            migrations,
+            email_provider,
+        },
+    };
+    (status_code, Json(report))
+}
+
+/// Run a check, timing how long it took
+async fn timed(
+    check: &'static str,
+    outcome: impl Future<Output = Result<(), anyhow::Error>>,
+) -> CheckReport {
+    let started_at = Instant::now();
+    let outcome = outcome.await;
+    CheckReport::new(check, outcome, started_at)
+}
+
+async fn ping_database(pool: &PgPool) -> Result<(), anyhow::Error> {
+    sqlx::query!("SELECT 1 AS ping").fetch_one(pool).await?;
+    Ok(())
+}
+
+/// Serving requests against an older schema would fail in confusing ways
+async fn check_migrations(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let applied: HashSet<i64> =
+        sqlx::query_scalar!("SELECT version FROM _sqlx_migrations WHERE success")
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .collect();
+    let pending: Vec<_> = MIGRATOR
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version))
+        .map(|migration| migration.version.to_string())
+        .collect();
+
+    if pending.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "Pending migrations: {}",
+            pending.join(", ")
+        ))
+    }
+}
+
+async fn ping_email_provider(email_client: &dyn EmailSender) -> CheckReport {
+    let started_at = Instant::now();
+    match email_client.ping().await {
+        Some(outcome) => {
+            CheckReport::new("email_provider", outcome.map_err(Into::into), started_at)
+        }
+        // Nothing cheap to ask this provider
+        None => CheckReport::skipped(),
+    }
 }
@@ -1,15 +1,14 @@
 use axum::response::{Html, IntoResponse};
-use axum_extra::extract::cookie::{Cookie, SignedCookieJar};
+use axum_extra::extract::cookie::SignedCookieJar;
+
+use crate::util::take_flash_html;
 
 #[tracing::instrument(name = "Login form", skip(jar))]
 pub async fn login_form(jar: SignedCookieJar) -> impl IntoResponse {
-    let error_html = match jar.get("_flash") {
-        None => "".into(),
-        Some(cookie) => format!("<p><i>{}</i></p>", cookie.value()),
-    };
+    let (jar, error_html) = take_flash_html(jar);
 
     (
-        jar.remove(Cookie::named("_flash")),
+        jar,
         Html(format!(
             r#"<!DOCTYPE html>
       <html lang="en">
@@ -1,15 +1,18 @@
+use anyhow::Context;
 use axum::{
     extract::{Form, State},
     http,
     response::{IntoResponse, Redirect},
 };
-use axum_extra::extract::cookie::{Cookie, SignedCookieJar};
+use axum_extra::extract::cookie::SignedCookieJar;
 use secrecy::Secret;
 
 use crate::{
     authentication::{validate_credentials, AuthError, Credentials},
+    problem::Problem,
+    session_state::{renew_session, session_cookie, session_id_from_jar},
     startup::AppState,
-    util::error_chain_fmt,
+    util::{error_chain_fmt, flash_cookie},
 };
 
 #[derive(serde::Deserialize)]
@@ -35,9 +38,14 @@ impl std::fmt::Debug for LoginError {
 impl IntoResponse for LoginError {
     fn into_response(self) -> axum::response::Response {
         match self {
-            LoginError::Unexpected(_) => http::StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            LoginError::Unexpected(_) => Problem::internal_error().into_response(),
             // ! Not sure we even need this or should be impl IntoResponse
-            LoginError::Auth(_) => http::StatusCode::UNAUTHORIZED.into_response(),
+            LoginError::Auth(_) => Problem::new(
+                http::StatusCode::UNAUTHORIZED,
+                "invalid_credentials",
+                "Invalid username or password.",
+            )
+            .into_response(),
         }
     }
 }
@@ -57,24 +65,35 @@ pub async fn login(
         username: form.username,
         password: form.password,
     };
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
 
-    match validate_credentials(credentials, &app_state.pool).await {
+    let outcome = match validate_credentials(credentials, &app_state.pool).await {
         Ok(user_id) => {
-            tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
 
-            Ok(Redirect::to("/"))
+            // Always hand out a brand new session id on login
+            renew_session(&app_state.pool, session_id_from_jar(&jar), user_id)
+                .await
+                .context("Failed to create a new session.")
+                .map_err(LoginError::Unexpected)
         }
-        Err(e) => {
-            let e = match e {
-                AuthError::InvalidCredentials(_) => LoginError::Auth(e.into()),
-                AuthError::Unexpected(_) => LoginError::Unexpected(e.into()),
-            };
+        Err(e) => Err(match e {
+            AuthError::InvalidCredentials(_) => LoginError::Auth(e.into()),
+            AuthError::Unexpected(_) => LoginError::Unexpected(e.into()),
+        }),
+    };
 
-            Err((
-                jar.add(Cookie::new("_flash", e.to_string())),
-                Redirect::to("/login"),
-            ))
-        }
+    match &outcome {
+        Ok(_) => app_state.metrics.login_succeeded(),
+        Err(LoginError::Auth(_)) => app_state.metrics.login_failed(),
+        Err(LoginError::Unexpected(_)) => {}
+    }
+
+    match outcome {
+        Ok(session_id) => Ok((
+            jar.add(session_cookie(session_id)),
+            Redirect::to("/admin/dashboard"),
+        )),
+        Err(e) => Err((jar.add(flash_cookie(e.to_string())), Redirect::to("/login"))),
     }
 }
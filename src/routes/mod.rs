@@ -1,13 +1,21 @@
+mod admin;
+mod dev_outbox;
 mod health_check;
 mod home;
 mod login;
 mod newsletters;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_resend_confirmation;
+mod subscriptions_unsubscribe;
 
-pub use health_check::health_check;
+pub use admin::*;
+pub use dev_outbox::{outbox_listing, outbox_message};
+pub use health_check::{health_check, health_ready};
 pub use home::home;
 pub use login::{login, login_form};
 pub use newsletters::publish_newsletter;
 pub use subscriptions::{subscribe, FormData};
 pub use subscriptions_confirm::confirm;
+pub use subscriptions_resend_confirmation::resend_confirmation;
+pub use subscriptions_unsubscribe::{unsubscribe, unsubscribe_form};
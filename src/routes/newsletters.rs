@@ -2,14 +2,19 @@ use anyhow::Context;
 use axum::{
     extract::{Json, State},
     http::{self, header},
-    response::IntoResponse,
+    response::{IntoResponse, Response},
 };
 use secrecy::Secret;
-use sqlx::PgPool;
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
 
 use crate::{
     authentication::{validate_credentials, AuthError, Credentials},
-    domain::SubscriberEmail,
+    domain::{ListSlug, SegmentSlug},
+    idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+    mailing_lists::find_list,
+    problem::{FieldError, Problem, ValidationErrors},
+    segments::find_segment,
     startup::AppState,
 };
 
@@ -17,6 +22,12 @@ use crate::{
 pub struct BodyData {
     title: String,
     content: Content,
+    /// Slug of the list to publish to, the default list if missing
+    #[serde(default)]
+    list: Option<String>,
+    /// Slug of a saved segment, to only send to part of the list
+    #[serde(default)]
+    segment: Option<String>,
 }
 
 #[derive(serde::Deserialize)]
@@ -27,6 +38,10 @@ pub struct Content {
 
 #[derive(thiserror::Error)]
 pub enum PublishError {
+    #[error("{0}")]
+    Validation(ValidationErrors),
+    #[error("{0}")]
+    InvalidIdempotencyKey(String),
     #[error("Authentication failed.")]
     Auth(#[source] anyhow::Error),
     #[error(transparent)]
@@ -42,7 +57,14 @@ impl std::fmt::Debug for PublishError {
 impl IntoResponse for PublishError {
     fn into_response(self) -> axum::response::Response {
         match self {
-            PublishError::Unexpected(_) => http::StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            PublishError::Validation(errors) => Problem::validation(errors).into_response(),
+            PublishError::InvalidIdempotencyKey(detail) => Problem::new(
+                http::StatusCode::BAD_REQUEST,
+                "invalid_idempotency_key",
+                detail,
+            )
+            .into_response(),
+            PublishError::Unexpected(_) => Problem::internal_error().into_response(),
             PublishError::Auth(_) => {
                 let mut headers = header::HeaderMap::new();
 
@@ -51,7 +73,12 @@ impl IntoResponse for PublishError {
 
                 headers.insert(header::WWW_AUTHENTICATE, header_value);
 
-                (http::StatusCode::UNAUTHORIZED, headers).into_response()
+                let problem = Problem::new(
+                    http::StatusCode::UNAUTHORIZED,
+                    "invalid_credentials",
+                    "Valid basic authentication credentials are required.",
+                );
+                (headers, problem).into_response()
             }
         }
     }
@@ -67,9 +94,9 @@ pub async fn publish_newsletter(
     headers: header::HeaderMap,
     State(app_state): State<AppState>,
     Json(body): Json<BodyData>,
-) -> Result<impl IntoResponse, PublishError> {
+) -> Result<Response, PublishError> {
     let credentials = basic_authentication(&headers).map_err(PublishError::Auth)?;
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
 
     let user_id = validate_credentials(credentials, &app_state.pool)
         .await
@@ -77,42 +104,221 @@ pub async fn publish_newsletter(
             AuthError::InvalidCredentials(_) => PublishError::Auth(e.into()),
             AuthError::Unexpected(_) => PublishError::Unexpected(e.into()),
         })?;
-    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
-
-    let subscribers = get_confirmed_subscribers(&app_state.pool).await?;
-    for subscriber in subscribers {
-        match subscriber {
-            Ok(subscriber) => {
-                app_state
-                    .email_client
-                    .send_email(
-                        &subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
-                    )
-                    .await
-                    .context(format!(
-                        "Failed to send newsletter issue to {}",
-                        subscriber.email
-                    ))?;
-                // ! Not working with `with_context` because `()` doesn't implement `Display`
-                // .with_context(|| {
-                //     format!("Failed to send newsletter issue to {}", subscriber.email);
-                // })?;
-            }
-            Err(error) => {
-                tracing::warn!(
-                  // Record the error chain as a structured field
-                  // on the log record
-                  error.cause_chain = ?error,
-                  "Skipping a confirmed subscriber. \
-                  Their stored contact details are invalid."
-                )
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    // Clients opt into safe retries by sending an `Idempotency-Key`
+    let idempotency_key = idempotency_key(&headers).map_err(PublishError::InvalidIdempotencyKey)?;
+    let list = body
+        .list
+        .map_or_else(|| Ok(ListSlug::default()), ListSlug::parse)
+        .map_err(|e| {
+            PublishError::Validation(ValidationErrors(vec![FieldError::new(
+                "list",
+                "invalid_list",
+                e,
+            )]))
+        })?;
+    let list = find_list(&app_state.pool, &list)
+        .await
+        .context("Failed to look up the mailing list.")?
+        .ok_or_else(|| {
+            PublishError::Validation(ValidationErrors(vec![FieldError::new(
+                "list",
+                "unknown_list",
+                format!("There is no {list} list."),
+            )]))
+        })?;
+    let segment_id = match body.segment {
+        None => None,
+        Some(segment) => {
+            let segment = SegmentSlug::parse(segment).map_err(|e| {
+                PublishError::Validation(ValidationErrors(vec![FieldError::new(
+                    "segment",
+                    "invalid_segment",
+                    e,
+                )]))
+            })?;
+            let found = find_segment(&app_state.pool, &segment)
+                .await
+                .context("Failed to look up the segment.")?
+                .ok_or_else(|| {
+                    PublishError::Validation(ValidationErrors(vec![FieldError::new(
+                        "segment",
+                        "unknown_segment",
+                        format!("There is no {segment} segment."),
+                    )]))
+                })?;
+            Some(found.segment_id)
+        }
+    };
+
+    let (mut transaction, idempotency_key) = match idempotency_key {
+        Some(idempotency_key) => {
+            match try_processing(&app_state.pool, &idempotency_key, user_id).await? {
+                NextAction::StartProcessing(transaction) => (transaction, Some(idempotency_key)),
+                NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
             }
         }
+        None => (
+            app_state
+                .pool
+                .begin()
+                .await
+                .context("Failed to acquire a Postgres connection from the pool.")?,
+            None,
+        ),
+    };
+
+    enqueue_newsletter_issue(
+        &mut transaction,
+        list.list_id,
+        segment_id,
+        &body.title,
+        &body.content.html,
+        &body.content.text,
+    )
+    .await?;
+
+    // The issue and its delivery tasks are committed together with the saved response
+    let response = http::StatusCode::OK.into_response();
+    match idempotency_key {
+        Some(idempotency_key) => {
+            Ok(save_response(transaction, &idempotency_key, user_id, response).await?)
+        }
+        None => {
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit the newsletter issue.")?;
+            Ok(response)
+        }
     }
-    Ok(http::StatusCode::OK)
+}
+
+/// Parse the optional `Idempotency-Key` header
+fn idempotency_key(headers: &header::HeaderMap) -> Result<Option<IdempotencyKey>, String> {
+    headers
+        .get("Idempotency-Key")
+        .map(|value| {
+            value
+                .to_str()
+                .map_err(|_| {
+                    "The 'Idempotency-Key' header was not a valid UTF8 string.".to_string()
+                })
+                .and_then(|value| IdempotencyKey::try_from(value.to_string()))
+        })
+        .transpose()
+}
+
+/// Persist a newsletter issue and queue one delivery per confirmed member of the list,
+/// or only to those in the segment when there is one
+///
+/// Emails are sent in the background by the issue delivery worker
+#[tracing::instrument(
+    name = "Enqueue a newsletter issue for delivery",
+    skip(transaction, html_content, text_content),
+    err(Debug)
+)]
+pub(crate) async fn enqueue_newsletter_issue(
+    transaction: &mut Transaction<'static, Postgres>,
+    list_id: Uuid,
+    segment_id: Option<Uuid>,
+    title: &str,
+    html_content: &str,
+    text_content: &str,
+) -> Result<Uuid, anyhow::Error> {
+    let newsletter_issue_id = insert_newsletter_issue(
+        transaction,
+        list_id,
+        segment_id,
+        title,
+        text_content,
+        html_content,
+    )
+    .await
+    .context("Failed to store newsletter issue details.")?;
+    enqueue_delivery_tasks(transaction, newsletter_issue_id)
+        .await
+        .context("Failed to enqueue delivery tasks.")?;
+
+    Ok(newsletter_issue_id)
+}
+
+#[tracing::instrument(skip_all)]
+async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'static, Postgres>,
+    list_id: Uuid,
+    segment_id: Option<Uuid>,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+          newsletter_issue_id,
+          list_id,
+          segment_id,
+          title,
+          text_content,
+          html_content,
+          published_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, now())
+        "#,
+        newsletter_issue_id,
+        list_id,
+        segment_id,
+        title,
+        text_content,
+        html_content
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(newsletter_issue_id)
+}
+
+#[tracing::instrument(skip_all)]
+async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'static, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (
+          newsletter_issue_id,
+          subscriber_email
+        )
+        SELECT i.newsletter_issue_id, s.email
+        FROM newsletter_issues i
+        JOIN list_memberships m ON m.list_id = i.list_id
+        JOIN subscriptions s ON s.id = m.subscriber_id
+        LEFT JOIN segments g ON g.segment_id = i.segment_id
+        WHERE
+          i.newsletter_issue_id = $1 AND
+          m.status = 'confirmed' AND
+          (
+            g.segment_id IS NULL OR (
+              s.tags @> g.tags AND
+              NOT s.tags && g.excluded_tags AND
+              s.attributes @> g.attributes AND
+              (g.subscribed_after IS NULL OR m.subscribed_at >= g.subscribed_after) AND
+              (g.subscribed_before IS NULL OR m.subscribed_at < g.subscribed_before) AND
+              (
+                g.engaged_within_days IS NULL OR
+                s.last_engaged_at >= now() - make_interval(days => g.engaged_within_days)
+              )
+            )
+          )
+        "#,
+        newsletter_issue_id,
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(())
 }
 
 fn basic_authentication(headers: &header::HeaderMap) -> Result<Credentials, anyhow::Error> {
@@ -146,30 +352,3 @@ fn basic_authentication(headers: &header::HeaderMap) -> Result<Credentials, anyh
         password: Secret::new(password),
     })
 }
-
-struct ConfirmedSubscriber {
-    email: SubscriberEmail,
-}
-
-#[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
-async fn get_confirmed_subscribers(
-    pool: &PgPool,
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
-    let confirmed_subscribers = sqlx::query!(
-        r#"
-      SELECT email
-      FROM subscriptions
-      WHERE status = 'confirmed'
-    "#,
-    )
-    .fetch_all(pool)
-    .await?
-    .into_iter()
-    .map(|r| match SubscriberEmail::parse(r.email) {
-        Ok(email) => Ok(ConfirmedSubscriber { email }),
-        Err(error) => Err(anyhow::anyhow!(error)),
-    })
-    .collect();
-
-    Ok(confirmed_subscribers)
-}
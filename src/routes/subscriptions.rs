@@ -1,40 +1,213 @@
+use std::collections::HashMap;
+
 use anyhow::Context;
 use axum::{
-    extract::{Form, State},
-    http,
-    response::IntoResponse,
+    async_trait,
+    body::HttpBody,
+    extract::{Form, FromRequest, Json, State},
+    http::{self, header, Request},
+    response::{IntoResponse, Response},
+    BoxError,
 };
-use chrono::Utc;
+use chrono::{Duration, Utc};
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use serde_json::{Map, Value};
 use sqlx::{Postgres, Transaction};
 use uuid::Uuid;
 
 use crate::{
-    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
-    email_client::EmailClient,
+    domain::{
+        ListSlug, NewSubscriber, SubscriberEmail, SubscriberName, SubscriberTag,
+        MAX_SUBSCRIBER_TAGS,
+    },
+    email_client::{EmailSender, SendEmailError},
+    mailing_lists::find_list,
+    problem::{FieldError, Problem, ValidationErrors},
     startup::AppState,
+    subscriber_attributes::{get_attribute_schema, AttributeSchema},
 };
 
+/// How long a confirmation link stays valid after being sent
+pub const SUBSCRIPTION_TOKEN_TTL_HOURS: i64 = 48;
+
 #[derive(serde::Deserialize)]
 pub struct FormData {
     email: String,
     name: String,
+    /// Slug of the list to join, the default list if missing
+    #[serde(default)]
+    list: Option<String>,
+    #[serde(default)]
+    tags: Option<TagsField>,
+    /// Custom attributes, JSON clients send them as an object
+    #[serde(default)]
+    attributes: Option<Map<String, Value>>,
+    /// Forms send one `attributes[<key>]` field per attribute
+    #[serde(flatten)]
+    other_fields: HashMap<String, Value>,
+}
+
+impl FormData {
+    /// The attributes sent either way
+    fn attributes(&mut self) -> impl Iterator<Item = (String, Value)> {
+        let from_form = std::mem::take(&mut self.other_fields)
+            .into_iter()
+            .filter_map(|(field, value)| {
+                let key = field.strip_prefix("attributes[")?.strip_suffix(']')?;
+                Some((key.to_owned(), value))
+            });
+        self.attributes
+            .take()
+            .unwrap_or_default()
+            .into_iter()
+            .chain(from_form)
+    }
+}
+
+/// Forms send tags comma separated, JSON clients may send an array
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum TagsField {
+    Many(Vec<String>),
+    Joined(String),
+}
+
+impl TagsField {
+    fn parse(self) -> Result<Vec<SubscriberTag>, String> {
+        let tags = match self {
+            TagsField::Many(tags) => SubscriberTag::parse_all(tags.iter().map(String::as_str))?,
+            TagsField::Joined(tags) => SubscriberTag::parse_list(&tags)?,
+        };
+        if tags.len() > MAX_SUBSCRIBER_TAGS {
+            return Err(format!("At most {MAX_SUBSCRIBER_TAGS} tags can be picked."));
+        }
+        Ok(tags)
+    }
+}
+
+/// A request body, read as JSON or as a form depending on its `Content-Type`
+///
+/// Anything but JSON is read as a form, which is what browsers send.
+/// Bodies that can't be read are rejected with an `invalid_body` problem
+pub struct FormOrJson<T>(pub T);
+
+#[async_trait]
+impl<S, B, T> FromRequest<S, B> for FormOrJson<T>
+where
+    T: serde::de::DeserializeOwned,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(request: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let rejection = if has_json_content_type(&request) {
+            match Json::<T>::from_request(request, state).await {
+                Ok(Json(value)) => return Ok(Self(value)),
+                Err(rejection) => rejection.into_response(),
+            }
+        } else {
+            match Form::<T>::from_request(request, state).await {
+                Ok(Form(value)) => return Ok(Self(value)),
+                Err(rejection) => rejection.into_response(),
+            }
+        };
+        Err(invalid_body(rejection).await)
+    }
+}
+
+/// Keeps the status and the message axum picked, e.g. a 422 saying which
+/// field is missing from the JSON
+async fn invalid_body(rejection: Response) -> Response {
+    let status = rejection.status();
+    let detail = hyper::body::to_bytes(rejection.into_body())
+        .await
+        .map(|body| String::from_utf8_lossy(&body).into_owned())
+        .unwrap_or_default();
+    Problem::new(status, "invalid_body", detail).into_response()
+}
+
+fn has_json_content_type<B>(request: &Request<B>) -> bool {
+    request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<mime::Mime>().ok())
+        .is_some_and(|mime| {
+            mime.type_() == "application"
+                && (mime.subtype() == "json" || mime.suffix().is_some_and(|s| s == "json"))
+        })
+}
+
+/// Whether the client asked for a JSON answer rather than a bare status code
+fn accepts_json(headers: &header::HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|value| value.trim().parse::<mime::Mime>().ok())
+        .any(|mime| mime.type_() == "application" && mime.subtype() == "json")
 }
 
-impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+/// What JSON clients get back on success
+///
+/// The same whatever the status of the address, so it can't be used
+/// to find out who is on the list
+fn subscribe_response(headers: &header::HeaderMap) -> Response {
+    if accepts_json(headers) {
+        Json(serde_json::json!({
+            "status": "accepted",
+            "detail": "Check your inbox to confirm your subscription.",
+        }))
+        .into_response()
+    } else {
+        http::StatusCode::OK.into_response()
+    }
+}
 
-    fn try_from(form: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(form.name)?;
-        let email = SubscriberEmail::parse(form.email)?;
-        Ok(Self { email, name })
+/// Check every field of the request, attributes against the admin-defined `schema`
+fn parse_new_subscriber(
+    mut form: FormData,
+    schema: &AttributeSchema,
+) -> Result<NewSubscriber, ValidationErrors> {
+    let attributes = schema.validate(form.attributes());
+    let name =
+        SubscriberName::parse(form.name).map_err(|e| FieldError::new("name", "invalid_name", e));
+    let email = SubscriberEmail::parse(form.email)
+        .map_err(|e| FieldError::new("email", "invalid_email", e));
+    let list = form
+        .list
+        .map_or_else(|| Ok(ListSlug::default()), ListSlug::parse)
+        .map_err(|e| FieldError::new("list", "invalid_list", e));
+    let tags = form
+        .tags
+        .map_or_else(|| Ok(vec![]), TagsField::parse)
+        .map_err(|e| FieldError::new("tags", "invalid_tags", e));
+    match (name, email, list, tags, attributes) {
+        (Ok(name), Ok(email), Ok(list), Ok(tags), Ok(attributes)) => Ok(NewSubscriber {
+            email,
+            name,
+            list,
+            tags,
+            attributes,
+        }),
+        (name, email, list, tags, attributes) => Err(ValidationErrors(
+            [name.err(), email.err(), list.err(), tags.err()]
+                .into_iter()
+                .flatten()
+                .chain(attributes.err().into_iter().flatten())
+                .collect(),
+        )),
     }
 }
 
 #[derive(thiserror::Error)]
 pub enum SubscribeError {
     #[error("{0}")]
-    Validation(String),
+    Validation(ValidationErrors),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -48,17 +221,15 @@ impl std::fmt::Debug for SubscribeError {
 impl IntoResponse for SubscribeError {
     fn into_response(self) -> axum::response::Response {
         match self {
-            SubscribeError::Validation(_) => http::StatusCode::BAD_REQUEST.into_response(),
-            SubscribeError::UnexpectedError(_) => {
-                http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
-            }
+            SubscribeError::Validation(errors) => Problem::validation(errors).into_response(),
+            SubscribeError::UnexpectedError(_) => Problem::internal_error().into_response(),
         }
     }
 }
 
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, app_state),
+    skip(headers, form, app_state),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name,
@@ -66,56 +237,147 @@ impl IntoResponse for SubscribeError {
     err(Debug),
 )]
 pub async fn subscribe(
+    headers: header::HeaderMap,
     State(app_state): State<AppState>,
-    Form(form): Form<FormData>,
-) -> Result<impl IntoResponse, SubscribeError> {
-    let new_subscriber = form.try_into().map_err(SubscribeError::Validation)?;
+    FormOrJson(form): FormOrJson<FormData>,
+) -> Result<Response, SubscribeError> {
+    let schema = get_attribute_schema(&app_state.pool)
+        .await
+        .context("Failed to load the subscriber attribute schema.")?;
+    let new_subscriber = parse_new_subscriber(form, &schema).map_err(SubscribeError::Validation)?;
     let mut transaction = app_state
         .pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool.")?;
 
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+    let list = find_list(&mut transaction, &new_subscriber.list)
         .await
-        .context("Failed to insert new subscriber in the database.")?;
-    let subscription_token = generate_subscription_token();
+        .context("Failed to look up the mailing list.")?
+        .ok_or_else(|| unknown_list(&new_subscriber.list))?;
 
-    store_token(&mut transaction, subscriber_id, &subscription_token)
+    let subscriber_id = match insert_subscriber(&mut transaction, &new_subscriber)
+        .await
+        .context("Failed to insert new subscriber in the database.")?
+    {
+        Some(subscriber_id) => subscriber_id,
+        None => get_existing_subscriber_id(&mut transaction, &new_subscriber.email)
+            .await
+            .context("Failed to fetch an existing subscriber.")?,
+    };
+    let created = insert_membership(&mut transaction, list.list_id, subscriber_id)
         .await
-        .context("Failed to commit SQL transaction to store a new subscriber.")?;
+        .context("Failed to add the subscriber to the mailing list.")?;
+    if !created {
+        // Someone already subscribed to this list with this address. Whatever
+        // their status the caller gets the same answer, so it can't be used
+        // to find out who is on the list
+        let status = get_membership_status(&mut transaction, list.list_id, subscriber_id)
+            .await
+            .context("Failed to fetch the status of an existing subscriber.")?;
+        match status.as_str() {
+            "confirmed" => return Ok(subscribe_response(&headers)),
+            "unsubscribed" => restart_double_opt_in(&mut transaction, list.list_id, subscriber_id)
+                .await
+                .context("Failed to restart the double opt-in of a subscriber.")?,
+            _ => {}
+        }
+        delete_tokens(&mut transaction, subscriber_id, list.list_id)
+            .await
+            .context("Failed to delete previous subscription tokens.")?;
+    }
+    let subscription_token = generate_subscription_token();
+
+    // Anyone can sign up with any address, so nothing sent here is applied
+    // to the subscriber before they confirm
+    let pending = PendingSignup {
+        name: Some(new_subscriber.name.as_ref().to_owned()),
+        tags: SubscriberTag::to_strings(&new_subscriber.tags),
+        attributes: new_subscriber.attributes.to_json(),
+    };
+    store_token(
+        &mut transaction,
+        subscriber_id,
+        list.list_id,
+        &subscription_token,
+        &pending,
+    )
+    .await
+    .context("Failed to commit SQL transaction to store a new subscriber.")?;
     transaction
         .commit()
         .await
         .context("Failed to store the confirmation token for a new subscriber.")?;
+    if created {
+        app_state.metrics.subscription_created();
+    }
 
     send_confirmation_email(
-        &app_state.email_client,
-        new_subscriber,
+        app_state.email_client.as_ref(),
+        &new_subscriber.email,
+        &list.name,
         &app_state.application_base_url,
         &subscription_token,
     )
     .await
     .context("Failed to send a confirmation email.")?;
 
-    Ok(http::StatusCode::OK)
+    Ok(subscribe_response(&headers))
+}
+
+/// Asking for a list that doesn't exist is a mistake of the caller
+pub(crate) fn unknown_list(slug: &ListSlug) -> SubscribeError {
+    SubscribeError::Validation(ValidationErrors(vec![FieldError::new(
+        "list",
+        "unknown_list",
+        format!("There is no {slug} list."),
+    )]))
+}
+
+/// What a subscriber sent at signup, kept with their token until they confirm
+#[derive(Debug)]
+pub struct PendingSignup {
+    /// Replaces the name of the subscriber, `None` for tokens sent before names were kept
+    pub name: Option<String>,
+    pub tags: Vec<String>,
+    /// A JSON object, as stored in JSONB columns
+    pub attributes: Value,
+}
+
+impl Default for PendingSignup {
+    fn default() -> Self {
+        Self {
+            name: None,
+            tags: vec![],
+            attributes: Value::Object(Map::new()),
+        }
+    }
 }
 
 #[tracing::instrument(
     name = "Store subscription token in the database",
-    skip(subscription_token, transaction),
+    skip(subscription_token, transaction, pending),
     err(Debug)
 )]
 pub async fn store_token(
     transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
+    list_id: Uuid,
     subscription_token: &str,
+    pending: &PendingSignup,
 ) -> Result<(), StoreTokenError> {
+    let now = Utc::now();
     sqlx::query!(
-        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id)
-        VALUES ($1, $2)"#,
+        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id, list_id, created_at, expires_at, name, tags, attributes)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"#,
         subscription_token,
         subscriber_id,
+        list_id,
+        now,
+        now + Duration::hours(SUBSCRIPTION_TOKEN_TTL_HOURS),
+        pending.name,
+        &pending.tags,
+        pending.attributes,
     )
     .execute(transaction)
     .await
@@ -135,35 +397,36 @@ impl std::fmt::Debug for StoreTokenError {
 
 #[tracing::instrument(
     name = "Send a confirmation email to a new subscriber",
-    skip(email_client, new_subscriber, application_base_url, subscription_token),
+    skip(email_client, recipient, application_base_url, subscription_token),
     err(Debug)
 )]
 pub async fn send_confirmation_email(
-    email_client: &EmailClient,
-    new_subscriber: NewSubscriber,
+    email_client: &dyn EmailSender,
+    recipient: &SubscriberEmail,
+    list_name: &str,
     application_base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), SendEmailError> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         application_base_url, subscription_token
     );
-    let html_content = format!("Welcome to our newsletter!<br />Click <a href=\"{}\">here</a> to confirm your subscription.", confirmation_link);
-    let text_content = format!(
-        "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
+    let html_content = format!(
+        "Welcome to {}!<br />Click <a href=\"{}\">here</a> to confirm your subscription.",
+        htmlescape::encode_minimal(list_name),
         confirmation_link
     );
+    let text_content = format!(
+        "Welcome to {}!\nVisit {} to confirm your subscription.",
+        list_name, confirmation_link
+    );
 
     email_client
-        .send_email(
-            &new_subscriber.email,
-            "Welcome!",
-            &html_content,
-            &text_content,
-        )
+        .send_email(recipient, "Welcome!", &html_content, &text_content)
         .await
 }
 
+/// Returns `None` if someone already subscribed with this email address
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
     skip(new_subscriber, transaction)
@@ -171,12 +434,13 @@ pub async fn send_confirmation_email(
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
+) -> Result<Option<Uuid>, sqlx::Error> {
     let subscriber_id = Uuid::new_v4();
-    sqlx::query!(
+    let inserted = sqlx::query!(
         r#"
-            INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-            VALUES ($1, $2, $3, $4, 'pending_confirmation')
+            INSERT INTO subscriptions (id, email, name, subscribed_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (email) DO NOTHING
         "#,
         subscriber_id,
         new_subscriber.email.as_ref(),
@@ -184,12 +448,132 @@ pub async fn insert_subscriber(
         Utc::now()
     )
     .execute(transaction)
+    .await?
+    .rows_affected()
+        == 1;
+    Ok(inserted.then_some(subscriber_id))
+}
+
+/// Lock the subscriber row so concurrent subscribe calls don't race each other
+#[tracing::instrument(name = "Get existing subscriber", skip_all, err(Debug))]
+async fn get_existing_subscriber_id(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<Uuid, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT id FROM subscriptions WHERE email = $1 FOR UPDATE"#,
+        email.as_ref()
+    )
+    .fetch_one(transaction)
+    .await
+}
+
+/// Returns `false` if the subscriber already is on the list, whatever their status
+#[tracing::instrument(name = "Add subscriber to a mailing list", skip(transaction))]
+async fn insert_membership(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO list_memberships (list_id, subscriber_id, status, subscribed_at)
+        VALUES ($1, $2, 'pending_confirmation', now())
+        ON CONFLICT (list_id, subscriber_id) DO NOTHING
+        "#,
+        list_id,
+        subscriber_id,
+    )
+    .execute(transaction)
+    .await?
+    .rows_affected()
+        == 1;
+    Ok(inserted)
+}
+
+#[tracing::instrument(name = "Get membership status", skip(transaction), err(Debug))]
+async fn get_membership_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<String, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT status FROM list_memberships
+        WHERE list_id = $1 AND subscriber_id = $2
+        FOR UPDATE
+        "#,
+        list_id,
+        subscriber_id,
+    )
+    .fetch_one(transaction)
+    .await
+}
+
+/// Put a subscriber who left a list back into `pending_confirmation`,
+/// they need to confirm their address again before getting its newsletters
+#[tracing::instrument(
+    name = "Restart double opt-in",
+    skip_all,
+    fields(subscriber_id = %subscriber_id, list_id = %list_id),
+    err(Debug)
+)]
+async fn restart_double_opt_in(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE list_memberships
+        SET
+          subscribed_at = now(),
+          status = 'pending_confirmation',
+          unsubscribed_at = NULL
+        WHERE list_id = $1 AND subscriber_id = $2
+        "#,
+        list_id,
+        subscriber_id,
+    )
+    .execute(transaction)
     .await?;
-    Ok(subscriber_id)
+
+    Ok(())
+}
+
+/// Drop the previous confirmation links of a subscriber for a list,
+/// so only the most recent one stays valid
+///
+/// Returns what was sent with the most recent signup, if any
+#[tracing::instrument(name = "Delete previous subscription tokens", skip_all, err(Debug))]
+pub(crate) async fn delete_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<Option<PendingSignup>, sqlx::Error> {
+    let deleted = sqlx::query!(
+        r#"
+        DELETE FROM subscription_tokens WHERE subscriber_id = $1 AND list_id = $2
+        RETURNING created_at, name, tags, attributes
+        "#,
+        subscriber_id,
+        list_id
+    )
+    .fetch_all(transaction)
+    .await?;
+
+    Ok(deleted
+        .into_iter()
+        .max_by_key(|r| r.created_at)
+        .map(|r| PendingSignup {
+            name: r.name,
+            tags: r.tags,
+            attributes: r.attributes,
+        }))
 }
 
 /// Generate a random 25-character long case-sensitive subscription token
-fn generate_subscription_token() -> String {
+pub(crate) fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
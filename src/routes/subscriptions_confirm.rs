@@ -2,12 +2,14 @@ use anyhow::Context;
 use axum::{
     extract::{Query, State},
     http,
-    response::IntoResponse,
+    response::{Html, IntoResponse},
 };
-use sqlx::PgPool;
+use chrono::{DateTime, Utc};
+use sqlx::{Postgres, Transaction};
 use uuid::Uuid;
 
-use crate::startup::AppState;
+use super::subscriptions::PendingSignup;
+use crate::{domain::SubscriberTag, problem::Problem, startup::AppState};
 
 #[derive(serde::Deserialize)]
 pub struct Parameters {
@@ -18,6 +20,8 @@ pub struct Parameters {
 pub enum ConfirmError {
     #[error("{0}")]
     Unauthorized(String),
+    #[error("The subscription token expired")]
+    Expired { list: String },
     #[error(transparent)]
     Unexpected(#[from] anyhow::Error),
 }
@@ -31,8 +35,17 @@ impl std::fmt::Debug for ConfirmError {
 impl IntoResponse for ConfirmError {
     fn into_response(self) -> axum::response::Response {
         match self {
-            ConfirmError::Unauthorized(_) => http::StatusCode::UNAUTHORIZED.into_response(),
-            ConfirmError::Unexpected(_) => http::StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            ConfirmError::Unauthorized(detail) => Problem::new(
+                http::StatusCode::UNAUTHORIZED,
+                "invalid_subscription_token",
+                detail,
+            )
+            .into_response(),
+            // Expired links are followed by people, they get a page to act on
+            ConfirmError::Expired { list } => {
+                (http::StatusCode::GONE, expired_link_page(&list)).into_response()
+            }
+            ConfirmError::Unexpected(_) => Problem::internal_error().into_response(),
         }
     }
 }
@@ -46,55 +59,185 @@ pub async fn confirm(
     State(app_state): State<AppState>,
     Query(parameters): Query<Parameters>,
 ) -> Result<impl IntoResponse, ConfirmError> {
-    let id = get_subscriber_id_from_token(&app_state.pool, &parameters.subscription_token)
+    let mut transaction = app_state
+        .pool
+        .begin()
         .await
-        .context("Failed to get subscriber id from token.")?;
+        .context("Failed to acquire a Postgres connection from the pool.")?;
 
-    if let Some(subscriber_id) = id {
-        confirm_subscriber(&app_state.pool, subscriber_id)
-            .await
-            .context("Failed to mark subscriber as confirmed")?;
+    // Tokens are single-use, consuming one deletes it even if it expired
+    let token = consume_token(&mut transaction, &parameters.subscription_token)
+        .await
+        .context("Failed to get subscriber id from token.")?
+        .ok_or_else(|| {
+            ConfirmError::Unauthorized(
+                "No associated subscriber id was found for the provided subscription token".into(),
+            )
+        })?;
 
-        Ok(http::StatusCode::OK)
-    } else {
-        Err(ConfirmError::Unauthorized(
-            "No associated subscriber id was found for the provided subscription token".into(),
-        ))
+    if token.expires_at <= Utc::now() {
+        transaction
+            .commit()
+            .await
+            .context("Failed to delete an expired subscription token.")?;
+        return Err(ConfirmError::Expired { list: token.slug });
     }
+
+    let pending = PendingSignup {
+        name: token.name,
+        tags: token.tags,
+        attributes: token.attributes,
+    };
+    confirm_subscriber(
+        &mut transaction,
+        token.subscriber_id,
+        token.list_id,
+        &pending,
+    )
+    .await
+    .context("Failed to mark subscriber as confirmed")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a subscriber.")?;
+    app_state.metrics.subscription_confirmed();
+
+    Ok(http::StatusCode::OK)
+}
+
+/// Shown when following a confirmation link that expired
+fn expired_link_page(list: &str) -> Html<String> {
+    Html(format!(
+        r#"<!DOCTYPE html>
+      <html lang="en">
+        <head>
+          <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+          <title>Link expired</title>
+        </head>
+        <body>
+          <p>This confirmation link has expired.</p>
+          <p>Enter your email address to receive a new one.</p>
+          <form action="/subscriptions/resend-confirmation" method="post">
+            <label
+              >Email
+              <input type="email" placeholder="Enter your email" name="email" />
+            </label>
+            <input hidden type="text" name="list" value="{}" />
+            <button type="submit">Send a new link</button>
+          </form>
+        </body>
+      </html>
+      "#,
+        htmlescape::encode_attribute(list)
+    ))
 }
 
+/// Confirm the subscription to a list, dropping any other pending token for it
+///
+/// The name sent at signup replaces the current one, the tags picked
+/// are added to those the subscriber already has and the attributes sent
+/// replace those with the same key
 #[tracing::instrument(
     name = "Mark subscriber as confirmed",
-    skip(subscriber_id, pool),
+    skip(subscriber_id, transaction, pending),
     err(Debug)
 )]
-pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+pub async fn confirm_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    pending: &PendingSignup,
+) -> Result<(), sqlx::Error> {
     sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
+        r#"
+        UPDATE list_memberships SET status = 'confirmed'
+        WHERE subscriber_id = $1 AND list_id = $2
+        "#,
+        subscriber_id,
+        list_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    let current_tags = sqlx::query_scalar!(
+        r#"SELECT tags FROM subscriptions WHERE id = $1 FOR UPDATE"#,
         subscriber_id
     )
-    .execute(pool)
+    .fetch_one(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET
+          last_engaged_at = now(),
+          name = COALESCE($2, name),
+          tags = $3,
+          attributes = attributes || $4
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        pending.name,
+        &SubscriberTag::merge(current_tags, &pending.tags),
+        pending.attributes,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1 AND list_id = $2"#,
+        subscriber_id,
+        list_id
+    )
+    .execute(&mut *transaction)
     .await?;
 
     Ok(())
 }
 
+pub struct ConsumedToken {
+    pub subscriber_id: Uuid,
+    pub list_id: Uuid,
+    /// Of the list the token confirms
+    pub slug: String,
+    pub expires_at: DateTime<Utc>,
+    /// Sent at signup
+    pub name: Option<String>,
+    /// Picked at signup
+    pub tags: Vec<String>,
+    /// Sent at signup
+    pub attributes: serde_json::Value,
+}
+
 #[tracing::instrument(
-    name = "Get subscriber_id from token",
-    skip(subscription_token, pool),
+    name = "Consume subscription token",
+    skip(subscription_token, transaction),
     err(Debug)
 )]
-pub async fn get_subscriber_id_from_token(
-    pool: &PgPool,
+pub async fn consume_token(
+    transaction: &mut Transaction<'_, Postgres>,
     subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        "SELECT subscriber_id FROM subscription_tokens \
-      WHERE subscription_token = $1",
+) -> Result<Option<ConsumedToken>, sqlx::Error> {
+    sqlx::query_as!(
+        ConsumedToken,
+        r#"
+        WITH consumed AS (
+          DELETE FROM subscription_tokens
+          WHERE subscription_token = $1
+          RETURNING subscriber_id, list_id, expires_at, name, tags, attributes
+        )
+        SELECT
+          c.subscriber_id AS "subscriber_id!",
+          c.list_id AS "list_id!",
+          l.slug,
+          c.expires_at AS "expires_at!",
+          c.name AS "name?",
+          c.tags AS "tags!",
+          c.attributes AS "attributes!"
+        FROM consumed c
+        JOIN lists l ON l.list_id = c.list_id
+        "#,
         subscription_token
     )
-    .fetch_optional(pool)
-    .await?;
-
-    Ok(result.map(|r| r.subscriber_id))
+    .fetch_optional(transaction)
+    .await
 }
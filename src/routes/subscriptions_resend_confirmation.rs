@@ -0,0 +1,162 @@
+use anyhow::Context;
+use axum::{
+    extract::{Form, State},
+    http,
+    response::IntoResponse,
+};
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+use super::subscriptions::{
+    delete_tokens, generate_subscription_token, send_confirmation_email, store_token,
+};
+use crate::{
+    domain::{ListSlug, SubscriberEmail},
+    mailing_lists::find_list,
+    problem::{FieldError, Problem, ValidationErrors},
+    startup::AppState,
+};
+
+#[derive(serde::Deserialize)]
+pub struct ResendFormData {
+    email: String,
+    /// Slug of the list to confirm, the default list if missing
+    #[serde(default)]
+    list: Option<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum ResendConfirmationError {
+    #[error("{0}")]
+    Validation(ValidationErrors),
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ResendConfirmationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        crate::util::error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for ResendConfirmationError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            ResendConfirmationError::Validation(errors) => {
+                Problem::validation(errors).into_response()
+            }
+            ResendConfirmationError::Unexpected(_) => Problem::internal_error().into_response(),
+        }
+    }
+}
+
+/// Issue a fresh confirmation link to a pending subscriber
+///
+/// The response is the same whether or not the address belongs to
+/// a pending subscriber, so it can't be used to probe the list
+#[tracing::instrument(
+    name = "Resend a confirmation email",
+    skip(form, app_state),
+    fields(subscriber_email = %form.email),
+    err(Debug)
+)]
+pub async fn resend_confirmation(
+    State(app_state): State<AppState>,
+    Form(form): Form<ResendFormData>,
+) -> Result<impl IntoResponse, ResendConfirmationError> {
+    let email = SubscriberEmail::parse(form.email)
+        .map_err(|e| FieldError::new("email", "invalid_email", e));
+    let list = form
+        .list
+        .map_or_else(|| Ok(ListSlug::default()), ListSlug::parse)
+        .map_err(|e| FieldError::new("list", "invalid_list", e));
+    let (email, list) = match (email, list) {
+        (Ok(email), Ok(list)) => (email, list),
+        (email, list) => {
+            return Err(ResendConfirmationError::Validation(ValidationErrors(
+                [email.err(), list.err()].into_iter().flatten().collect(),
+            )))
+        }
+    };
+
+    let mut transaction = app_state
+        .pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+
+    let list = find_list(&mut transaction, &list)
+        .await
+        .context("Failed to look up the mailing list.")?
+        .ok_or_else(|| {
+            ResendConfirmationError::Validation(ValidationErrors(vec![FieldError::new(
+                "list",
+                "unknown_list",
+                format!("There is no {list} list."),
+            )]))
+        })?;
+
+    let subscriber_id = match get_pending_subscriber_id(&mut transaction, list.list_id, &email)
+        .await
+        .context("Failed to look up a pending subscriber.")?
+    {
+        Some(subscriber_id) => subscriber_id,
+        None => return Ok(http::StatusCode::OK),
+    };
+
+    let subscription_token = generate_subscription_token();
+    // The new link applies what was sent at signup, like the one it replaces
+    let pending = delete_tokens(&mut transaction, subscriber_id, list.list_id)
+        .await
+        .context("Failed to delete previous subscription tokens.")?
+        .unwrap_or_default();
+    store_token(
+        &mut transaction,
+        subscriber_id,
+        list.list_id,
+        &subscription_token,
+        &pending,
+    )
+    .await
+    .context("Failed to store a new subscription token.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a new subscription token.")?;
+
+    send_confirmation_email(
+        app_state.email_client.as_ref(),
+        &email,
+        &list.name,
+        &app_state.application_base_url,
+        &subscription_token,
+    )
+    .await
+    .context("Failed to send a confirmation email.")?;
+
+    Ok(http::StatusCode::OK)
+}
+
+/// Lock the membership row so concurrent resends don't race each other
+#[tracing::instrument(name = "Get pending subscriber id", skip_all, err(Debug))]
+async fn get_pending_subscriber_id(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    email: &SubscriberEmail,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT s.id
+        FROM subscriptions s
+        JOIN list_memberships m ON m.subscriber_id = s.id
+        WHERE s.email = $1 AND m.list_id = $2 AND m.status = 'pending_confirmation'
+        FOR UPDATE OF m
+        "#,
+        email.as_ref(),
+        list_id
+    )
+    .fetch_optional(transaction)
+    .await?;
+
+    Ok(row.map(|r| r.id))
+}
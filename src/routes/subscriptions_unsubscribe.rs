@@ -0,0 +1,148 @@
+use anyhow::Context;
+use axum::{
+    extract::{Query, State},
+    http,
+    response::{Html, IntoResponse},
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{problem::Problem, startup::AppState, unsubscribe_links::Unsubscription};
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("The unsubscribe token is invalid")]
+    InvalidToken,
+    #[error(transparent)]
+    Unexpected(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        crate::util::error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for UnsubscribeError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            UnsubscribeError::InvalidToken => Problem::new(
+                http::StatusCode::UNAUTHORIZED,
+                "invalid_unsubscribe_token",
+                self.to_string(),
+            )
+            .into_response(),
+            UnsubscribeError::Unexpected(_) => Problem::internal_error().into_response(),
+        }
+    }
+}
+
+/// Ask for confirmation before unsubscribing
+///
+/// Mail scanners follow links in emails, so a plain `GET`
+/// must never change anything
+#[tracing::instrument(name = "Unsubscribe form", skip(parameters, app_state), err(Debug))]
+pub async fn unsubscribe_form(
+    State(app_state): State<AppState>,
+    Query(parameters): Query<Parameters>,
+) -> Result<impl IntoResponse, UnsubscribeError> {
+    app_state
+        .unsubscribe_links
+        .verify(&parameters.token)
+        .ok_or(UnsubscribeError::InvalidToken)?;
+
+    let token = htmlescape::encode_attribute(&parameters.token);
+    Ok(Html(format!(
+        r#"<!DOCTYPE html>
+      <html lang="en">
+        <head>
+          <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+          <title>Unsubscribe</title>
+        </head>
+        <body>
+          <p>Do you really want to stop receiving our newsletter?</p>
+          <form action="/subscriptions/unsubscribe?token={token}" method="post">
+            <button type="submit">Unsubscribe</button>
+          </form>
+        </body>
+      </html>
+      "#
+    )))
+}
+
+/// Unsubscribe the owner of the token
+///
+/// Works as the one-click endpoint as well, the token is in the query
+/// and whatever the body holds (e.g. `List-Unsubscribe=One-Click`) is ignored
+#[tracing::instrument(
+    name = "Unsubscribe a subscriber",
+    skip(parameters, app_state),
+    fields(subscriber_id=tracing::field::Empty),
+    err(Debug)
+)]
+pub async fn unsubscribe(
+    State(app_state): State<AppState>,
+    Query(parameters): Query<Parameters>,
+) -> Result<impl IntoResponse, UnsubscribeError> {
+    let unsubscription = app_state
+        .unsubscribe_links
+        .verify(&parameters.token)
+        .ok_or(UnsubscribeError::InvalidToken)?;
+    tracing::Span::current().record(
+        "subscriber_id",
+        tracing::field::display(unsubscription.subscriber_id),
+    );
+
+    mark_subscriber_as_unsubscribed(&app_state.pool, &unsubscription)
+        .await
+        .context("Failed to mark subscriber as unsubscribed.")?;
+
+    Ok(Html(
+        r#"<!DOCTYPE html>
+      <html lang="en">
+        <head>
+          <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+          <title>Unsubscribed</title>
+        </head>
+        <body>
+          <p>You have been unsubscribed, you won't receive our newsletter anymore.</p>
+        </body>
+      </html>
+      "#,
+    ))
+}
+
+/// Idempotent, unsubscribing twice keeps the original timestamp
+///
+/// Leaves every list when the token doesn't name one
+#[tracing::instrument(
+    name = "Mark subscriber as unsubscribed",
+    skip(pool, unsubscription),
+    err(Debug)
+)]
+async fn mark_subscriber_as_unsubscribed(
+    pool: &PgPool,
+    unsubscription: &Unsubscription,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE list_memberships
+        SET status = 'unsubscribed', unsubscribed_at = now()
+        WHERE
+          subscriber_id = $1 AND
+          ($2::uuid IS NULL OR list_id = $2) AND
+          status <> 'unsubscribed'
+        "#,
+        unsubscription.subscriber_id,
+        unsubscription.list_id as Option<Uuid>
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,108 @@
+//! Saved audiences, narrowing down who gets an issue within its list
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    domain::{SegmentSlug, SubscriberTag},
+    subscriber_attributes::SubscriberAttributes,
+};
+
+pub struct Segment {
+    pub segment_id: Uuid,
+    pub slug: String,
+    pub name: String,
+    pub tags: Vec<String>,
+    pub excluded_tags: Vec<String>,
+    pub subscribed_after: Option<DateTime<Utc>>,
+    pub subscribed_before: Option<DateTime<Utc>>,
+    pub engaged_within_days: Option<i32>,
+    pub attributes: serde_json::Value,
+}
+
+/// Every condition that is set must hold for a subscriber to be in the segment
+pub struct NewSegment {
+    pub slug: SegmentSlug,
+    pub name: String,
+    /// Subscribers must carry every one of these
+    pub tags: Vec<SubscriberTag>,
+    /// and none of these
+    pub excluded_tags: Vec<SubscriberTag>,
+    /// Signed up to the list at or after
+    pub subscribed_after: Option<DateTime<Utc>>,
+    /// Signed up to the list strictly before
+    pub subscribed_before: Option<DateTime<Utc>>,
+    /// Seen acting on their subscription in the last days
+    pub engaged_within_days: Option<i32>,
+    /// Subscribers must have these exact attribute values
+    pub attributes: SubscriberAttributes,
+}
+
+#[tracing::instrument(name = "Find a segment", skip(executor), err(Debug))]
+pub async fn find_segment(
+    executor: impl PgExecutor<'_>,
+    slug: &SegmentSlug,
+) -> Result<Option<Segment>, sqlx::Error> {
+    sqlx::query_as!(
+        Segment,
+        r#"
+        SELECT
+          segment_id, slug, name, tags, excluded_tags,
+          subscribed_after, subscribed_before, engaged_within_days, attributes
+        FROM segments
+        WHERE slug = $1
+        "#,
+        slug.as_ref()
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+#[tracing::instrument(name = "Get all segments", skip(pool), err(Debug))]
+pub async fn get_segments(pool: &PgPool) -> Result<Vec<Segment>, sqlx::Error> {
+    sqlx::query_as!(
+        Segment,
+        r#"
+        SELECT
+          segment_id, slug, name, tags, excluded_tags,
+          subscribed_after, subscribed_before, engaged_within_days, attributes
+        FROM segments
+        ORDER BY created_at
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Returns `None` if a segment with the same slug already exists
+#[tracing::instrument(name = "Create a segment", skip_all, fields(slug = %segment.slug), err(Debug))]
+pub async fn create_segment(
+    pool: &PgPool,
+    segment: &NewSegment,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let segment_id = Uuid::new_v4();
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO segments (
+          segment_id, slug, name, tags, excluded_tags,
+          subscribed_after, subscribed_before, engaged_within_days, attributes, created_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, now())
+        ON CONFLICT (slug) DO NOTHING
+        "#,
+        segment_id,
+        segment.slug.as_ref(),
+        segment.name,
+        &SubscriberTag::to_strings(&segment.tags),
+        &SubscriberTag::to_strings(&segment.excluded_tags),
+        segment.subscribed_after,
+        segment.subscribed_before,
+        segment.engaged_within_days,
+        segment.attributes.to_json(),
+    )
+    .execute(pool)
+    .await?
+    .rows_affected()
+        == 1;
+    Ok(inserted.then_some(segment_id))
+}
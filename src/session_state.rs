@@ -0,0 +1,109 @@
+use axum_extra::extract::cookie::{Cookie, SameSite, SignedCookieJar};
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Name of the signed cookie holding the id of the current session
+pub const SESSION_COOKIE_NAME: &str = "session_id";
+
+/// How long a session stays valid after logging in
+const SESSION_TTL_HOURS: i64 = 12;
+
+/// Read the session id out of the signed cookie jar, if there is a valid one
+pub fn session_id_from_jar(jar: &SignedCookieJar) -> Option<Uuid> {
+    jar.get(SESSION_COOKIE_NAME)
+        .and_then(|cookie| Uuid::parse_str(cookie.value()).ok())
+}
+
+/// Build the cookie used to hand the session id to the client
+///
+/// `path` is pinned to `/` so the cookie is sent to every route,
+/// otherwise the browser would scope it to the path of the login request
+pub fn session_cookie(session_id: Uuid) -> Cookie<'static> {
+    Cookie::build(SESSION_COOKIE_NAME, session_id.to_string())
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .finish()
+}
+
+/// Cookie that tells the client to drop the session cookie
+pub fn session_removal_cookie() -> Cookie<'static> {
+    Cookie::build(SESSION_COOKIE_NAME, "").path("/").finish()
+}
+
+/// Start a fresh session for `user_id`
+///
+/// Any session referenced by `previous_session_id` is destroyed, so the
+/// session id is always rotated on login and a session id planted
+/// before authenticating can never be promoted to a logged in one
+#[tracing::instrument(name = "Renew session", skip(pool, previous_session_id), err(Debug))]
+pub async fn renew_session(
+    pool: &PgPool,
+    previous_session_id: Option<Uuid>,
+    user_id: Uuid,
+) -> Result<Uuid, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    if let Some(previous_session_id) = previous_session_id {
+        sqlx::query!(
+            r#"DELETE FROM sessions WHERE session_id = $1"#,
+            previous_session_id
+        )
+        .execute(&mut transaction)
+        .await?;
+    }
+
+    // Opportunistically clean up sessions of this user that already expired
+    sqlx::query!(
+        r#"DELETE FROM sessions WHERE user_id = $1 AND expires_at < now()"#,
+        user_id
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    let session_id = Uuid::new_v4();
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO sessions (session_id, user_id, created_at, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        session_id,
+        user_id,
+        now,
+        now + Duration::hours(SESSION_TTL_HOURS),
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    transaction.commit().await?;
+    Ok(session_id)
+}
+
+/// Look up the user owning a session, ignoring sessions that expired
+#[tracing::instrument(name = "Get user_id from session", skip(pool, session_id), err(Debug))]
+pub async fn get_user_id(pool: &PgPool, session_id: Uuid) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id
+        FROM sessions
+        WHERE session_id = $1 AND expires_at > now()
+        "#,
+        session_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|r| r.user_id))
+}
+
+/// Destroy a session, e.g. when the user logs out
+#[tracing::instrument(name = "Delete session", skip(pool, session_id), err(Debug))]
+pub async fn delete_session(pool: &PgPool, session_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query!(r#"DELETE FROM sessions WHERE session_id = $1"#, session_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
@@ -1,29 +1,41 @@
-use std::{net::TcpListener, ops::Deref, sync::Arc};
+use std::{net::TcpListener, ops::Deref, path::PathBuf, sync::Arc};
 
 use axum::{
     extract::FromRef,
+    middleware,
     routing::{get, post, IntoMakeService},
     Router, Server,
 };
 use axum_extra::extract::cookie::Key;
 use http::Request;
 use hyper::{server::conn::AddrIncoming, Body};
-use reqwest::Url;
 use secrecy::{ExposeSecret, Secret};
 use sqlx::{postgres::PgPoolOptions, PgPool};
 use tower::ServiceBuilder;
 use tower_http::trace::TraceLayer;
-use uuid::Uuid;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 use crate::{
     configuration::{DatabaseSettings, Settings},
-    email_client::EmailClient,
+    email_client::{CircuitBreaker, EmailSender},
+    issue_delivery_worker::run_worker_until_stopped,
+    metrics::{get_metrics, track_requests, Metrics},
+    request_id::{propagate_request_id, REQUEST_ID_HEADER},
     routes::*,
+    telemetry::extract_trace_context,
+    unsubscribe_links::UnsubscribeLinks,
 };
 
 #[derive(Clone)]
 pub struct HmacSecret(pub Secret<String>);
 
+/// Everything the application needs to send emails
+pub struct EmailDelivery {
+    pub client: Arc<dyn EmailSender>,
+    pub circuit_breaker: Arc<CircuitBreaker>,
+    pub ping_on_readiness: bool,
+}
+
 #[derive(Clone)]
 pub struct AppState(Arc<InnerState>);
 
@@ -37,8 +49,16 @@ impl Deref for AppState {
 
 pub struct InnerState {
     pub pool: PgPool,
-    pub email_client: EmailClient,
+    pub email_client: Arc<dyn EmailSender>,
+    /// Reported by the health checks
+    pub email_circuit_breaker: Arc<CircuitBreaker>,
+    /// Whether `/health/ready` checks that the email provider can be reached
+    pub ping_email_provider_on_readiness: bool,
+    pub metrics: Arc<Metrics>,
     pub application_base_url: String,
+    pub unsubscribe_links: UnsubscribeLinks,
+    /// Set when the outbox email provider is used with its listing enabled
+    pub outbox_directory: Option<PathBuf>,
     pub key: Key,
 }
 
@@ -55,19 +75,25 @@ pub async fn build(
     // Setup db connection pool
     let connection_pool = get_connection_pool(&configuration.database);
 
-    // Setup `EmailClient` for sending emails after subscribing
-    let sender_email = configuration
+    let outbox_directory = configuration.email_client.outbox_listing_directory();
+
+    // Setup the email provider for sending emails after subscribing
+    let ping_on_readiness = configuration.email_client.ping_on_readiness;
+    let metrics = Arc::new(Metrics::new());
+    let email_circuit_breaker = configuration.email_client.circuit_breaker();
+    let email_client = configuration
         .email_client
-        .sender()
-        .expect("Invalid sender email address.");
-    let timeout = configuration.email_client.timeout();
-    let email_client = EmailClient::new(
-        Url::parse(configuration.email_client.base_url.as_str())
-            .expect("Unable to parse config base_url"),
-        sender_email,
-        configuration.email_client.authorization_token,
-        timeout,
-    );
+        .client(email_circuit_breaker.clone(), metrics.clone());
+
+    // Deliver queued newsletter issues in the background, next to the server
+    tokio::spawn(run_worker_until_stopped(
+        connection_pool.clone(),
+        email_client.clone(),
+        UnsubscribeLinks::new(
+            configuration.application.base_url.clone(),
+            configuration.application.hmac_secret.clone(),
+        ),
+    ));
 
     // Get `TcpListener` for setting up as server
     let address = format!(
@@ -80,9 +106,15 @@ pub async fn build(
     run(
         listener,
         connection_pool,
-        email_client,
+        EmailDelivery {
+            client: email_client,
+            circuit_breaker: email_circuit_breaker,
+            ping_on_readiness,
+        },
         configuration.application.base_url,
         HmacSecret(configuration.application.hmac_secret),
+        outbox_directory,
+        metrics,
     )
 }
 
@@ -95,35 +127,95 @@ pub fn get_connection_pool(database_settings: &DatabaseSettings) -> PgPool {
 pub fn run(
     listener: TcpListener,
     pool: PgPool,
-    email_client: EmailClient,
+    email: EmailDelivery,
     base_url: String,
     hmac_secret: HmacSecret,
+    outbox_directory: Option<PathBuf>,
+    metrics: Arc<Metrics>,
 ) -> Result<Server<AddrIncoming, IntoMakeService<Router>>, std::io::Error> {
     // Initialize application state
     let app_state = AppState(Arc::new(InnerState {
         pool,
-        email_client,
+        email_client: email.client,
+        email_circuit_breaker: email.circuit_breaker,
+        ping_email_provider_on_readiness: email.ping_on_readiness,
+        metrics,
+        unsubscribe_links: UnsubscribeLinks::new(base_url.clone(), hmac_secret.0.clone()),
         application_base_url: base_url,
+        outbox_directory: outbox_directory.clone(),
         key: Key::from(hmac_secret.0.expose_secret().as_bytes()),
     }));
 
     // Setup tracing for the application
     // Rust yells at me when I don't include the request in the `new_make_span` closure. No idea why
-    let svc = ServiceBuilder::new().layer(TraceLayer::new_for_http().make_span_with(
-        |_request: &Request<Body>| {
-            tracing::info_span!("request", request_id = Uuid::new_v4().to_string())
-        },
-    ));
-
-    let app = Router::new()
+    let svc = ServiceBuilder::new()
+        // First, so that everything after knows the id of the request
+        .layer(middleware::from_fn(propagate_request_id))
+        .layer(
+            TraceLayer::new_for_http().make_span_with(|request: &Request<Body>| {
+                let request_id = request
+                    .headers()
+                    .get(REQUEST_ID_HEADER)
+                    .and_then(|value| value.to_str().ok())
+                    .unwrap_or_default();
+                let span = tracing::info_span!("request", request_id = %request_id);
+                // Join the caller's trace when it sends a `traceparent`
+                span.set_parent(extract_trace_context(request.headers()));
+                span
+            }),
+        )
+        // Request counts and latencies for `/metrics`
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            track_requests,
+        ));
+
+    let mut app = Router::new()
         .route("/health_check", get(health_check))
+        .route("/health/ready", get(health_ready))
+        .route("/metrics", get(get_metrics))
         .route("/subscriptions", post(subscribe))
         .route("/subscriptions/confirm", get(confirm))
+        .route(
+            "/subscriptions/resend-confirmation",
+            post(resend_confirmation),
+        )
+        .route(
+            "/subscriptions/unsubscribe",
+            get(unsubscribe_form).post(unsubscribe),
+        )
         .route("/newsletters", post(publish_newsletter))
         .route("/", get(home))
         .route("/login", get(login_form).post(login))
-        .layer(svc)
-        .with_state(app_state);
+        .route("/admin/dashboard", get(admin_dashboard))
+        .route(
+            "/admin/password",
+            get(change_password_form).post(change_password),
+        )
+        .route("/admin/logout", post(logout))
+        .route("/admin/subscribers", get(list_subscribers))
+        .route("/admin/lists", get(mailing_lists).post(create_mailing_list))
+        .route(
+            "/admin/segments",
+            get(segments).post(create_segment_from_form),
+        )
+        .route("/admin/subscribers/tags", post(set_subscriber_tags))
+        .route("/admin/subscribers/export", get(export_subscribers))
+        .route(
+            "/admin/attributes",
+            get(subscriber_attributes).post(create_subscriber_attribute),
+        )
+        .route(
+            "/admin/newsletters",
+            get(publish_newsletter_form).post(publish_newsletter_from_form),
+        );
+
+    if outbox_directory.is_some() {
+        app = app
+            .route("/dev/outbox", get(outbox_listing))
+            .route("/dev/outbox/:message_id", get(outbox_message));
+    }
+    let app = app.layer(svc).with_state(app_state);
 
     tracing::info!("Listening on {}", listener.local_addr().unwrap());
 
@@ -0,0 +1,359 @@
+//! Custom subscriber attributes, checked against the schema admins define
+use anyhow::anyhow;
+use serde_json::{Map, Value};
+use sqlx::{PgExecutor, PgPool};
+
+use crate::{domain::AttributeKey, problem::FieldError};
+
+/// Longest text value, in characters
+pub const MAX_TEXT_LENGTH: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttributeKind {
+    /// Free-form, on a single line
+    Text,
+    Number,
+    /// One of a fixed set of options
+    Choice,
+}
+
+impl AttributeKind {
+    pub const ALL: [AttributeKind; 3] = [Self::Text, Self::Number, Self::Choice];
+
+    pub fn parse(s: &str) -> Result<AttributeKind, String> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == s)
+            .ok_or_else(|| format!("{s} is not a valid attribute kind."))
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::Number => "number",
+            Self::Choice => "choice",
+        }
+    }
+}
+
+pub struct AttributeDefinition {
+    pub key: String,
+    pub label: String,
+    pub kind: AttributeKind,
+    /// The values a `Choice` can take, empty for other kinds
+    pub options: Vec<String>,
+    /// Subscribers can't sign up without it
+    pub required: bool,
+}
+
+pub struct NewAttributeDefinition {
+    pub key: AttributeKey,
+    pub label: String,
+    pub kind: AttributeKind,
+    pub options: Vec<String>,
+    pub required: bool,
+}
+
+impl AttributeDefinition {
+    /// Normalise a submitted value, `None` if it is blank
+    fn parse_value(&self, value: Value) -> Result<Option<Value>, String> {
+        let label = &self.label;
+        let value = match value {
+            Value::Null => return Ok(None),
+            Value::String(s) if s.trim().is_empty() => return Ok(None),
+            value => value,
+        };
+        match (self.kind, value) {
+            (AttributeKind::Text, Value::String(s)) => {
+                let s = s.trim();
+                if s.chars().count() > MAX_TEXT_LENGTH {
+                    Err(format!(
+                        "{label} must be at most {MAX_TEXT_LENGTH} characters long."
+                    ))
+                } else if s.chars().any(char::is_control) {
+                    Err(format!("{label} must fit on a single line."))
+                } else {
+                    Ok(Some(Value::String(s.to_owned())))
+                }
+            }
+            (AttributeKind::Text, _) => Err(format!("{label} must be text.")),
+            (AttributeKind::Number, Value::Number(n)) => Ok(Some(Value::Number(n))),
+            (AttributeKind::Number, Value::String(s)) => parse_number(s.trim())
+                .map(Some)
+                .ok_or_else(|| format!("{label} must be a number.")),
+            (AttributeKind::Number, _) => Err(format!("{label} must be a number.")),
+            (AttributeKind::Choice, Value::String(s))
+                if self.options.iter().any(|option| option == s.trim()) =>
+            {
+                Ok(Some(Value::String(s.trim().to_owned())))
+            }
+            (AttributeKind::Choice, _) => Err(format!(
+                "{label} must be one of {}.",
+                self.options.join(", ")
+            )),
+        }
+    }
+}
+
+/// Forms only send strings, integers are kept as such
+fn parse_number(s: &str) -> Option<Value> {
+    if let Ok(n) = s.parse::<i64>() {
+        return Some(n.into());
+    }
+    s.parse::<f64>()
+        .ok()
+        .and_then(serde_json::Number::from_f64)
+        .map(Value::Number)
+}
+
+/// Every attribute defined by admins
+pub struct AttributeSchema(Vec<AttributeDefinition>);
+
+impl AttributeSchema {
+    pub fn definitions(&self) -> &[AttributeDefinition] {
+        &self.0
+    }
+
+    /// Attributes submitted by a subscriber, every required one must be there
+    ///
+    /// Blank values are dropped. All the errors are returned at once,
+    /// each under the `attributes.<key>` field
+    pub fn validate(
+        &self,
+        values: impl IntoIterator<Item = (String, Value)>,
+    ) -> Result<SubscriberAttributes, Vec<FieldError>> {
+        self.parse(values, true)
+    }
+
+    /// Attribute values a segment matches on, none is required
+    pub fn validate_filter(
+        &self,
+        values: impl IntoIterator<Item = (String, Value)>,
+    ) -> Result<SubscriberAttributes, Vec<FieldError>> {
+        self.parse(values, false)
+    }
+
+    fn parse(
+        &self,
+        values: impl IntoIterator<Item = (String, Value)>,
+        check_required: bool,
+    ) -> Result<SubscriberAttributes, Vec<FieldError>> {
+        let mut attributes = Map::new();
+        let mut errors = vec![];
+        for (key, value) in values {
+            let field = format!("attributes.{key}");
+            let Some(definition) = self.0.iter().find(|d| d.key == key) else {
+                let detail = format!("There is no {key} attribute.");
+                errors.push(FieldError::new(field, "unknown_attribute", detail));
+                continue;
+            };
+            match definition.parse_value(value) {
+                Ok(Some(value)) => {
+                    attributes.insert(key, value);
+                }
+                Ok(None) => {}
+                Err(e) => errors.push(FieldError::new(field, "invalid_attribute", e)),
+            }
+        }
+        if check_required {
+            for definition in self.0.iter().filter(|d| d.required) {
+                let field = format!("attributes.{}", definition.key);
+                // Invalid values were already reported
+                if !attributes.contains_key(&definition.key)
+                    && !errors.iter().any(|e| e.field == field)
+                {
+                    let detail = format!("{} is required.", definition.label);
+                    errors.push(FieldError::new(field, "missing_attribute", detail));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(SubscriberAttributes(attributes))
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Attribute values that passed validation, keyed by attribute
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SubscriberAttributes(Map<String, Value>);
+
+impl SubscriberAttributes {
+    /// As stored in JSONB columns
+    pub fn to_json(&self) -> Value {
+        Value::Object(self.0.clone())
+    }
+}
+
+/// How an attribute value shows up in pages, exports and emails
+pub fn display_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+#[tracing::instrument(name = "Get the subscriber attribute schema", skip_all, err(Debug))]
+pub async fn get_attribute_schema(
+    executor: impl PgExecutor<'_>,
+) -> Result<AttributeSchema, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT key, label, kind, options, required
+        FROM subscriber_attributes
+        ORDER BY created_at, key
+        "#
+    )
+    .fetch_all(executor)
+    .await?;
+
+    let definitions = rows
+        .into_iter()
+        .map(|r| {
+            Ok(AttributeDefinition {
+                kind: AttributeKind::parse(&r.kind).map_err(|e| anyhow!(e))?,
+                key: r.key,
+                label: r.label,
+                options: r.options,
+                required: r.required,
+            })
+        })
+        .collect::<Result<_, anyhow::Error>>()?;
+    Ok(AttributeSchema(definitions))
+}
+
+/// Returns `false` if an attribute with the same key already exists
+#[tracing::instrument(
+    name = "Create a subscriber attribute",
+    skip_all,
+    fields(key = %definition.key),
+    err(Debug)
+)]
+pub async fn create_attribute_definition(
+    pool: &PgPool,
+    definition: &NewAttributeDefinition,
+) -> Result<bool, sqlx::Error> {
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO subscriber_attributes (key, label, kind, options, required, created_at)
+        VALUES ($1, $2, $3, $4, $5, now())
+        ON CONFLICT (key) DO NOTHING
+        "#,
+        definition.key.as_ref(),
+        definition.label,
+        definition.kind.as_str(),
+        &definition.options,
+        definition.required,
+    )
+    .execute(pool)
+    .await?
+    .rows_affected()
+        == 1;
+    Ok(inserted)
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::assert_ok;
+    use serde_json::{json, Value};
+
+    use super::{AttributeDefinition, AttributeKind, AttributeSchema};
+
+    fn schema() -> AttributeSchema {
+        let definition = |key: &str, kind, options: &[&str], required| AttributeDefinition {
+            key: key.into(),
+            label: key.into(),
+            kind,
+            options: options.iter().map(|o| o.to_string()).collect(),
+            required,
+        };
+        AttributeSchema(vec![
+            definition("company", AttributeKind::Text, &[], false),
+            definition("employees", AttributeKind::Number, &[], false),
+            definition("country", AttributeKind::Choice, &["FR", "DE"], true),
+        ])
+    }
+
+    fn values(pairs: &[(&str, Value)]) -> Vec<(String, Value)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.clone()))
+            .collect()
+    }
+
+    fn error_codes(pairs: &[(&str, Value)]) -> Vec<(String, &'static str)> {
+        schema()
+            .validate(values(pairs))
+            .unwrap_err()
+            .into_iter()
+            .map(|e| (e.field.into_owned(), e.code))
+            .collect()
+    }
+
+    #[test]
+    fn valid_values_are_normalised() {
+        let attributes = assert_ok!(schema().validate(values(&[
+            ("company", json!(" Acme ")),
+            ("employees", json!("12")),
+            ("country", json!("FR")),
+        ])));
+        assert_eq!(
+            attributes.to_json(),
+            json!({ "company": "Acme", "employees": 12, "country": "FR" })
+        );
+    }
+
+    #[test]
+    fn blank_values_are_dropped() {
+        let attributes = assert_ok!(schema().validate(values(&[
+            ("company", json!("  ")),
+            ("employees", Value::Null),
+            ("country", json!("DE")),
+        ])));
+        assert_eq!(attributes.to_json(), json!({ "country": "DE" }));
+    }
+
+    #[test]
+    fn every_invalid_attribute_is_reported() {
+        assert_eq!(
+            error_codes(&[
+                ("company", json!(42)),
+                ("employees", json!("a dozen")),
+                ("nickname", json!("Ferris")),
+            ]),
+            vec![
+                ("attributes.company".into(), "invalid_attribute"),
+                ("attributes.employees".into(), "invalid_attribute"),
+                ("attributes.nickname".into(), "unknown_attribute"),
+                ("attributes.country".into(), "missing_attribute"),
+            ]
+        );
+    }
+
+    #[test]
+    fn choices_must_be_one_of_the_options() {
+        assert_eq!(
+            error_codes(&[("country", json!("IT"))]),
+            vec![("attributes.country".into(), "invalid_attribute")]
+        );
+    }
+
+    #[test]
+    fn text_must_be_short_and_on_one_line() {
+        for text in ["a".repeat(257), "two\nlines".into()] {
+            assert_eq!(
+                error_codes(&[("company", json!(text)), ("country", json!("FR"))]),
+                vec![("attributes.company".into(), "invalid_attribute")]
+            );
+        }
+    }
+
+    #[test]
+    fn filters_do_not_need_required_attributes() {
+        let filter = assert_ok!(schema().validate_filter(values(&[("employees", json!("2.5"))])));
+        assert_eq!(filter.to_json(), json!({ "employees": 2.5 }));
+    }
+}
@@ -1,19 +1,37 @@
+use opentelemetry::{
+    global,
+    sdk::{
+        propagation::TraceContextPropagator,
+        trace::{self, Sampler, Tracer, TracerProvider},
+        Resource,
+    },
+    trace::TracerProvider as _,
+    KeyValue,
+};
+use opentelemetry_http::{HeaderExtractor, HeaderInjector};
+use opentelemetry_otlp::WithExportConfig;
 use tokio::task::JoinHandle;
 use tracing::{subscriber::set_global_default, Subscriber};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Registry};
 
+use crate::configuration::TelemetrySettings;
+
 /// Compose multiple layers into `tracing`'s subscriber.
 ///
 /// We are using `impl Subscriber` as a return type so we dont have to
 /// spell out the actual type of the returned subscriber
 /// `init_subscriber` requires a type with `Send` and `Sync` so we have
 /// added it to the return signature also
+///
+/// Spans are also handed over to `tracer` when there is one, see `get_tracer`
 pub fn get_subscriber<Sink>(
     name: String,
     env_filter: String,
     sink: Sink,
+    tracer: Option<Tracer>,
 ) -> impl Subscriber + Send + Sync
 where
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static, // a higher-ranked trait bound. See [here](https://doc.rust-lang.org/nomicon/hrtb.html) for details
@@ -21,12 +39,51 @@ where
     let env_filter =
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
     let formatting_layer = BunyanFormattingLayer::new(name, sink);
+    let telemetry_layer = tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer));
     Registry::default()
         .with(env_filter)
+        .with(telemetry_layer)
         .with(JsonStorageLayer)
         .with(formatting_layer)
 }
 
+/// A tracer exporting spans to the configured OTLP collector
+///
+/// Without a collector spans are not exported, but still get trace ids:
+/// the trace context of incoming requests is forwarded all the same.
+///
+/// Must be called from within a Tokio runtime, spans are exported in batches
+/// by a background task
+pub fn get_tracer(settings: &TelemetrySettings) -> Tracer {
+    let config = trace::config()
+        // Callers that already decided whether to sample get their way
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            settings.sampling_ratio,
+        ))))
+        .with_resource(Resource::new(vec![KeyValue::new(
+            "service.name",
+            settings.service_name.clone(),
+        )]));
+
+    let Some(endpoint) = settings.otlp_endpoint.as_ref() else {
+        let provider = TracerProvider::builder().with_config(config).build();
+        let tracer = provider.tracer(settings.service_name.clone());
+        // The global provider keeps the tracer alive
+        global::set_tracer_provider(provider);
+        return tracer;
+    };
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(config)
+        .install_batch(opentelemetry::runtime::Tokio)
+        .expect("Failed to install the OTLP trace exporter.")
+}
+
 /// Register subscriber as global default for processing span data
 ///
 /// Should only be called once!
@@ -34,6 +91,23 @@ pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     // Redirect all `log` events to our subscriber
     LogTracer::init().expect("Failed to set logger");
     set_global_default(subscriber).expect("Failed to set subscriber");
+    // W3C `traceparent`/`tracestate` headers, in and out
+    global::set_text_map_propagator(TraceContextPropagator::new());
+}
+
+/// The trace context sent by the caller of an incoming request, if any
+pub fn extract_trace_context(headers: &http::HeaderMap) -> opentelemetry::Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)))
+}
+
+/// Headers carrying the current trace context to the services we call
+pub fn trace_context_headers() -> http::HeaderMap {
+    let context = tracing::Span::current().context();
+    let mut headers = http::HeaderMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(&mut headers))
+    });
+    headers
 }
 
 /// Spawn a new blocking thread with the current span
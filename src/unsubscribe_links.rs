@@ -0,0 +1,176 @@
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Builds and checks the per-subscriber links used to leave a mailing list
+///
+/// A token is the subscriber and list ids followed by an HMAC of them, so it
+/// can't be forged for another subscriber and doesn't need to be stored anywhere
+#[derive(Clone)]
+pub struct UnsubscribeLinks {
+    base_url: String,
+    hmac_secret: Secret<String>,
+}
+
+/// Who wants to leave what, as carried by a valid token
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Unsubscription {
+    pub subscriber_id: Uuid,
+    /// `None` for links sent before there were several lists, they leave them all
+    pub list_id: Option<Uuid>,
+}
+
+impl UnsubscribeLinks {
+    pub fn new(base_url: String, hmac_secret: Secret<String>) -> Self {
+        Self {
+            base_url,
+            hmac_secret,
+        }
+    }
+
+    /// The signed token to leave a list
+    pub fn token(&self, subscriber_id: Uuid, list_id: Uuid) -> String {
+        let signature = hex::encode(
+            self.mac(subscriber_id, Some(list_id))
+                .finalize()
+                .into_bytes(),
+        );
+        format!("{}.{}.{}", subscriber_id, list_id, signature)
+    }
+
+    /// The link to put in every email of a list sent to a subscriber
+    pub fn link(&self, subscriber_id: Uuid, list_id: Uuid) -> String {
+        format!(
+            "{}/subscriptions/unsubscribe?token={}",
+            self.base_url,
+            self.token(subscriber_id, list_id)
+        )
+    }
+
+    /// Returns what `token` asks for if its signature is valid
+    pub fn verify(&self, token: &str) -> Option<Unsubscription> {
+        let mut parts = token.split('.');
+        let (subscriber_id, list_id, signature) =
+            match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                (Some(subscriber_id), Some(list_id), Some(signature), None) => {
+                    (subscriber_id, Some(list_id), signature)
+                }
+                (Some(subscriber_id), Some(signature), None, None) => {
+                    (subscriber_id, None, signature)
+                }
+                _ => return None,
+            };
+        let subscriber_id = Uuid::parse_str(subscriber_id).ok()?;
+        let list_id = list_id.map(Uuid::parse_str).transpose().ok()?;
+        let signature = hex::decode(signature).ok()?;
+
+        // `verify_slice` compares in constant time
+        self.mac(subscriber_id, list_id)
+            .verify_slice(&signature)
+            .ok()
+            .map(|_| Unsubscription {
+                subscriber_id,
+                list_id,
+            })
+    }
+
+    fn mac(&self, subscriber_id: Uuid, list_id: Option<Uuid>) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(self.hmac_secret.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size");
+        // Prefix with a purpose so the signature can't be replayed elsewhere
+        mac.update(b"unsubscribe:");
+        mac.update(subscriber_id.as_bytes());
+        if let Some(list_id) = list_id {
+            mac.update(list_id.as_bytes());
+        }
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_none, assert_some_eq};
+    use hmac::Mac;
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    use super::{UnsubscribeLinks, Unsubscription};
+
+    fn links(secret: &str) -> UnsubscribeLinks {
+        UnsubscribeLinks::new("http://127.0.0.1".into(), Secret::new(secret.into()))
+    }
+
+    #[test]
+    fn a_generated_token_is_valid() {
+        let links = links("secret");
+        let subscriber_id = Uuid::new_v4();
+        let list_id = Uuid::new_v4();
+        assert_some_eq!(
+            links.verify(&links.token(subscriber_id, list_id)),
+            Unsubscription {
+                subscriber_id,
+                list_id: Some(list_id)
+            }
+        );
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = links("another-secret").token(Uuid::new_v4(), Uuid::new_v4());
+        assert_none!(links("secret").verify(&token));
+    }
+
+    #[test]
+    fn a_token_for_another_subscriber_is_rejected() {
+        let links = links("secret");
+        let list_id = Uuid::new_v4();
+        let token = links.token(Uuid::new_v4(), list_id);
+        let signature = token.rsplit('.').next().unwrap();
+        let forged = format!("{}.{}.{}", Uuid::new_v4(), list_id, signature);
+        assert_none!(links.verify(&forged));
+    }
+
+    #[test]
+    fn a_token_for_another_list_is_rejected() {
+        let links = links("secret");
+        let subscriber_id = Uuid::new_v4();
+        let token = links.token(subscriber_id, Uuid::new_v4());
+        let signature = token.rsplit('.').next().unwrap();
+        let forged = format!("{}.{}.{}", subscriber_id, Uuid::new_v4(), signature);
+        assert_none!(links.verify(&forged));
+    }
+
+    #[test]
+    fn tokens_sent_before_lists_existed_leave_every_list() {
+        let links = links("secret");
+        let subscriber_id = Uuid::new_v4();
+        let signature = hex::encode(links.mac(subscriber_id, None).finalize().into_bytes());
+        let legacy = format!("{}.{}", subscriber_id, signature);
+
+        assert_some_eq!(
+            links.verify(&legacy),
+            Unsubscription {
+                subscriber_id,
+                list_id: None
+            }
+        );
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        let links = links("secret");
+        for token in [
+            "",
+            ".",
+            "..",
+            "not-a-token",
+            "my-invalid-token.abc",
+            "a.b.c.d",
+        ] {
+            assert_none!(links.verify(token));
+        }
+    }
+}
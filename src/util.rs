@@ -1,3 +1,5 @@
+use axum_extra::extract::cookie::{Cookie, SignedCookieJar};
+
 pub fn error_chain_fmt(
     e: &impl std::error::Error,
     f: &mut std::fmt::Formatter<'_>,
@@ -10,3 +12,32 @@ pub fn error_chain_fmt(
     }
     Ok(())
 }
+
+/// Name of the cookie carrying a one-off message to the next rendered page
+pub const FLASH_COOKIE_NAME: &str = "_flash";
+
+/// Build a flash message cookie
+///
+/// `path` is pinned to `/` so a message set by e.g. `/admin/password`
+/// is still visible after redirecting to `/login`
+pub fn flash_cookie(message: impl Into<String>) -> Cookie<'static> {
+    Cookie::build(FLASH_COOKIE_NAME, message.into())
+        .path("/")
+        .finish()
+}
+
+/// Read the pending flash message, if any, rendered as an HTML snippet
+///
+/// Returns the jar with the flash cookie removed so the message is only shown once
+pub fn take_flash_html(jar: SignedCookieJar) -> (SignedCookieJar, String) {
+    let flash_html = match jar.get(FLASH_COOKIE_NAME) {
+        None => "".into(),
+        Some(cookie) => format!(
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(cookie.value())
+        ),
+    };
+    let jar = jar.remove(Cookie::build(FLASH_COOKIE_NAME, "").path("/").finish());
+
+    (jar, flash_html)
+}
@@ -0,0 +1,123 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_access_the_admin_dashboard() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = test_app.get_admin_dashboard().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = test_app.get_login_html().await;
+    assert!(html_page.contains("<p><i>You must be logged in to access this page.</i></p>"));
+}
+
+#[tokio::test]
+async fn the_admin_dashboard_links_to_the_admin_actions() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.login_as_test_user().await;
+
+    // Act
+    let html_page = test_app.get_admin_dashboard_html().await;
+
+    // Assert
+    assert!(html_page.contains(r#"href="/admin/password""#));
+    assert!(html_page.contains(r#"href="/admin/subscribers""#));
+    assert!(html_page.contains(r#"href="/admin/newsletters""#));
+    assert!(html_page.contains(r#"action="/admin/logout""#));
+}
+
+#[tokio::test]
+async fn an_expired_session_is_rejected() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.login_as_test_user().await;
+    sqlx::query!("UPDATE sessions SET expires_at = now() - interval '1 minute'")
+        .execute(&test_app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = test_app.get_admin_dashboard().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_subscriber_list() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = test_app.get_admin_subscribers().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_subscriber_list_shows_stored_subscribers() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let subscriber_id = uuid::Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO subscriptions (id, email, name, subscribed_at)
+        VALUES ($1, 'ursula_le_guin@gmail.com', 'le guin', now())",
+        subscriber_id
+    )
+    .execute(&test_app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "INSERT INTO list_memberships (list_id, subscriber_id, status, subscribed_at)
+        SELECT list_id, $1, 'confirmed', now() FROM lists WHERE slug = 'newsletter'",
+        subscriber_id
+    )
+    .execute(&test_app.db_pool)
+    .await
+    .unwrap();
+    test_app.login_as_test_user().await;
+
+    // Act
+    let html_page = test_app.get_admin_subscribers().await.text().await.unwrap();
+
+    // Assert
+    assert!(html_page.contains("ursula_le_guin@gmail.com"));
+    assert!(html_page.contains("confirmed"));
+}
+
+#[tokio::test]
+async fn logout_clears_session_state() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act - Part 1 - Login
+    test_app.login_as_test_user().await;
+    let html_page = test_app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", test_app.test_user.username)));
+
+    // Act - Part 2 - Logout
+    let response = test_app.post_logout().await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 3 - Follow the redirect
+    let html_page = test_app.get_login_html().await;
+    assert!(html_page.contains("<p><i>You have successfully logged out.</i></p>"));
+
+    // Act - Part 4 - Attempt to load admin dashboard
+    let response = test_app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Assert - the session is gone server-side too
+    let sessions = sqlx::query!("SELECT session_id FROM sessions")
+        .fetch_all(&test_app.db_pool)
+        .await
+        .unwrap();
+    assert!(sessions.is_empty());
+}
@@ -0,0 +1,381 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::{
+    domain::{AttributeKey, SegmentSlug},
+    segments::{create_segment, NewSegment},
+    subscriber_attributes::{
+        create_attribute_definition, get_attribute_schema, AttributeKind, NewAttributeDefinition,
+    },
+};
+
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber_with, create_rust_weekly,
+    create_unconfirmed_subscriber_with, newsletter_body, spawn_app, PostmarkBatchResponder,
+    TestApp,
+};
+
+async fn define_attribute(app: &TestApp, key: &str, kind: AttributeKind, required: bool) {
+    let options = match kind {
+        AttributeKind::Choice => vec!["FR".into(), "DE".into()],
+        _ => vec![],
+    };
+    let created = create_attribute_definition(
+        &app.db_pool,
+        &NewAttributeDefinition {
+            key: AttributeKey::parse(key.into()).unwrap(),
+            label: key.into(),
+            kind,
+            options,
+            required,
+        },
+    )
+    .await
+    .unwrap();
+    assert!(created);
+}
+
+/// `company` (text), `employees` (number) and `country` (FR or DE), all optional
+async fn define_attributes(app: &TestApp) {
+    define_attribute(app, "company", AttributeKind::Text, false).await;
+    define_attribute(app, "employees", AttributeKind::Number, false).await;
+    define_attribute(app, "country", AttributeKind::Choice, false).await;
+}
+
+async fn mock_confirmation_emails(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+async fn stored_attributes(app: &TestApp) -> serde_json::Value {
+    sqlx::query!("SELECT attributes FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .attributes
+}
+
+#[tokio::test]
+async fn attributes_sent_with_a_form_are_stored_once_confirmed() {
+    // Arrange
+    let app = spawn_app().await;
+    define_attributes(&app).await;
+
+    // Act - Part 1 - Sign up
+    let confirmation_link = create_unconfirmed_subscriber_with(
+        &app,
+        &[
+            ("attributes[company]", " Acme"),
+            ("attributes[employees]", "12"),
+            ("attributes[country]", ""),
+        ],
+    )
+    .await;
+    assert_eq!(stored_attributes(&app).await, serde_json::json!({}));
+
+    // Act - Part 2 - Confirm
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    assert_eq!(
+        stored_attributes(&app).await,
+        serde_json::json!({ "company": "Acme", "employees": 12 })
+    );
+}
+
+#[tokio::test]
+async fn attributes_sent_as_json_are_stored() {
+    // Arrange
+    let app = spawn_app().await;
+    define_attributes(&app).await;
+    mock_confirmation_emails(&app).await;
+
+    // Act
+    app.post_subscriptions_json(serde_json::json!({
+        "name": "le guin",
+        "email": "ursula_le_guin@gmail.com",
+        "attributes": { "country": "FR", "employees": 2.5 },
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    assert_eq!(
+        stored_attributes(&app).await,
+        serde_json::json!({ "country": "FR", "employees": 2.5 })
+    );
+}
+
+#[tokio::test]
+async fn signing_up_to_another_list_does_not_change_the_attributes_of_a_confirmed_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    define_attributes(&app).await;
+    create_rust_weekly(&app).await;
+    create_confirmed_subscriber_with(
+        &app,
+        &[
+            ("attributes[company]", "Acme"),
+            ("attributes[country]", "FR"),
+        ],
+    )
+    .await;
+
+    // Act - Part 1 - Someone signs up with the same address
+    let confirmation_link = create_unconfirmed_subscriber_with(
+        &app,
+        &[
+            ("list", "rust-weekly"),
+            ("attributes[company]", "Evil Corp"),
+        ],
+    )
+    .await;
+    assert_eq!(
+        stored_attributes(&app).await,
+        serde_json::json!({ "company": "Acme", "country": "FR" })
+    );
+
+    // Act - Part 2 - The owner of the address confirms
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    assert_eq!(
+        stored_attributes(&app).await,
+        serde_json::json!({ "company": "Evil Corp", "country": "FR" })
+    );
+}
+
+#[tokio::test]
+async fn attributes_that_do_not_fit_the_schema_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    define_attributes(&app).await;
+    define_attribute(&app, "role", AttributeKind::Text, true).await;
+
+    // Act
+    let response = app
+        .post_subscriptions_json(serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+            "attributes": { "country": "IT", "nickname": "Ursula" },
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    let mut errors: Vec<(String, String)> = problem["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| {
+            (
+                e["field"].as_str().unwrap().into(),
+                e["code"].as_str().unwrap().into(),
+            )
+        })
+        .collect();
+    errors.sort();
+    assert_eq!(
+        errors,
+        vec![
+            ("attributes.country".into(), "invalid_attribute".into()),
+            ("attributes.nickname".into(), "unknown_attribute".into()),
+            ("attributes.role".into(), "missing_attribute".into()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn merge_fields_are_filled_in_for_each_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    define_attributes(&app).await;
+    create_confirmed_subscriber_with(&app, &[("attributes[company]", "<Acme>")]).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::accept_all())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(newsletter_body(serde_json::json!({
+            "title": "Hello {{ name }}",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Hi {{ name }} from {{ attributes.company }} in {{ attributes.country | Europe }}</p>",
+            },
+        })))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+    let sent = app.newsletter_emails().await;
+    assert_eq!(sent[0]["Subject"], "Hello le guin");
+    assert!(sent[0]["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("<p>Hi le guin from &lt;Acme&gt; in Europe</p>"));
+}
+
+#[tokio::test]
+async fn segments_can_filter_on_attributes() {
+    // Arrange
+    let app = spawn_app().await;
+    define_attributes(&app).await;
+    for (email, country) in [("french@example.com", "FR"), ("german@example.com", "DE")] {
+        create_confirmed_subscriber_with(
+            &app,
+            &[("email", email), ("attributes[country]", country)],
+        )
+        .await;
+    }
+    let schema = get_attribute_schema(&app.db_pool).await.unwrap();
+    create_segment(
+        &app.db_pool,
+        &NewSegment {
+            slug: SegmentSlug::parse("france".into()).unwrap(),
+            name: "France".into(),
+            tags: vec![],
+            excluded_tags: vec![],
+            subscribed_after: None,
+            subscribed_before: None,
+            engaged_within_days: None,
+            attributes: schema
+                .validate_filter([("country".into(), "FR".into())])
+                .unwrap(),
+        },
+    )
+    .await
+    .unwrap()
+    .unwrap();
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::accept_all())
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(newsletter_body(serde_json::json!({ "segment": "france" })))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(
+        app.newsletter_recipients().await,
+        vec!["french@example.com"]
+    );
+}
+
+#[tokio::test]
+async fn exports_have_a_column_per_attribute() {
+    // Arrange
+    let app = spawn_app().await;
+    define_attributes(&app).await;
+    create_confirmed_subscriber_with(
+        &app,
+        &[
+            ("name", "=1+1"),
+            ("attributes[company]", "@SUM(A1, A2)"),
+            ("attributes[employees]", "12"),
+        ],
+    )
+    .await;
+    app.login_as_test_user().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/admin/subscribers/export", &app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/csv; charset=utf-8"
+    );
+    let csv = response.text().await.unwrap();
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(
+        lines[0],
+        "email,name,list,status,subscribed_at,tags,company,employees,country"
+    );
+    // Values a spreadsheet would run as formulas are exported as text
+    assert!(lines[1].starts_with("ursula_le_guin@gmail.com,'=1+1,newsletter,confirmed,"));
+    assert!(lines[1].ends_with(",\"'@SUM(A1, A2)\",12,"));
+}
+
+#[tokio::test]
+async fn admins_can_define_attributes() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+
+    // Act - Part 1 - Define the attribute
+    let response = app
+        .api_client
+        .post(format!("{}/admin/attributes", &app.address))
+        .form(&serde_json::json!({
+            "key": "preferred_language",
+            "label": "Preferred language",
+            "kind": "choice",
+            "options": "en, fr, en",
+            "required": "on",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/attributes");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app
+        .api_client
+        .get(format!("{}/admin/attributes", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("The preferred_language attribute has been created."));
+
+    // Act - Part 3 - Signing up now requires it
+    let schema = get_attribute_schema(&app.db_pool).await.unwrap();
+    let definition = &schema.definitions()[0];
+    assert_eq!(definition.options, vec!["en", "fr"]);
+    assert!(definition.required);
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+}
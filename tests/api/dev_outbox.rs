@@ -0,0 +1,124 @@
+use std::path::PathBuf;
+
+use uuid::Uuid;
+use zero2prod::configuration::EmailProvider;
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+async fn spawn_app_with_outbox(serve_listing: bool) -> (TestApp, PathBuf) {
+    let directory = std::env::temp_dir().join(format!("outbox-{}", Uuid::new_v4()));
+    let outbox = directory.to_str().unwrap().to_owned();
+    let app = spawn_app_with(|c| {
+        c.email_client.provider = EmailProvider::Outbox {
+            directory: outbox,
+            serve_listing,
+        }
+    })
+    .await;
+    (app, directory)
+}
+
+#[tokio::test]
+async fn subscribing_writes_the_confirmation_email_to_the_outbox() {
+    // Arrange
+    let (app, directory) = spawn_app_with_outbox(false).await;
+
+    // Act
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+
+    let files: Vec<_> = std::fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(files.len(), 2);
+    assert!(files.iter().any(|f| f.extension().unwrap() == "eml"));
+    assert!(files.iter().any(|f| f.extension().unwrap() == "json"));
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[tokio::test]
+async fn confirmation_links_can_be_followed_from_the_outbox_listing() {
+    // Arrange
+    let (app, directory) = spawn_app_with_outbox(true).await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act - Part 1 - The listing links to the message
+    let listing = app.get_outbox().await;
+    assert_eq!(listing.status().as_u16(), 200);
+    let listing = listing.text().await.unwrap();
+    assert!(listing.contains("ursula_le_guin@gmail.com"));
+    let message_id = listing
+        .split(r#"<a href="/dev/outbox/"#)
+        .nth(1)
+        .and_then(|rest| rest.split('"').next())
+        .unwrap()
+        .to_owned();
+
+    // Act - Part 2 - The message has the confirmation link
+    let message = app.get_outbox_message(&message_id).await;
+    assert_eq!(message.status().as_u16(), 200);
+    let message = message.text().await.unwrap();
+    let confirmation_link = linkify::LinkFinder::new()
+        .links(&message)
+        .map(|l| l.as_str().to_owned())
+        .find(|l| l.contains("/subscriptions/confirm"))
+        .unwrap();
+    let mut confirmation_link = reqwest::Url::parse(&confirmation_link).unwrap();
+    confirmation_link.set_port(Some(app.port)).unwrap();
+
+    // Act - Part 3 - Follow it
+    reqwest::get(confirmation_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let saved = sqlx::query!("SELECT status FROM list_memberships")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[tokio::test]
+async fn the_outbox_listing_is_only_served_when_enabled() {
+    // Arrange
+    let app = spawn_app().await;
+    let (outbox_app, directory) = spawn_app_with_outbox(false).await;
+
+    // Act
+    let response = app.get_outbox().await;
+    let outbox_response = outbox_app.get_outbox().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+    assert_eq!(outbox_response.status().as_u16(), 404);
+
+    let _ = std::fs::remove_dir_all(directory);
+}
+
+#[tokio::test]
+async fn unknown_outbox_messages_are_a_404() {
+    // Arrange
+    let (app, directory) = spawn_app_with_outbox(true).await;
+
+    // Act
+    let response = app.get_outbox_message(&Uuid::new_v4().to_string()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+
+    let _ = std::fs::remove_dir_all(directory);
+}
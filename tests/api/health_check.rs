@@ -1,4 +1,9 @@
-use crate::helpers::spawn_app;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, spawn_app_with};
 
 #[tokio::test]
 async fn health_check_works() {
@@ -13,5 +18,102 @@ async fn health_check_works() {
         .expect("failed to execute request");
 
     assert!(response.status().is_success());
-    assert_eq!(Some(0), response.content_length());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "ok");
+    assert_eq!(body["email_provider"]["circuit_breaker"], "closed");
+}
+
+#[tokio::test]
+async fn the_instance_is_ready_when_the_database_is_up_to_date() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = test_app.get_health_ready().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "ready");
+    for check in ["database", "migrations"] {
+        assert_eq!(body["checks"][check]["status"], "ok");
+        assert!(body["checks"][check]["latency_ms"].is_number());
+    }
+    // Not pinged unless asked to
+    assert_eq!(
+        body["checks"]["email_provider"],
+        serde_json::json!({ "status": "skipped" })
+    );
+}
+
+#[tokio::test]
+async fn the_instance_is_not_ready_with_pending_migrations() {
+    // Arrange
+    let test_app = spawn_app().await;
+    sqlx::query!(
+        "DELETE FROM _sqlx_migrations WHERE version = (SELECT MAX(version) FROM _sqlx_migrations)"
+    )
+    .execute(&test_app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = test_app.get_health_ready().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "not_ready");
+    assert_eq!(body["checks"]["database"]["status"], "ok");
+    // Which migrations are pending is only logged
+    let migrations = body["checks"]["migrations"].as_object().unwrap();
+    assert_eq!(migrations["status"], "failing");
+    assert_eq!(
+        migrations.keys().collect::<Vec<_>>(),
+        vec!["latency_ms", "status"]
+    );
+}
+
+#[tokio::test]
+async fn an_unreachable_email_provider_is_reported_without_failing_readiness() {
+    // Arrange
+    let test_app = spawn_app_with(|c| c.email_client.ping_on_readiness = true).await;
+
+    Mock::given(path("/server"))
+        .and(method("GET"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app.get_health_ready().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "ready");
+    assert_eq!(body["checks"]["email_provider"]["status"], "failing");
+    assert!(body["checks"]["email_provider"]["latency_ms"].is_number());
+    assert!(body["checks"]["email_provider"].get("error").is_none());
+}
+
+#[tokio::test]
+async fn a_reachable_email_provider_is_reported_up() {
+    // Arrange
+    let test_app = spawn_app_with(|c| c.email_client.ping_on_readiness = true).await;
+
+    Mock::given(path("/server"))
+        .and(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({"ID": 1})))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app.get_health_ready().await;
+
+    // Assert
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["checks"]["email_provider"]["status"], "ok");
 }
@@ -1,12 +1,24 @@
+use std::sync::Arc;
+
 use argon2::{password_hash::SaltString, Algorithm, Argon2, Params, PasswordHasher, Version};
 use once_cell::sync::Lazy;
+use opentelemetry::{sdk::trace::TracerProvider, trace::TracerProvider as _};
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
-use wiremock::MockServer;
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockServer, Respond, ResponseTemplate,
+};
 use zero2prod::{
-    configuration::{get_configuration, DatabaseSettings},
+    configuration::{get_configuration, DatabaseSettings, EmailProvider, Settings},
+    domain::ListSlug,
+    email_client::EmailSender,
+    issue_delivery_worker::{try_execute_task, ExecutionOutcome},
+    mailing_lists::create_list,
+    metrics::Metrics,
     startup::{build, get_connection_pool},
     telemetry::{get_subscriber, init_subscriber},
+    unsubscribe_links::UnsubscribeLinks,
 };
 
 // To see all logs
@@ -16,11 +28,27 @@ static TRACING: Lazy<()> = Lazy::new(|| {
     let default_filter_level = "info".to_string();
     let subscriber_name = "test".to_string();
 
+    // Spans get trace ids to propagate, without being exported anywhere.
+    // The global provider keeps the tracer alive
+    let provider = TracerProvider::builder().build();
+    let tracer = provider.tracer("test");
+    opentelemetry::global::set_tracer_provider(provider);
+
     if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::stdout);
+        let subscriber = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            std::io::stdout,
+            Some(tracer),
+        );
         init_subscriber(subscriber);
     } else {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink);
+        let subscriber = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            std::io::sink,
+            Some(tracer),
+        );
         init_subscriber(subscriber);
     }
 });
@@ -72,6 +100,8 @@ pub struct TestApp {
     pub port: u16,
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
+    pub email_client: Arc<dyn EmailSender>,
+    pub unsubscribe_links: UnsubscribeLinks,
 }
 
 /// Confirmation links embedded in the request to the email API
@@ -80,10 +110,97 @@ pub struct ConfirmationLinks {
     pub plain_text: reqwest::Url,
 }
 
+/// Unsubscribe links embedded in a newsletter issue sent through the email API
+pub struct UnsubscribeEmailLinks {
+    pub html: reqwest::Url,
+    pub plain_text: reqwest::Url,
+}
+
 impl TestApp {
+    /// Attempt every due newsletter delivery before returning
+    ///
+    /// The background worker spawned by `build` may be holding a task,
+    /// so keep polling until no due task is left. Deliveries rescheduled
+    /// for a later retry are left in the queue
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            let outcome = try_execute_task(
+                &self.db_pool,
+                self.email_client.as_ref(),
+                &self.unsubscribe_links,
+            )
+            .await
+            .unwrap();
+            // Nothing can be delivered until the circuit closes again
+            if let ExecutionOutcome::ProviderUnavailable = outcome {
+                break;
+            }
+            if let ExecutionOutcome::EmptyQueue = outcome {
+                let remaining = sqlx::query!(
+                    r#"
+                    SELECT COUNT(*) as "count!"
+                    FROM issue_delivery_queue
+                    WHERE next_attempt_at <= now()
+                    "#
+                )
+                .fetch_one(&self.db_pool)
+                .await
+                .unwrap()
+                .count;
+                if remaining == 0 {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        }
+    }
+
+    /// Every email sent through the batch API, i.e. newsletter issues
+    pub async fn newsletter_emails(&self) -> Vec<serde_json::Value> {
+        self.email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .filter(|r| r.url.path() == "/email/batch")
+            .flat_map(|r| serde_json::from_slice::<Vec<serde_json::Value>>(&r.body).unwrap())
+            .collect()
+    }
+
+    /// Who got a newsletter issue, sorted
+    pub async fn newsletter_recipients(&self) -> Vec<String> {
+        let mut recipients: Vec<String> = self
+            .newsletter_emails()
+            .await
+            .iter()
+            .map(|email| email["To"].as_str().unwrap().to_owned())
+            .collect();
+        recipients.sort();
+        recipients
+    }
+
     /// Extract the confirmation links embedded in the request to the email API
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
-        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let (html, plain_text) = self.get_links(email_request);
+        ConfirmationLinks { html, plain_text }
+    }
+
+    /// Extract the unsubscribe links embedded in a newsletter issue
+    pub fn get_unsubscribe_links(
+        &self,
+        email_request: &wiremock::Request,
+    ) -> UnsubscribeEmailLinks {
+        let (html, plain_text) = self.get_links(email_request);
+        UnsubscribeEmailLinks { html, plain_text }
+    }
+
+    /// Extract the only link of the HTML and of the text body of an email
+    fn get_links(&self, email_request: &wiremock::Request) -> (reqwest::Url, reqwest::Url) {
+        let mut body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        // Newsletter issues go out through the batch endpoint
+        if body.is_array() {
+            body = body[0].take();
+        }
 
         // Extract the link from one of the request fields
         let get_link = |s: &str| {
@@ -105,12 +222,96 @@ impl TestApp {
 
         let html = get_link(body["HtmlBody"].as_str().unwrap());
         let plain_text = get_link(body["TextBody"].as_str().unwrap());
-        ConfirmationLinks { html, plain_text }
+        (html, plain_text)
+    }
+
+    pub async fn get_unsubscribe(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/subscriptions/unsubscribe", &self.address))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// One-click unsubscribe, the way RFC 8058 mail clients do it
+    pub async fn post_unsubscribe(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/subscriptions/unsubscribe", &self.address))
+            .query(&[("token", token)])
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body("List-Unsubscribe=One-Click")
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_resend_confirmation(&self, body: String) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/subscriptions/resend-confirmation",
+                &self.address
+            ))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_outbox(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/dev/outbox", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_outbox_message(&self, message_id: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/dev/outbox/{}", &self.address, message_id))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_health_check(&self) -> serde_json::Value {
+        self.api_client
+            .get(format!("{}/health_check", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_metrics(&self) -> String {
+        self.api_client
+            .get(format!("{}/metrics", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .error_for_status()
+            .unwrap()
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_health_ready(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/health/ready", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
     }
 
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -118,9 +319,19 @@ impl TestApp {
             .expect("Failed to execute request")
     }
 
+    pub async fn post_subscriptions_json(&self, body: serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/subscriptions", &self.address))
+            .header("Accept", "application/json")
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
     pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/newsletters", &self.address))
+            .post(format!("{}/newsletters", &self.address))
             // Add random creds for basic auth
             .basic_auth(&self.test_user.username, Some(&self.test_user.password))
             .json(&body)
@@ -129,12 +340,27 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn post_newsletters_with_idempotency_key(
+        &self,
+        body: serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Idempotency-Key", idempotency_key)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
     where
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/login", &self.address))
+            .post(format!("{}/login", &self.address))
             // This `reqwest` method makes sure the body is URL-encoded
             // and the `Content-Type` header is set
             .form(body)
@@ -143,9 +369,94 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn get_admin_dashboard(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/dashboard", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_dashboard_html(&self) -> String {
+        self.get_admin_dashboard().await.text().await.unwrap()
+    }
+
+    pub async fn get_admin_subscribers(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/subscribers", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_publish_newsletter(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/newsletters", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_publish_newsletter_html(&self) -> String {
+        self.get_publish_newsletter().await.text().await.unwrap()
+    }
+
+    pub async fn post_publish_newsletter<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/newsletters", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_change_password(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/password", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_change_password_html(&self) -> String {
+        self.get_change_password().await.text().await.unwrap()
+    }
+
+    pub async fn post_change_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/password", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/logout", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Log in as the test user, so the cookie store holds a valid session
+    pub async fn login_as_test_user(&self) {
+        self.post_login(&serde_json::json!({
+            "username": &self.test_user.username,
+            "password": &self.test_user.password
+        }))
+        .await;
+    }
+
     pub async fn get_login_html(&self) -> String {
         self.api_client
-            .get(&format!("{}/login", &self.address))
+            .get(format!("{}/login", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -155,12 +466,115 @@ impl TestApp {
     }
 }
 
+/// Use the public API of the application under test to create
+/// an unconfirmed subscriber.
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    create_unconfirmed_subscriber_with(app, &[]).await
+}
+
+/// Same as `create_unconfirmed_subscriber`, with extra form `fields`
+/// (e.g. `list` or `tags`), which take over the default `name` and `email`
+pub async fn create_unconfirmed_subscriber_with(
+    app: &TestApp,
+    fields: &[(&str, &str)],
+) -> ConfirmationLinks {
+    let mut form = vec![("name", "le guin"), ("email", "ursula_le_guin@gmail.com")];
+    for &(key, value) in fields {
+        match form.iter_mut().find(|(k, _)| *k == key) {
+            Some(field) => field.1 = value,
+            None => form.push((key, value)),
+        }
+    }
+    let body: Vec<_> = form
+        .iter()
+        .map(|(key, value)| {
+            format!(
+                "{}={}",
+                urlencoding::encode(key),
+                urlencoding::encode(value)
+            )
+        })
+        .collect();
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(body.join("&"))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Inspect request received by the mock Postmark server
+    // to retrieve the confirmation link and return it
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+
+    app.get_confirmation_links(email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    create_confirmed_subscriber_with(app, &[]).await;
+}
+
+/// Same as `create_confirmed_subscriber`, with extra form `fields`
+pub async fn create_confirmed_subscriber_with(app: &TestApp, fields: &[(&str, &str)]) {
+    // Can reuse the above helper and just add an extra step
+    let confirmation_link = create_unconfirmed_subscriber_with(app, fields).await;
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+/// A second list, next to the default `newsletter` one
+pub async fn create_rust_weekly(app: &TestApp) {
+    create_list(
+        &app.db_pool,
+        &ListSlug::parse("rust-weekly".into()).unwrap(),
+        "Rust Weekly",
+    )
+    .await
+    .unwrap()
+    .unwrap();
+}
+
+/// A valid `POST /newsletters` body, `fields` are added at the top level
+/// or take over the default ones (e.g. `list`, `segment` or `content`)
+pub fn newsletter_body(fields: serde_json::Value) -> serde_json::Value {
+    let mut body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+    });
+    if let serde_json::Value::Object(fields) = fields {
+        body.as_object_mut().unwrap().extend(fields);
+    }
+    body
+}
+
 pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
     assert_eq!(response.status().as_u16(), 303);
     assert_eq!(response.headers().get("Location").unwrap(), location);
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Same as `spawn_app`, letting the test tweak the configuration first
+pub async fn spawn_app_with(customise: impl FnOnce(&mut Settings)) -> TestApp {
     // The first time `spawn_app` is invoked the code in `TRACING` is executed
     // All other invocations will skip it
     Lazy::force(&TRACING);
@@ -177,8 +591,11 @@ pub async fn spawn_app() -> TestApp {
         c.application.port = 0;
 
         // Use the mock server as email API
+        c.email_client.provider = EmailProvider::Postmark;
         c.email_client.base_url = email_server.uri();
 
+        customise(&mut c);
+
         c
     };
 
@@ -192,13 +609,13 @@ pub async fn spawn_app() -> TestApp {
     let port = address
         .as_str()
         .split(':')
-        .last()
+        .next_back()
         .expect("Failed to get application port")
         .parse::<u16>()
         .expect("Failed to convert port to u16");
 
     // TODO: Lookup and comment why we have to do this cause I forgot
-    let _ = tokio::spawn(server);
+    tokio::spawn(server);
 
     let client = reqwest::Client::builder()
         .redirect(reqwest::redirect::Policy::none())
@@ -206,6 +623,7 @@ pub async fn spawn_app() -> TestApp {
         .build()
         .unwrap();
 
+    let email_circuit_breaker = configuration.email_client.circuit_breaker();
     let test_app = TestApp {
         address,
         db_pool: get_connection_pool(&configuration.database),
@@ -213,6 +631,13 @@ pub async fn spawn_app() -> TestApp {
         port,
         test_user: TestUser::generate(),
         api_client: client,
+        email_client: configuration
+            .email_client
+            .client(email_circuit_breaker, Arc::new(Metrics::new())),
+        unsubscribe_links: UnsubscribeLinks::new(
+            configuration.application.base_url.clone(),
+            configuration.application.hmac_secret.clone(),
+        ),
     };
 
     test_app.test_user.store(&test_app.db_pool).await;
@@ -240,3 +665,58 @@ async fn configure_database(config: &DatabaseSettings) -> PgPool {
 
     connection_pool
 }
+
+/// Answers Postmark batch requests with one result per message,
+/// rejecting the messages addressed to `rejected_recipients`
+pub struct PostmarkBatchResponder {
+    rejected_recipients: Vec<String>,
+    delay: Option<std::time::Duration>,
+}
+
+impl PostmarkBatchResponder {
+    pub fn accept_all() -> Self {
+        Self {
+            rejected_recipients: vec![],
+            delay: None,
+        }
+    }
+
+    pub fn rejecting(recipient: &str) -> Self {
+        Self {
+            rejected_recipients: vec![recipient.to_owned()],
+            delay: None,
+        }
+    }
+
+    pub fn with_delay(self, delay: std::time::Duration) -> Self {
+        Self {
+            delay: Some(delay),
+            ..self
+        }
+    }
+}
+
+impl Respond for PostmarkBatchResponder {
+    fn respond(&self, request: &wiremock::Request) -> ResponseTemplate {
+        let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+        let results: Vec<_> = messages
+            .iter()
+            .map(|message| {
+                let recipient = message["To"].as_str().unwrap();
+                if self.rejected_recipients.iter().any(|r| r == recipient) {
+                    serde_json::json!({
+                        "ErrorCode": 406,
+                        "Message": "You tried to send to a recipient that has been marked as inactive.",
+                    })
+                } else {
+                    serde_json::json!({ "ErrorCode": 0, "Message": "OK" })
+                }
+            })
+            .collect();
+        let response = ResponseTemplate::new(200).set_body_json(results);
+        match self.delay {
+            Some(delay) => response.set_delay(delay),
+            None => response,
+        }
+    }
+}
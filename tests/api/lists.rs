@@ -0,0 +1,225 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock,
+};
+
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_confirmed_subscriber_with,
+    create_rust_weekly, create_unconfirmed_subscriber_with, newsletter_body, spawn_app,
+    PostmarkBatchResponder, TestApp,
+};
+
+async fn membership_statuses(app: &TestApp) -> Vec<(String, String)> {
+    sqlx::query!(
+        r#"
+        SELECT l.slug, m.status
+        FROM list_memberships m
+        JOIN lists l ON l.list_id = m.list_id
+        ORDER BY l.slug
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| (r.slug, r.status))
+    .collect()
+}
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_list_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com&list=nope".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["errors"][0]["field"], "list");
+    assert_eq!(problem["errors"][0]["code"], "unknown_list");
+}
+
+#[tokio::test]
+async fn confirmation_is_tracked_per_list() {
+    // Arrange
+    let app = spawn_app().await;
+    create_rust_weekly(&app).await;
+    create_unconfirmed_subscriber_with(&app, &[("list", "newsletter")]).await;
+    let rust_weekly = create_unconfirmed_subscriber_with(&app, &[("list", "rust-weekly")]).await;
+
+    // Act
+    reqwest::get(rust_weekly.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    assert_eq!(
+        membership_statuses(&app).await,
+        vec![
+            ("newsletter".into(), "pending_confirmation".into()),
+            ("rust-weekly".into(), "confirmed".into()),
+        ]
+    );
+    let n_subscribers = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 1);
+}
+
+#[tokio::test]
+async fn issues_only_go_to_the_confirmed_members_of_their_list() {
+    // Arrange
+    let app = spawn_app().await;
+    create_rust_weekly(&app).await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::accept_all())
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(newsletter_body(
+            serde_json::json!({ "list": "rust-weekly" }),
+        ))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[tokio::test]
+async fn issues_are_delivered_to_the_confirmed_members_of_their_list() {
+    // Arrange
+    let app = spawn_app().await;
+    create_rust_weekly(&app).await;
+    create_confirmed_subscriber_with(&app, &[("list", "rust-weekly")]).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::accept_all())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(newsletter_body(
+            serde_json::json!({ "list": "rust-weekly" }),
+        ))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(
+        app.newsletter_recipients().await,
+        vec!["ursula_le_guin@gmail.com"]
+    );
+}
+
+#[tokio::test]
+async fn publishing_to_an_unknown_list_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_newsletters(newsletter_body(serde_json::json!({ "list": "nope" })))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["errors"][0]["code"], "unknown_list");
+}
+
+#[tokio::test]
+async fn unsubscribing_only_leaves_the_list_of_the_link() {
+    // Arrange
+    let app = spawn_app().await;
+    create_rust_weekly(&app).await;
+    let newsletter_links =
+        create_unconfirmed_subscriber_with(&app, &[("list", "newsletter")]).await;
+    let rust_weekly_links =
+        create_unconfirmed_subscriber_with(&app, &[("list", "rust-weekly")]).await;
+    for link in [newsletter_links.html, rust_weekly_links.html] {
+        reqwest::get(link)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+    let membership = sqlx::query!(
+        "SELECT subscriber_id, m.list_id FROM list_memberships m
+        JOIN lists l ON l.list_id = m.list_id WHERE l.slug = 'rust-weekly'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    app.post_unsubscribe(
+        &app.unsubscribe_links
+            .token(membership.subscriber_id, membership.list_id),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    // Assert
+    assert_eq!(
+        membership_statuses(&app).await,
+        vec![
+            ("newsletter".into(), "confirmed".into()),
+            ("rust-weekly".into(), "unsubscribed".into()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn admins_can_create_lists() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+
+    // Act - Part 1 - Create the list
+    let response = app
+        .api_client
+        .post(format!("{}/admin/lists", &app.address))
+        .form(&serde_json::json!({ "slug": "rust-weekly", "name": "Rust Weekly" }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/lists");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app
+        .api_client
+        .get(format!("{}/admin/lists", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("The rust-weekly list has been created."));
+    assert!(html_page.contains("Rust Weekly"));
+
+    // Act - Part 3 - The list can be picked when publishing
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains(">Rust Weekly</option>"));
+}
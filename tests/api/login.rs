@@ -23,3 +23,73 @@ async fn an_error_flash_message_is_set_on_failure() {
     let html_page = test_app.get_login_html().await;
     assert!(!html_page.contains(r#"<p><i>Authentication failed</i></p>"#));
 }
+
+#[tokio::test]
+async fn a_session_is_created_on_login_success() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let login_body = serde_json::json!({
+      "username": &test_app.test_user.username,
+      "password": &test_app.test_user.password
+    });
+    let response = test_app.post_login(&login_body).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    assert!(response
+        .cookies()
+        .any(|cookie| cookie.name() == "session_id" && cookie.http_only()));
+
+    let saved = sqlx::query!("SELECT user_id FROM sessions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .expect("Failed to fetch saved session.");
+    assert_eq!(saved.user_id, test_app.test_user.user_id);
+}
+
+#[tokio::test]
+async fn the_session_id_is_rotated_on_every_login() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let login_body = serde_json::json!({
+      "username": &test_app.test_user.username,
+      "password": &test_app.test_user.password
+    });
+
+    // Act - Part 1 - Login
+    test_app.post_login(&login_body).await;
+    let first_session = sqlx::query!("SELECT session_id FROM sessions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .expect("Failed to fetch saved session.");
+
+    // Act - Part 2 - Login again with the session cookie still set
+    test_app.post_login(&login_body).await;
+    let second_session = sqlx::query!("SELECT session_id FROM sessions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .expect("Failed to fetch saved session.");
+
+    // Assert - the previous session was replaced, not kept around
+    assert_ne!(first_session.session_id, second_session.session_id);
+}
+
+#[tokio::test]
+async fn redirect_to_admin_dashboard_after_login_success() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act - Part 1 - Login
+    let login_body = serde_json::json!({
+      "username": &test_app.test_user.username,
+      "password": &test_app.test_user.password
+    });
+    let response = test_app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = test_app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", test_app.test_user.username)));
+}
@@ -1,6 +1,16 @@
+mod admin_dashboard;
+mod attributes;
+mod change_password;
+mod dev_outbox;
 mod health_check;
 mod helpers;
+mod lists;
 mod login;
+mod metrics;
 mod newsletters;
+mod request_id;
+mod segments;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_resend_confirmation;
+mod subscriptions_unsubscribe;
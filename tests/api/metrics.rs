@@ -0,0 +1,86 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app};
+
+#[tokio::test]
+async fn requests_are_counted_per_route_and_status() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    for _ in 0..2 {
+        test_app.get_login_html().await;
+    }
+    test_app
+        .api_client
+        .get(format!("{}/subscriptions/confirm", &test_app.address))
+        .send()
+        .await
+        .unwrap();
+    test_app
+        .api_client
+        .get(format!("{}/does-not-exist/{}", &test_app.address, 42))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    let metrics = test_app.get_metrics().await;
+    assert!(metrics.contains(r#"http_requests_total{method="GET",route="/login",status="200"} 2"#));
+    assert!(metrics.contains(
+        r#"http_requests_total{method="GET",route="/subscriptions/confirm",status="400"} 1"#
+    ));
+    // Unknown paths share a single series
+    assert!(
+        metrics.contains(r#"http_requests_total{method="GET",route="unmatched",status="404"} 1"#)
+    );
+    assert!(metrics.contains(
+        r#"http_request_duration_seconds_count{method="GET",route="/login",status="200"} 2"#
+    ));
+}
+
+#[tokio::test]
+async fn subscriptions_and_emails_are_counted() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    create_confirmed_subscriber(&test_app).await;
+
+    // Assert
+    let metrics = test_app.get_metrics().await;
+    assert!(metrics.contains("subscriptions_created_total 1"));
+    assert!(metrics.contains("subscriptions_confirmed_total 1"));
+    assert!(metrics.contains(r#"emails_sent_total{provider="postmark"} 1"#));
+}
+
+#[tokio::test]
+async fn logins_are_counted_by_outcome() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    test_app
+        .post_login(&serde_json::json!({
+            "username": "random-username",
+            "password": "random-password"
+        }))
+        .await;
+    test_app.login_as_test_user().await;
+
+    // Assert
+    let metrics = test_app.get_metrics().await;
+    assert!(metrics.contains(r#"logins_total{outcome="failure"} 1"#));
+    assert!(metrics.contains(r#"logins_total{outcome="success"} 1"#));
+}
+
+#[tokio::test]
+async fn pool_stats_are_exposed() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let metrics = test_app.get_metrics().await;
+
+    // Assert
+    assert!(metrics.contains("db_pool_connections "));
+    assert!(metrics.contains("db_pool_idle_connections "));
+}
@@ -4,7 +4,12 @@ use wiremock::{
     Mock, ResponseTemplate,
 };
 
-use crate::helpers::{spawn_app, ConfirmationLinks, TestApp};
+use zero2prod::issue_delivery_worker::MAX_DELIVERY_ATTEMPTS;
+
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app,
+    PostmarkBatchResponder,
+};
 
 #[tokio::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
@@ -32,6 +37,7 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
 
     // Assert
     assert_eq!(response.status().as_u16(), 200);
+    test_app.dispatch_all_pending_emails().await;
 }
 
 #[tokio::test]
@@ -40,9 +46,9 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     let test_app = spawn_app().await;
     create_confirmed_subscriber(&test_app).await;
 
-    Mock::given(path("/email"))
+    Mock::given(path("/email/batch"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(PostmarkBatchResponder::accept_all())
         .expect(1)
         .mount(&test_app.email_server)
         .await;
@@ -60,6 +66,7 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
 
     // Assert
     assert_eq!(response.status().as_u16(), 200);
+    test_app.dispatch_all_pending_emails().await;
 }
 
 #[tokio::test]
@@ -101,7 +108,7 @@ async fn requests_missing_authorization_are_rejected() {
     let test_app = spawn_app().await;
 
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &test_app.address))
+        .post(format!("{}/newsletters", &test_app.address))
         .json(&serde_json::json!({
             "title": "Newsletter title",
             "content": {
@@ -119,6 +126,13 @@ async fn requests_missing_authorization_are_rejected() {
         r#"Basic realm="publish""#,
         response.headers()["WWW-Authenticate"]
     );
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["status"], 401);
+    assert_eq!(problem["code"], "invalid_credentials");
 }
 
 #[tokio::test]
@@ -131,7 +145,7 @@ async fn non_existing_user_is_rejected() {
     let password = Uuid::new_v4().to_string();
 
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &test_app.address))
+        .post(format!("{}/newsletters", &test_app.address))
         .basic_auth(username, Some(password))
         .json(&serde_json::json!({
             "title": "Newsletter title",
@@ -162,7 +176,7 @@ async fn invalid_password_is_rejected() {
     assert_ne!(test_app.test_user.password, password);
 
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &test_app.address))
+        .post(format!("{}/newsletters", &test_app.address))
         .basic_auth(username, Some(password))
         .json(&serde_json::json!({
             "title": "Newsletter title",
@@ -183,42 +197,478 @@ async fn invalid_password_is_rejected() {
     );
 }
 
-/// Use the public API of the application under test to create
-/// an unconfirmed subscriber.
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_the_newsletter_form() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = test_app.get_publish_newsletter().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
 
-    let _mock_guard = Mock::given(path("/email"))
+#[tokio::test]
+async fn you_must_be_logged_in_to_publish_a_newsletter_from_the_form() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    });
+    let response = test_app
+        .post_publish_newsletter(&newsletter_request_body)
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn newsletters_are_delivered_to_confirmed_subscribers_from_the_form() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    test_app.login_as_test_user().await;
+
+    Mock::given(path("/email/batch"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
+        .respond_with(PostmarkBatchResponder::accept_all())
         .expect(1)
-        .mount_as_scoped(&app.email_server)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act - Part 1 - Submit newsletter form
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    });
+    let response = test_app
+        .post_publish_newsletter(&newsletter_request_body)
         .await;
-    app.post_subscriptions(body.into())
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = test_app.get_publish_newsletter_html().await;
+    assert!(html_page.contains(
+        "<p><i>The newsletter issue has been accepted - \
+        emails will go out shortly.</i></p>"
+    ));
+    test_app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletter_creation_is_idempotent() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    test_app.login_as_test_user().await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::accept_all())
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act - Part 1 - Submit newsletter form
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    });
+    let response = test_app
+        .post_publish_newsletter(&newsletter_request_body)
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = test_app.get_publish_newsletter_html().await;
+    assert!(html_page.contains(
+        "<p><i>The newsletter issue has been accepted - \
+        emails will go out shortly.</i></p>"
+    ));
+
+    // Act - Part 3 - Submit newsletter form **again**
+    let response = test_app
+        .post_publish_newsletter(&newsletter_request_body)
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Act - Part 4 - Follow the redirect
+    let html_page = test_app.get_publish_newsletter_html().await;
+    assert!(html_page.contains(
+        "<p><i>The newsletter issue has been accepted - \
+        emails will go out shortly.</i></p>"
+    ));
+
+    test_app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we have sent the newsletter email **once**
+}
+
+#[tokio::test]
+async fn concurrent_form_submission_is_handled_gracefully() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    test_app.login_as_test_user().await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        // Setting a long delay to ensure that the second request
+        // arrives before the first one completes
+        .respond_with(
+            PostmarkBatchResponder::accept_all().with_delay(std::time::Duration::from_secs(2)),
+        )
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act - Submit two newsletter forms concurrently
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    });
+    let response1 = test_app.post_publish_newsletter(&newsletter_request_body);
+    let response2 = test_app.post_publish_newsletter(&newsletter_request_body);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    // Assert
+    assert_eq!(response1.status(), response2.status());
+    assert_eq!(
+        response1.text().await.unwrap(),
+        response2.text().await.unwrap()
+    );
+
+    test_app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we have sent the newsletter email **once**
+}
+
+#[tokio::test]
+async fn api_requests_with_the_same_idempotency_key_publish_once() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::accept_all())
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act - Publish the same issue twice, e.g. a retry after a timeout
+    let newsletter_request_body = serde_json::json!({
+      "title": "Newsletter title",
+      "content": {
+        "text": "Newsletter body as plain text",
+        "html": "<p>Newsletter body as HTML</p>",
+      }
+    });
+    let idempotency_key = Uuid::new_v4().to_string();
+    let response1 = test_app
+        .post_newsletters_with_idempotency_key(newsletter_request_body.clone(), &idempotency_key)
+        .await;
+    let response2 = test_app
+        .post_newsletters_with_idempotency_key(newsletter_request_body, &idempotency_key)
+        .await;
+
+    // Assert
+    assert_eq!(response1.status().as_u16(), 200);
+    assert_eq!(response2.status().as_u16(), 200);
+
+    test_app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we have sent the newsletter email **once**
+}
+
+#[tokio::test]
+async fn an_invalid_idempotency_key_is_rejected_with_a_400() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+      "title": "Newsletter title",
+      "content": {
+        "text": "Newsletter body as plain text",
+        "html": "<p>Newsletter body as HTML</p>",
+      }
+    });
+    let response = test_app
+        .post_newsletters_with_idempotency_key(newsletter_request_body, &"a".repeat(50))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["code"], "invalid_idempotency_key");
+}
+
+#[tokio::test]
+async fn publishing_does_not_wait_for_the_email_provider() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+      "title": "Newsletter title",
+      "content": {
+        "text": "Newsletter body as plain text",
+        "html": "<p>Newsletter body as HTML</p>",
+      }
+    });
+    let response = test_app.post_newsletters(newsletter_request_body).await;
+
+    // Assert - the issue is accepted and stored, the failure happens in the background
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT title FROM newsletter_issues")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .expect("Failed to fetch saved newsletter issue.");
+    assert_eq!(saved.title, "Newsletter title");
+    test_app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn transient_delivery_failures_are_retried_later() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    let newsletter_request_body = serde_json::json!({
+      "title": "Newsletter title",
+      "content": {
+        "text": "Newsletter body as plain text",
+        "html": "<p>Newsletter body as HTML</p>",
+      }
+    });
+
+    // Act - Part 1 - The provider is having a bad day
+    {
+        let _mock_guard = Mock::given(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount_as_scoped(&test_app.email_server)
+            .await;
+        test_app.post_newsletters(newsletter_request_body).await;
+        test_app.dispatch_all_pending_emails().await;
+    }
+
+    // Assert - the delivery is rescheduled
+    let task = sqlx::query!(
+        "SELECT n_attempts, next_attempt_at > now() as scheduled_later FROM issue_delivery_queue"
+    )
+    .fetch_one(&test_app.db_pool)
+    .await
+    .expect("The failed delivery was not kept in the queue.");
+    assert_eq!(task.n_attempts, 1);
+    assert_eq!(task.scheduled_later, Some(true));
+
+    // Act - Part 2 - The provider recovered and the retry is due
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::accept_all())
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+    sqlx::query!("UPDATE issue_delivery_queue SET next_attempt_at = now()")
+        .execute(&test_app.db_pool)
         .await
-        .error_for_status()
         .unwrap();
+    test_app.dispatch_all_pending_emails().await;
 
-    // Inspect request received by the mock Postmark server
-    // to retrieve the confirmation link and return it
-    let email_request = &app
-        .email_server
-        .received_requests()
+    // Assert - delivered, nothing left behind
+    let queued = sqlx::query!("SELECT n_attempts FROM issue_delivery_queue")
+        .fetch_all(&test_app.db_pool)
         .await
-        .unwrap()
-        .pop()
         .unwrap();
+    assert!(queued.is_empty());
+    let dead_letters = sqlx::query!("SELECT n_attempts FROM issue_delivery_dead_letters")
+        .fetch_all(&test_app.db_pool)
+        .await
+        .unwrap();
+    assert!(dead_letters.is_empty());
+}
+
+#[tokio::test]
+async fn throttled_deliveries_are_not_retried_before_the_retry_after() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "3600"))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+      "title": "Newsletter title",
+      "content": {
+        "text": "Newsletter body as plain text",
+        "html": "<p>Newsletter body as HTML</p>",
+      }
+    });
+    test_app.post_newsletters(newsletter_request_body).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let task = sqlx::query!(
+        "SELECT n_attempts, next_attempt_at > now() + interval '59 minutes' as after_retry_after \
+        FROM issue_delivery_queue"
+    )
+    .fetch_one(&test_app.db_pool)
+    .await
+    .expect("The throttled delivery was not kept in the queue.");
+    assert_eq!(task.n_attempts, 1);
+    assert_eq!(task.after_retry_after, Some(true));
+}
+
+#[tokio::test]
+async fn permanent_delivery_failures_are_dead_lettered_right_away() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+      "title": "Newsletter title",
+      "content": {
+        "text": "Newsletter body as plain text",
+        "html": "<p>Newsletter body as HTML</p>",
+      }
+    });
+    test_app.post_newsletters(newsletter_request_body).await;
+    test_app.dispatch_all_pending_emails().await;
 
-    app.get_confirmation_links(email_request)
+    // Assert
+    let dead_letter = sqlx::query!(
+        "SELECT subscriber_email, n_attempts, last_error FROM issue_delivery_dead_letters"
+    )
+    .fetch_one(&test_app.db_pool)
+    .await
+    .expect("The failed delivery was not dead-lettered.");
+    assert_eq!(dead_letter.subscriber_email, "ursula_le_guin@gmail.com");
+    assert_eq!(dead_letter.n_attempts, 1);
+    assert!(dead_letter.last_error.contains("422"));
+
+    let queued = sqlx::query!("SELECT n_attempts FROM issue_delivery_queue")
+        .fetch_all(&test_app.db_pool)
+        .await
+        .unwrap();
+    assert!(queued.is_empty());
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
-    // Can reuse the above helper and just add an extra step
-    let confirmation_link = create_unconfirmed_subscriber(app).await;
-    reqwest::get(confirmation_link.html)
+#[tokio::test]
+async fn recipients_rejected_within_a_batch_are_dead_lettered() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::rejecting(
+            "ursula_le_guin@gmail.com",
+        ))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+      "title": "Newsletter title",
+      "content": {
+        "text": "Newsletter body as plain text",
+        "html": "<p>Newsletter body as HTML</p>",
+      }
+    });
+    test_app.post_newsletters(newsletter_request_body).await;
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert - the provider accepted the batch, but not this recipient
+    let dead_letter = sqlx::query!(
+        "SELECT subscriber_email, n_attempts, last_error FROM issue_delivery_dead_letters"
+    )
+    .fetch_one(&test_app.db_pool)
+    .await
+    .expect("The rejected delivery was not dead-lettered.");
+    assert_eq!(dead_letter.subscriber_email, "ursula_le_guin@gmail.com");
+    assert_eq!(dead_letter.n_attempts, 1);
+    assert!(dead_letter.last_error.contains("406"));
+
+    let queued = sqlx::query!("SELECT n_attempts FROM issue_delivery_queue")
+        .fetch_all(&test_app.db_pool)
         .await
-        .unwrap()
-        .error_for_status()
         .unwrap();
+    assert!(queued.is_empty());
+}
+
+#[tokio::test]
+async fn deliveries_are_dead_lettered_after_too_many_attempts() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+      "title": "Newsletter title",
+      "content": {
+        "text": "Newsletter body as plain text",
+        "html": "<p>Newsletter body as HTML</p>",
+      }
+    });
+    test_app.post_newsletters(newsletter_request_body).await;
+    // Pretend every previous attempt already failed
+    sqlx::query!(
+        "UPDATE issue_delivery_queue SET n_attempts = $1",
+        MAX_DELIVERY_ATTEMPTS - 1
+    )
+    .execute(&test_app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    test_app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let dead_letter =
+        sqlx::query!("SELECT n_attempts, last_error FROM issue_delivery_dead_letters")
+            .fetch_one(&test_app.db_pool)
+            .await
+            .expect("The failed delivery was not dead-lettered.");
+    assert_eq!(dead_letter.n_attempts, MAX_DELIVERY_ATTEMPTS);
+    assert!(dead_letter.last_error.contains("500"));
 }
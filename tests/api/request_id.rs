@@ -0,0 +1,98 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn a_request_id_is_generated_when_none_is_sent() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = test_app
+        .api_client
+        .get(format!("{}/health_check", &test_app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    let request_id = response.headers()["x-request-id"].to_str().unwrap();
+    assert!(uuid::Uuid::parse_str(request_id).is_ok());
+}
+
+#[tokio::test]
+async fn a_valid_incoming_request_id_is_echoed() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = test_app
+        .api_client
+        .get(format!("{}/health_check", &test_app.address))
+        .header("X-Request-Id", "lb-5f2a.9c1e_77")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.headers()["x-request-id"], "lb-5f2a.9c1e_77");
+}
+
+#[tokio::test]
+async fn an_invalid_incoming_request_id_is_replaced() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = test_app
+        .api_client
+        .get(format!("{}/health_check", &test_app.address))
+        .header("X-Request-Id", "<script>alert(1)</script>")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    let request_id = response.headers()["x-request-id"].to_str().unwrap();
+    assert!(uuid::Uuid::parse_str(request_id).is_ok());
+}
+
+#[tokio::test]
+async fn the_request_id_is_forwarded_to_the_email_provider_and_shown_on_errors() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app
+        .api_client
+        .post(format!("{}/subscriptions", &test_app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("X-Request-Id", "support-ticket-1234")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 500);
+    assert_eq!(response.headers()["x-request-id"], "support-ticket-1234");
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("support-ticket-1234"));
+
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let forwarded = email_request.headers.get(&"x-request-id".into()).unwrap();
+    assert_eq!(forwarded.as_str(), "support-ticket-1234");
+}
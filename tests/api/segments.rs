@@ -0,0 +1,289 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::{
+    domain::{SegmentSlug, SubscriberTag},
+    segments::{create_segment, NewSegment},
+};
+
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber_with, create_rust_weekly,
+    create_unconfirmed_subscriber_with, newsletter_body, spawn_app, PostmarkBatchResponder,
+    TestApp,
+};
+
+async fn create_rustaceans_segment(app: &TestApp) {
+    create_segment(
+        &app.db_pool,
+        &NewSegment {
+            slug: SegmentSlug::parse("rustaceans".into()).unwrap(),
+            name: "Rustaceans".into(),
+            tags: SubscriberTag::parse_list("rust").unwrap(),
+            excluded_tags: SubscriberTag::parse_list("churned").unwrap(),
+            subscribed_after: None,
+            subscribed_before: None,
+            engaged_within_days: Some(30),
+            attributes: Default::default(),
+        },
+    )
+    .await
+    .unwrap()
+    .unwrap();
+}
+
+async fn stored_tags(app: &TestApp) -> Vec<String> {
+    sqlx::query!("SELECT tags FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .tags
+}
+
+#[tokio::test]
+async fn tags_picked_at_signup_are_stored_once_confirmed() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act - Part 1 - Sign up
+    let confirmation_link =
+        create_unconfirmed_subscriber_with(&app, &[("tags", "Rust, beta")]).await;
+    assert!(stored_tags(&app).await.is_empty());
+
+    // Act - Part 2 - Confirm
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    assert_eq!(stored_tags(&app).await, vec!["beta", "rust"]);
+}
+
+#[tokio::test]
+async fn tags_can_be_sent_as_a_json_array() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions_json(serde_json::json!({
+        "name": "le guin",
+        "email": "ursula_le_guin@gmail.com",
+        "tags": ["rust", "rust", "conference"],
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    assert_eq!(stored_tags(&app).await, vec!["conference", "rust"]);
+}
+
+#[tokio::test]
+async fn signing_up_to_another_list_does_not_tag_a_confirmed_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_rust_weekly(&app).await;
+    create_confirmed_subscriber_with(&app, &[("tags", "rust")]).await;
+
+    // Act - Part 1 - Someone signs up with the same address
+    let confirmation_link =
+        create_unconfirmed_subscriber_with(&app, &[("list", "rust-weekly"), ("tags", "churned")])
+            .await;
+    assert_eq!(stored_tags(&app).await, vec!["rust"]);
+
+    // Act - Part 2 - The owner of the address confirms
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    assert_eq!(stored_tags(&app).await, vec!["churned", "rust"]);
+}
+
+#[tokio::test]
+async fn invalid_tags_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com&tags=%3Cb%3E".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["errors"][0]["field"], "tags");
+    assert_eq!(problem["errors"][0]["code"], "invalid_tags");
+}
+
+#[tokio::test]
+async fn issues_sent_to_a_segment_only_reach_its_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with(&app, &[("email", "ferris@example.com"), ("tags", "rust")])
+        .await;
+    create_confirmed_subscriber_with(&app, &[("email", "gopher@example.com"), ("tags", "go")])
+        .await;
+    create_confirmed_subscriber_with(
+        &app,
+        &[("email", "former@example.com"), ("tags", "rust,churned")],
+    )
+    .await;
+    create_rustaceans_segment(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::accept_all())
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(newsletter_body(
+            serde_json::json!({ "segment": "rustaceans" }),
+        ))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(
+        app.newsletter_recipients().await,
+        vec!["ferris@example.com"]
+    );
+}
+
+#[tokio::test]
+async fn subscribers_not_engaged_recently_are_left_out_of_the_segment() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with(&app, &[("email", "ferris@example.com"), ("tags", "rust")])
+        .await;
+    create_rustaceans_segment(&app).await;
+    sqlx::query!("UPDATE subscriptions SET last_engaged_at = now() - interval '90 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::accept_all())
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(newsletter_body(
+            serde_json::json!({ "segment": "rustaceans" }),
+        ))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[tokio::test]
+async fn publishing_to_an_unknown_segment_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_newsletters(newsletter_body(serde_json::json!({ "segment": "nope" })))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["errors"][0]["field"], "segment");
+    assert_eq!(problem["errors"][0]["code"], "unknown_segment");
+}
+
+#[tokio::test]
+async fn admins_can_create_segments() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+
+    // Act - Part 1 - Create the segment
+    let response = app
+        .api_client
+        .post(format!("{}/admin/segments", &app.address))
+        .form(&serde_json::json!({
+            "slug": "rustaceans",
+            "name": "Rustaceans",
+            "tags": "rust",
+            "excluded_tags": "",
+            "subscribed_from": "2023-01-01",
+            "subscribed_to": "2023-01-31",
+            "engaged_within_days": "30",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/segments");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app
+        .api_client
+        .get(format!("{}/admin/segments", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("The rustaceans segment has been created."));
+    assert!(html_page.contains("subscribed before 2023-02-01"));
+
+    // Act - Part 3 - The segment can be picked when publishing
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains(">Rustaceans</option>"));
+}
+
+#[tokio::test]
+async fn admins_can_set_the_tags_of_a_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with(&app, &[("email", "ferris@example.com"), ("tags", "rust")])
+        .await;
+    app.login_as_test_user().await;
+
+    // Act - Part 1 - Replace the tags
+    let response = app
+        .api_client
+        .post(format!("{}/admin/subscribers/tags", &app.address))
+        .form(&serde_json::json!({
+            "email": "ferris@example.com",
+            "tags": "beta, speaker",
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/subscribers");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_admin_subscribers().await.text().await.unwrap();
+    assert!(html_page.contains("The tags of ferris@example.com have been updated."));
+    assert_eq!(stored_tags(&app).await, vec!["beta", "speaker"]);
+}
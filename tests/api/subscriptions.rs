@@ -1,9 +1,11 @@
 use wiremock::{
-    matchers::{method, path},
+    matchers::{any, method, path},
     Mock, ResponseTemplate,
 };
 
-use crate::helpers::spawn_app;
+use crate::helpers::{
+    create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app, spawn_app_with,
+};
 
 #[tokio::test]
 async fn subscribe_returns_a_200_for_valid_form_data() {
@@ -38,10 +40,12 @@ async fn subscribe_persists_the_new_subscriber() {
 
     test_app.post_subscriptions(body.into()).await;
 
-    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions")
-        .fetch_one(&test_app.db_pool)
-        .await
-        .expect("Failed to fetch saved subscription.");
+    let saved = sqlx::query!(
+        "SELECT email, name, status FROM subscriptions JOIN list_memberships ON subscriber_id = id"
+    )
+    .fetch_one(&test_app.db_pool)
+    .await
+    .expect("Failed to fetch saved subscription.");
 
     assert_eq!(saved.email, "ursula_le_guin@gmail.com");
     assert_eq!(saved.name, "le guin");
@@ -88,6 +92,33 @@ async fn subscribe_returns_a_400_when_fields_are_present_but_invalid() {
     }
 }
 
+#[tokio::test]
+async fn invalid_fields_are_reported_as_problem_details() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = test_app
+        .post_subscriptions("name=%3Cscript%3E&email=definitely-not-an-email".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["status"], 400);
+    assert_eq!(problem["code"], "validation_failed");
+    let errors = problem["errors"].as_array().unwrap();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0]["field"], "name");
+    assert_eq!(errors[0]["code"], "invalid_name");
+    assert_eq!(errors[1]["field"], "email");
+    assert_eq!(errors[1]["code"], "invalid_email");
+}
+
 #[tokio::test]
 async fn subscribe_sends_a_confirmation_email_for_valid_data() {
     let test_app = spawn_app().await;
@@ -128,6 +159,115 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
     assert_eq!(confirmation_links.html, confirmation_links.plain_text);
 }
 
+#[tokio::test]
+async fn subscribe_accepts_json_and_answers_in_json() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app
+        .post_subscriptions_json(serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "application/json");
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "accepted");
+
+    let saved = sqlx::query!("SELECT email, name FROM subscriptions",)
+        .fetch_one(&test_app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+    assert_eq!(saved.name, "le guin");
+}
+
+#[tokio::test]
+async fn form_submissions_still_get_an_empty_200() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn invalid_json_fields_are_reported_as_problem_details() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = test_app
+        .post_subscriptions_json(serde_json::json!({
+            "name": "le guin",
+            "email": "definitely-not-an-email",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["errors"][0]["field"], "email");
+}
+
+#[tokio::test]
+async fn subscribe_rejects_json_with_missing_fields() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = test_app
+        .post_subscriptions_json(serde_json::json!({ "name": "le guin" }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 422);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["code"], "invalid_body");
+}
+
+#[tokio::test]
+async fn unreadable_forms_are_reported_as_problem_details() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = test_app.post_subscriptions("name=le%20guin".into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["code"], "invalid_body");
+    assert!(problem["detail"].as_str().unwrap().contains("email"));
+}
+
 #[tokio::test]
 async fn subscribe_fails_if_there_is_a_fatal_subscription_tokens_database_error() {
     let test_app = spawn_app().await;
@@ -145,6 +285,37 @@ async fn subscribe_fails_if_there_is_a_fatal_subscription_tokens_database_error(
     assert_eq!(response.status().as_u16(), 500);
 }
 
+#[tokio::test]
+async fn unexpected_errors_do_not_leak_internals() {
+    let test_app = spawn_app().await;
+
+    // Sabotage the database
+    sqlx::query!("ALTER TABLE subscription_tokens DROP COLUMN subscription_token;",)
+        .execute(&test_app.db_pool)
+        .await
+        .unwrap();
+
+    let response = test_app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 500);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let request_id = response.headers()["x-request-id"]
+        .to_str()
+        .unwrap()
+        .to_owned();
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["code"], "internal_error");
+    assert_eq!(problem["request_id"], request_id);
+    let body = problem.to_string();
+    assert!(!body.contains("subscription_token"));
+    assert!(!body.contains("database"));
+}
+
 #[tokio::test]
 async fn subscribe_fails_if_there_is_a_fatal_subscriptions_database_error() {
     let test_app = spawn_app().await;
@@ -161,3 +332,189 @@ async fn subscribe_fails_if_there_is_a_fatal_subscriptions_database_error() {
 
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn subscribing_twice_while_pending_resends_a_fresh_confirmation_link() {
+    let test_app = spawn_app().await;
+    let first_links = create_unconfirmed_subscriber(&test_app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let response = test_app.post_subscriptions(body.into()).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+
+    let email_request = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let second_links = test_app.get_confirmation_links(&email_request);
+
+    // The token was rotated, only the latest link works
+    let first_response = reqwest::get(first_links.html).await.unwrap();
+    assert_eq!(first_response.status().as_u16(), 401);
+    let second_response = reqwest::get(second_links.html).await.unwrap();
+    assert_eq!(second_response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribing_twice_when_confirmed_returns_200_without_sending_an_email() {
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let response = test_app.post_subscriptions(body.into()).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+
+    let saved = sqlx::query!("SELECT status FROM list_memberships")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_again_after_unsubscribing_restarts_double_opt_in() {
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+
+    let membership = sqlx::query!("SELECT subscriber_id, list_id FROM list_memberships")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap();
+    test_app
+        .post_unsubscribe(
+            &test_app
+                .unsubscribe_links
+                .token(membership.subscriber_id, membership.list_id),
+        )
+        .await
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let body = "name=ursula&email=ursula_le_guin%40gmail.com";
+    let response = test_app.post_subscriptions(body.into()).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+
+    let saved = sqlx::query!("SELECT name, status, unsubscribed_at FROM subscriptions JOIN list_memberships ON subscriber_id = id")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap();
+    // The new name only applies once the link is followed
+    assert_eq!(saved.name, "le guin");
+    assert_eq!(saved.status, "pending_confirmation");
+    assert!(saved.unsubscribed_at.is_none());
+
+    // Confirming the new link makes them a confirmed subscriber again
+    let email_request = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = test_app.get_confirmation_links(&email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let saved = sqlx::query!(
+        "SELECT name, status FROM subscriptions JOIN list_memberships ON subscriber_id = id"
+    )
+    .fetch_one(&test_app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.name, "ursula");
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn the_trace_context_is_propagated_to_the_email_provider() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    test_app
+        .api_client
+        .post(format!("{}/subscriptions", &test_app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("traceparent", format!("00-{trace_id}-00f067aa0ba902b7-01"))
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .error_for_status()
+        .unwrap();
+
+    // Assert - the call to the provider is part of the caller's trace
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let traceparent = email_request.headers.get(&"traceparent".into()).unwrap();
+    assert!(traceparent.as_str().starts_with(&format!("00-{trace_id}-")));
+}
+
+#[tokio::test]
+async fn subscribing_fails_fast_once_the_email_provider_circuit_is_open() {
+    // Arrange
+    let test_app = spawn_app_with(|c| {
+        c.email_client.circuit_breaker.failure_threshold = 1;
+        c.email_client.circuit_breaker.cool_down_milliseconds = 60_000;
+    })
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        // Only the first attempt reaches the provider
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let first = test_app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let second = test_app
+        .post_subscriptions("name=ursula&email=ursula%40example.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 500);
+    assert_eq!(second.status().as_u16(), 500);
+    let health = test_app.get_health_check().await;
+    assert_eq!(health["status"], "ok");
+    assert_eq!(health["email_provider"]["circuit_breaker"], "open");
+}
@@ -3,7 +3,7 @@ use wiremock::{
     Mock, ResponseTemplate,
 };
 
-use crate::helpers::spawn_app;
+use crate::helpers::{create_unconfirmed_subscriber, spawn_app};
 
 #[tokio::test]
 async fn confirmations_without_token_are_rejected_with_a_400() {
@@ -65,10 +65,12 @@ async fn clicking_the_confirmation_link_confirms_a_subscriber() {
         .error_for_status() // So we return an error if non-200 (I think)
         .unwrap();
 
-    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions",)
-        .fetch_one(&test_app.db_pool)
-        .await
-        .expect("Failed to fetch saved subscriptions.");
+    let saved = sqlx::query!(
+        "SELECT email, name, status FROM subscriptions JOIN list_memberships ON subscriber_id = id",
+    )
+    .fetch_one(&test_app.db_pool)
+    .await
+    .expect("Failed to fetch saved subscriptions.");
 
     assert_eq!(saved.email, "ursula_le_guin@gmail.com");
     assert_eq!(saved.name, "le guin");
@@ -127,7 +129,7 @@ async fn confirm_fails_if_there_is_a_fatal_subscriptions_database_error() {
     let confirmation_links = test_app.get_confirmation_links(email_request);
 
     // Sabotage the database
-    sqlx::query!("ALTER TABLE subscriptions DROP COLUMN status;",)
+    sqlx::query!("ALTER TABLE list_memberships DROP COLUMN status;",)
         .execute(&test_app.db_pool)
         .await
         .unwrap();
@@ -166,4 +168,64 @@ async fn confirm_returns_401_with_invalid_subscription_token() {
         .expect("Failed to execute request");
 
     assert_eq!(response.status().as_u16(), 401);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["code"], "invalid_subscription_token");
+}
+
+#[tokio::test]
+async fn confirmation_links_can_only_be_used_once() {
+    let test_app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&test_app).await;
+
+    let first = reqwest::get(confirmation_links.html.clone()).await.unwrap();
+    let second = reqwest::get(confirmation_links.html).await.unwrap();
+
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 401);
+
+    let saved = sqlx::query!("SELECT status FROM list_memberships")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn confirmation_tokens_expire_after_48_hours() {
+    let test_app = spawn_app().await;
+    create_unconfirmed_subscriber(&test_app).await;
+
+    let token = sqlx::query!("SELECT created_at, expires_at FROM subscription_tokens")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        token.expires_at - token.created_at,
+        chrono::Duration::hours(48)
+    );
+}
+
+#[tokio::test]
+async fn an_expired_confirmation_link_shows_the_link_expired_page() {
+    let test_app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&test_app).await;
+
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&test_app.db_pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 410);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("This confirmation link has expired."));
+    assert!(html_page.contains(r#"action="/subscriptions/resend-confirmation""#));
+
+    let saved = sqlx::query!("SELECT status FROM list_memberships")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
 }
@@ -0,0 +1,156 @@
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{
+    create_confirmed_subscriber, create_unconfirmed_subscriber, create_unconfirmed_subscriber_with,
+    spawn_app,
+};
+
+#[tokio::test]
+async fn resending_sends_a_new_confirmation_link_to_a_pending_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    let old_links = create_unconfirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_resend_confirmation("email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let new_links = app.get_confirmation_links(&email_request);
+    assert_ne!(new_links.html, old_links.html);
+
+    // Only the latest link is valid
+    let old_response = reqwest::get(old_links.html).await.unwrap();
+    assert_eq!(old_response.status().as_u16(), 401);
+    let new_response = reqwest::get(new_links.html).await.unwrap();
+    assert_eq!(new_response.status().as_u16(), 200);
+
+    let saved = sqlx::query!("SELECT status FROM list_memberships")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn the_new_link_applies_the_tags_picked_at_signup() {
+    // Arrange
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber_with(&app, &[("tags", "rust")]).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_resend_confirmation("email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let new_links = app.get_confirmation_links(&email_request);
+    reqwest::get(new_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let saved = sqlx::query!("SELECT tags FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.tags, vec!["rust"]);
+}
+
+#[tokio::test]
+async fn resending_to_an_unknown_address_returns_200_without_sending_anything() {
+    // Arrange
+    let app = spawn_app().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_resend_confirmation("email=nobody%40example.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn resending_to_a_confirmed_subscriber_returns_200_without_sending_anything() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_resend_confirmation("email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn resending_returns_a_400_for_an_invalid_email() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        ("".to_string(), "missing email"),
+        ("email=".to_string(), "empty email"),
+        ("email=not-an-email".to_string(), "invalid email"),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_resend_confirmation(body).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            description
+        );
+    }
+}
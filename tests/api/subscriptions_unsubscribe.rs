@@ -0,0 +1,233 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock,
+};
+use zero2prod::unsubscribe_links::Unsubscription;
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app, PostmarkBatchResponder, TestApp};
+
+/// Publish an issue to the confirmed subscriber and return
+/// the unsubscribe token embedded in the email
+async fn publish_and_get_unsubscribe_token(app: &TestApp) -> String {
+    let _mock_guard = Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::accept_all())
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let links = app.get_unsubscribe_links(&email_request);
+    assert_eq!(links.html, links.plain_text);
+
+    links
+        .html
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .map(|(_, value)| value.into_owned())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn newsletters_contain_an_unsubscribe_link() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let token = publish_and_get_unsubscribe_token(&app).await;
+
+    // Assert
+    let membership = sqlx::query!("SELECT subscriber_id, list_id FROM list_memberships")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        app.unsubscribe_links.verify(&token),
+        Some(Unsubscription {
+            subscriber_id: membership.subscriber_id,
+            list_id: Some(membership.list_id)
+        })
+    );
+}
+
+#[tokio::test]
+async fn the_unsubscribe_page_asks_for_confirmation_without_unsubscribing() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = publish_and_get_unsubscribe_token(&app).await;
+
+    // Act
+    let response = app.get_unsubscribe(&token).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains(r#"<button type="submit">Unsubscribe</button>"#));
+
+    let saved = sqlx::query!("SELECT status FROM list_memberships")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn posting_to_the_unsubscribe_link_unsubscribes_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = publish_and_get_unsubscribe_token(&app).await;
+
+    // Act
+    let response = app.post_unsubscribe(&token).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+
+    let saved = sqlx::query!("SELECT status, unsubscribed_at FROM list_memberships")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+    assert!(saved.unsubscribed_at.is_some());
+}
+
+#[tokio::test]
+async fn unsubscribing_twice_is_fine() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = publish_and_get_unsubscribe_token(&app).await;
+
+    // Act
+    let first = app.post_unsubscribe(&token).await;
+    let second = app.post_unsubscribe(&token).await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn an_invalid_token_is_rejected_with_a_401() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = publish_and_get_unsubscribe_token(&app).await;
+    let tampered = format!("{}00", token);
+
+    for token in ["not-a-token", tampered.as_str()] {
+        // Act
+        let get_response = app.get_unsubscribe(token).await;
+        let post_response = app.post_unsubscribe(token).await;
+
+        // Assert
+        for response in [get_response, post_response] {
+            assert_eq!(response.status().as_u16(), 401);
+            assert_eq!(
+                response.headers()["Content-Type"],
+                "application/problem+json"
+            );
+            let problem: serde_json::Value = response.json().await.unwrap();
+            assert_eq!(problem["code"], "invalid_unsubscribe_token");
+        }
+    }
+
+    let saved = sqlx::query!("SELECT status FROM list_memberships")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_dont_receive_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = publish_and_get_unsubscribe_token(&app).await;
+    app.post_unsubscribe(&token)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponder::accept_all())
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_carry_one_click_list_unsubscribe_headers() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let token = publish_and_get_unsubscribe_token(&app).await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let headers: Vec<(&str, &str)> = body[0]["Headers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|h| (h["Name"].as_str().unwrap(), h["Value"].as_str().unwrap()))
+        .collect();
+
+    let list_unsubscribe = headers
+        .iter()
+        .find(|(name, _)| *name == "List-Unsubscribe")
+        .unwrap()
+        .1;
+    assert!(list_unsubscribe.starts_with('<') && list_unsubscribe.ends_with('>'));
+    assert!(list_unsubscribe.contains(&format!("/subscriptions/unsubscribe?token={token}")));
+    assert!(headers.contains(&("List-Unsubscribe-Post", "List-Unsubscribe=One-Click")));
+}